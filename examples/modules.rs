@@ -0,0 +1,128 @@
+use std::{collections::HashMap, error::Error, net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use auth_for_warp::{
+    handle_auth_errors, Auth, AuthConfig, AuthFilters, HashedPassword, UserDatabase, UserID,
+    Username,
+};
+use tokio::sync::Mutex;
+use warp::Filter;
+
+// each module builds its routes from the filters it is handed, without needing the Auth itself
+mod profile {
+    use auth_for_warp::{AuthContext, AuthFilters, UserID};
+    use serde_json::json;
+    use warp::{path, Filter, Rejection, Reply};
+
+    pub fn routes(
+        filters: &AuthFilters,
+    ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+        let whoami = path!("profile").and(filters.with_auth_context()).then(
+            |context: AuthContext| async move {
+                context.finish(warp::reply::json(&json!({ "user id": context.user_id })))
+            },
+        );
+
+        let greeting = path!("greeting").and(filters.with_optional_auth()).then(
+            |user_id: Option<UserID>| async move {
+                match user_id {
+                    Some(user_id) => format!("welcome back, {}", user_id.0),
+                    None => "hello, stranger".to_owned(),
+                }
+            },
+        );
+
+        whoami.or(greeting)
+    }
+}
+
+mod admin {
+    use auth_for_warp::{AuthFilters, ClaimRequirement};
+    use serde_json::json;
+    use warp::{path, Filter, Rejection, Reply};
+
+    pub fn routes(
+        filters: &AuthFilters,
+    ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+        path!("admin" / "dashboard")
+            .and(filters.with_auth_requiring(&[ClaimRequirement::contains("roles", "admin")]))
+            .then(|user_id| async move { warp::reply::json(&json!({ "admin": user_id })) })
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let database_connection = Arc::new(SimpleInMemoryDb::new());
+
+    let config = AuthConfig::new(
+        "this is a terrible salt",
+        "insert app or organisation name here",
+        "this is a really bad secret",
+        Duration::from_secs(60 * 60),
+        database_connection,
+    );
+
+    let auth = Auth::new(config);
+    auth.warmup()
+        .await
+        .expect("invalid password hashing parameters");
+
+    let filters = AuthFilters::new(&auth);
+
+    let all_routes = profile::routes(&filters)
+        .or(admin::routes(&filters))
+        .or(filters.api_routes())
+        .recover(handle_auth_errors);
+
+    warp::serve(all_routes)
+        .run("127.0.0.1:4000".parse::<SocketAddr>().unwrap())
+        .await;
+}
+
+// requests reach the database concurrently, so it guards its users with a lock of its own
+struct SimpleInMemoryDb {
+    storage: Mutex<HashMap<String, (UserID, HashedPassword)>>,
+}
+
+impl SimpleInMemoryDb {
+    pub fn new() -> Self {
+        Self {
+            storage: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl UserDatabase for SimpleInMemoryDb {
+    async fn create_user_if_not_exists(
+        &self,
+        user_id: &UserID,
+        username: &Username,
+        hashed_password: &HashedPassword,
+    ) -> Result<UserID, Box<dyn Error + Send + Sync>> {
+        Ok(self
+            .storage
+            .lock()
+            .await
+            .entry(username.0.clone())
+            .or_insert_with(|| (user_id.clone(), hashed_password.clone()))
+            .0
+            .clone())
+    }
+
+    async fn retreive_user(
+        &self,
+        username: &Username,
+    ) -> Result<(UserID, HashedPassword), Box<dyn Error + Send + Sync>> {
+        let result = self
+            .storage
+            .lock()
+            .await
+            .get(&username.0)
+            .ok_or_else(|| anyhow!("user not found"))
+            .cloned()?;
+
+        Ok(result)
+    }
+}
@@ -3,8 +3,8 @@ use std::{collections::HashMap, error::Error, net::SocketAddr, sync::Arc, time::
 use anyhow::anyhow;
 use async_trait::async_trait;
 use auth_for_warp::{
-    build_api_route_filter, handle_auth_errors, with_auth, Auth, AuthConfig, HashedPassword,
-    UserDatabase, UserID, Username,
+    build_api_route_filter, handle_auth_errors, with_auth, with_auth_context, Auth, AuthConfig,
+    AuthContext, HashedPassword, UserDatabase, UserID, Username,
 };
 use serde_json::json;
 use tokio::sync::Mutex;
@@ -12,17 +12,20 @@ use warp::{path, Filter};
 
 #[tokio::main]
 async fn main() {
-    let database_connection = Arc::new(Mutex::new(SimpleInMemoryDb::new()));
+    let database_connection = Arc::new(SimpleInMemoryDb::new());
 
-    let config = AuthConfig {
-        password_salt: "this is a terrible salt".into(),
-        auth_token_issuer: "insert app or organisation name here".into(),
-        auth_token_secret: "this is a really bad secret".into(),
-        auth_token_lifetime: Duration::from_secs(60 * 60),
+    let config = AuthConfig::new(
+        "this is a terrible salt",
+        "insert app or organisation name here",
+        "this is a really bad secret",
+        Duration::from_secs(60 * 60),
         database_connection,
-    };
+    );
 
     let auth = Auth::new(config);
+    auth.warmup()
+        .await
+        .expect("invalid password hashing parameters");
 
     let auth_routes = build_api_route_filter(&auth);
 
@@ -33,8 +36,17 @@ async fn main() {
         .and(with_auth(&auth))
         .then(|user_id| async move { warp::reply::json(&json!({ "user id": user_id })) });
 
+    // replies built from the user's own data are marked private, so that shared caches don't serve them to others
+    let private_page =
+        path!("profile")
+            .and(with_auth_context(&auth))
+            .then(|context: AuthContext| async move {
+                context.finish(warp::reply::json(&json!({ "user id": context.user_id })))
+            });
+
     let all_routes = unsecured_homepage
         .or(secure_page)
+        .or(private_page)
         .or(auth_routes)
         .recover(handle_auth_errors);
 
@@ -43,14 +55,15 @@ async fn main() {
         .await;
 }
 
+// requests reach the database concurrently, so it guards its users with a lock of its own
 struct SimpleInMemoryDb {
-    storage: HashMap<String, (UserID, HashedPassword)>,
+    storage: Mutex<HashMap<String, (UserID, HashedPassword)>>,
 }
 
 impl SimpleInMemoryDb {
     pub fn new() -> Self {
         Self {
-            storage: HashMap::new(),
+            storage: Mutex::new(HashMap::new()),
         }
     }
 }
@@ -58,15 +71,17 @@ impl SimpleInMemoryDb {
 #[async_trait]
 impl UserDatabase for SimpleInMemoryDb {
     async fn create_user_if_not_exists(
-        &mut self,
+        &self,
         user_id: &UserID,
         username: &Username,
         hashed_password: &HashedPassword,
     ) -> Result<UserID, Box<dyn Error + Send + Sync>> {
-        if self.storage.contains_key(&username.0) {
-            Ok(self.storage[&username.0.clone()].0.clone())
+        let mut storage = self.storage.lock().await;
+
+        if storage.contains_key(&username.0) {
+            Ok(storage[&username.0.clone()].0.clone())
         } else {
-            self.storage.insert(
+            storage.insert(
                 username.0.clone(),
                 (user_id.clone(), hashed_password.clone()),
             );
@@ -80,6 +95,8 @@ impl UserDatabase for SimpleInMemoryDb {
     ) -> Result<(UserID, HashedPassword), Box<dyn Error + Send + Sync>> {
         let result = self
             .storage
+            .lock()
+            .await
             .get(&username.0)
             .ok_or_else(|| anyhow!("user not found"))
             .cloned()?;
@@ -0,0 +1,64 @@
+use std::convert::Infallible;
+
+use uuid::Uuid;
+use warp::{
+    http::{header::SET_COOKIE, HeaderValue},
+    reply::Response,
+    Filter, Reply,
+};
+
+use crate::headers::optional_cookie;
+
+/// The cookie that carries the anonymous session id.
+pub const ANONYMOUS_SESSION_COOKIE: &str = "session_id";
+
+// Long enough to follow a visitor from their first visit through to signing up
+const ANONYMOUS_SESSION_MAX_AGE_SECS: u64 = 365 * 24 * 60 * 60;
+
+/// A stable id for a visitor, whether or not they are logged in, for pre-login flows such as a shopping cart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnonymousSession {
+    pub id: String,
+    is_new: bool,
+}
+
+impl AnonymousSession {
+    /// Whether the id was minted for this request, rather than sent by the client.
+    pub fn is_new(&self) -> bool {
+        self.is_new
+    }
+
+    /// Wrap a reply so that a newly minted session id is stored in the session cookie.
+    pub fn reply(&self, reply: impl Reply) -> Response {
+        let mut response = reply.into_response();
+
+        if self.is_new {
+            let cookie = format!(
+                "{ANONYMOUS_SESSION_COOKIE}={}; Max-Age={ANONYMOUS_SESSION_MAX_AGE_SECS}; Path=/; HttpOnly; Secure; SameSite=Lax",
+                self.id
+            );
+            response
+                .headers_mut()
+                .append(SET_COOKIE, HeaderValue::from_str(&cookie).unwrap());
+        }
+
+        response
+    }
+}
+
+/// Extract the visitor's anonymous session from the session cookie, independent of auth. If the request has no
+/// session cookie (or an invalid one), a new session id is minted, which the handler should send back to the client
+/// by wrapping its reply with [`AnonymousSession::reply`]. Set `AuthConfig::anonymous_session_linker` to associate
+/// the session with the user when they log in.
+pub fn with_anonymous_session(
+) -> impl Filter<Extract = (AnonymousSession,), Error = Infallible> + Clone {
+    optional_cookie(ANONYMOUS_SESSION_COOKIE.into()).map(|id: Option<String>| {
+        match id.filter(|id| Uuid::parse_str(id).is_ok()) {
+            Some(id) => AnonymousSession { id, is_new: false },
+            None => AnonymousSession {
+                id: Uuid::new_v4().to_string(),
+                is_new: true,
+            },
+        }
+    })
+}
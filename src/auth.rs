@@ -1,143 +1,2877 @@
 use std::{
+    collections::{HashMap, VecDeque},
     error::Error,
+    future::Future,
+    net::IpAddr,
+    ops::Deref,
+    pin::Pin,
     sync::Arc,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use async_trait::async_trait;
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
-use tokio::sync::Mutex;
+use futures_util::{Stream, StreamExt};
+use jsonwebtoken::{
+    decode, encode, errors::ErrorKind, Algorithm, DecodingKey, EncodingKey, Header, TokenData,
+    Validation,
+};
+use ring::hmac;
+use serde_json::{json, Map, Value};
+use tokio::{sync::Mutex, task::JoinHandle};
+use uuid::Uuid;
+use warp::http::{HeaderMap, StatusCode};
 
+#[cfg(feature = "compression")]
+use crate::compression;
+#[cfg(feature = "oidc")]
+use crate::jwks::RemoteJwks;
+#[cfg(feature = "unicode-normalization")]
+use crate::unicode_folding;
 use crate::{
+    block_list::BlockList,
+    case_insensitive_string_ext::CaseInsensitiveStringExt,
+    claim_requirement::ClaimRequirement,
     error::AuthError,
-    types::{Claims, HashedPassword, UserID, Username},
+    expiring_map::ExpiringMap,
+    failure_reporter::FailureReporter,
+    hash_cost::AdaptiveHashCost,
+    hash_limit::{default_max_concurrent_hashes, HashLimiter},
+    password_encoding::PasswordEncoding,
+    password_hasher::PasswordHasher,
+    password_manager::PasswordManager,
+    registration_challenge::RegistrationChallenge,
+    secrets::{SecretProvider, StaticSecret},
+    static_jwks::StaticJwks,
+    throttle::{MemoryThrottleStore, ThrottleStore},
+    token_store::TokenStore,
+    types::{Actor, Claims, Confirmation, HashedPassword, Role, Tenant, UserID, Username},
 };
 
+/// The store of users. Every method takes `&self`, as requests reach the database concurrently: a database with its
+/// own connection pool serves them in parallel, while one that keeps users in memory should guard them with a lock of
+/// its own, such as a `Mutex` around its state.
 #[async_trait]
 pub trait UserDatabase: Send + Sync + 'static {
     /// Create the specified user, and return the user id. If a user with the given username already exists,
     /// return the userid of that user instead.
     async fn create_user_if_not_exists(
-        &mut self,
+        &self,
         userid: &UserID,
         username: &Username,
         hashed_password: &HashedPassword,
     ) -> Result<UserID, Box<dyn Error + Send + Sync>>;
 
     /// Retreive the user id and hashed password of the user with the specified username.
+    /// Return an error if no such user exists.
+    ///
+    /// Login always performs a password verification, whether or not the user exists, so that an attacker can't
+    /// tell the difference from the response time. If looking up a missing user is much faster than looking up
+    /// an existing one, that may still leak which usernames exist; consider `AuthConfig::min_login_duration`.
     async fn retreive_user(
         &self,
         username: &Username,
     ) -> Result<(UserID, HashedPassword), Box<dyn Error + Send + Sync>>;
+
+    /// Retrieve any additional claims (such as a role) to include in auth tokens issued to the specified user.
+    /// Claims are captured when the token is issued, so changes only take effect at the next login.
+    /// Claims that collide with the registered claims used by this crate are ignored.
+    async fn custom_claims(
+        &self,
+        _userid: &UserID,
+    ) -> Result<Map<String, Value>, Box<dyn Error + Send + Sync>> {
+        Ok(Map::new())
+    }
+
+    /// Retrieve when the specified user's account was created, if known. This is stamped into auth tokens as the
+    /// `created_at` claim, which `with_account_age` requires.
+    async fn account_created_at(
+        &self,
+        _userid: &UserID,
+    ) -> Result<Option<SystemTime>, Box<dyn Error + Send + Sync>> {
+        Ok(None)
+    }
+
+    /// Retrieve the roles of the specified user, for apps that keep roles in the database rather than in token
+    /// claims. These are loaded by `with_auth_roles`.
+    async fn roles(&self, _userid: &UserID) -> Result<Vec<Role>, Box<dyn Error + Send + Sync>> {
+        Ok(vec![])
+    }
+
+    /// Retrieve how long auth tokens issued to the specified user should remain valid for, if it differs from
+    /// `AuthConfig::auth_token_lifetime`, such as for service accounts.
+    async fn token_lifetime(
+        &self,
+        _userid: &UserID,
+    ) -> Result<Option<Duration>, Box<dyn Error + Send + Sync>> {
+        Ok(None)
+    }
+
+    /// Replace the hashed password of the specified user.
+    /// Databases that enforce password history should also record the replaced hash in the history.
+    async fn update_password(
+        &self,
+        _userid: &UserID,
+        _hashed_password: &HashedPassword,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("this user database does not support changing passwords".into())
+    }
+
+    /// Retrieve the current version of the user's password, which must change whenever the password does, such as an
+    /// `updated_at` timestamp or a counter. Databases that return one get optimistic concurrency on password changes:
+    /// the password is replaced with `update_password_if_version`, and a change that raced with another fails as
+    /// `AuthError::PasswordChangeConflict`. If `None`, the last change wins.
+    async fn password_version(
+        &self,
+        _userid: &UserID,
+    ) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        Ok(None)
+    }
+
+    /// As `update_password`, but only if the password's version is still `expected_version`, returning whether it was
+    /// replaced. The check and the update must be atomic, as with `UPDATE ... WHERE version = ?`.
+    async fn update_password_if_version(
+        &self,
+        userid: &UserID,
+        hashed_password: &HashedPassword,
+        _expected_version: &str,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        self.update_password(userid, hashed_password).await?;
+        Ok(true)
+    }
+
+    /// Retrieve the hashes of the user's previous passwords, most recent first. Only consulted if
+    /// `AuthConfig::password_history_length` is non-zero. Never store previous passwords in plaintext.
+    async fn password_history(
+        &self,
+        _userid: &UserID,
+    ) -> Result<Vec<HashedPassword>, Box<dyn Error + Send + Sync>> {
+        Ok(vec![])
+    }
+
+    /// As `create_user_if_not_exists`, but usernames need only be unique within the given tenant.
+    /// Used instead of `create_user_if_not_exists` when `AuthConfig::tenant_scoped_usernames` is enabled.
+    async fn create_tenant_user_if_not_exists(
+        &self,
+        _tenant: &Tenant,
+        _userid: &UserID,
+        _username: &Username,
+        _hashed_password: &HashedPassword,
+    ) -> Result<UserID, Box<dyn Error + Send + Sync>> {
+        Err("this user database does not support tenants".into())
+    }
+
+    /// As `retreive_user`, but only for users within the given tenant.
+    /// Used instead of `retreive_user` when `AuthConfig::tenant_scoped_usernames` is enabled.
+    async fn retreive_tenant_user(
+        &self,
+        _tenant: &Tenant,
+        _username: &Username,
+    ) -> Result<(UserID, HashedPassword), Box<dyn Error + Send + Sync>> {
+        Err("this user database does not support tenants".into())
+    }
+
+    /// List up to `limit` users, for administration, as (user id, username). Users must be ordered by user id, and
+    /// only those with an id greater than `after` listed, so that paging through users by passing the last id of
+    /// each page as the next `after` visits every user exactly once, even as users are added between pages.
+    async fn list_users(
+        &self,
+        _after: Option<&UserID>,
+        _limit: usize,
+    ) -> Result<Vec<(UserID, Username)>, Box<dyn Error + Send + Sync>> {
+        Err("this user database does not support listing users".into())
+    }
+
+    /// Retrieve whether the specified user has verified their email, by following a link from
+    /// `AuthConfig::verification_sender`. Users that are already verified aren't sent another verification token.
+    async fn is_verified(&self, _userid: &UserID) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        Ok(false)
+    }
+
+    /// Record that the specified user has verified their email.
+    async fn mark_verified(&self, _userid: &UserID) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("this user database does not support email verification".into())
+    }
+
+    /// Create each of the given users that doesn't already exist, and return how many were created.
+    /// The default implementation calls `create_user_if_not_exists` for each user in turn; databases that support
+    /// batch inserts should override it.
+    async fn import_users(
+        &self,
+        mut users: UserImportStream<'_>,
+    ) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let mut created = 0;
+
+        while let Some((user_id, username, hashed_password)) = users.next().await {
+            let existing_id = self
+                .create_user_if_not_exists(&user_id, &username, &hashed_password)
+                .await?;
+
+            if existing_id.0 == user_id.0 {
+                created += 1;
+            }
+        }
+
+        Ok(created)
+    }
 }
 
+/// Users to be imported in bulk, as (user id, username, hashed password).
+pub type UserImportStream<'a> =
+    Pin<Box<dyn Stream<Item = (UserID, Username, HashedPassword)> + Send + 'a>>;
+
 #[derive(Clone)]
 pub struct AuthConfig {
-    /// The secret used to salt passwords stored in the database.
+    /// The secret used to salt passwords stored in the database, which must be between 8 and 48 bytes.
     /// If the salt changes, all previously-stored passwords can no longer be authenticated.
     pub password_salt: String,
     /// The issuer for auth tokens. We will validate that all auth tokens match the given issuer.
     pub auth_token_issuer: String,
     /// The secret used to encrypt JWT authorization tokens.
     /// If the secret changes, all currently authenticated sessions will be terminated.
+    /// Ignored if a `secret_provider` is configured.
     pub auth_token_secret: String,
     /// How long auth tokens should remain valid for. After this interval, the client will have to re-login.
     pub auth_token_lifetime: Duration,
-    pub database_connection: Arc<Mutex<dyn UserDatabase>>,
+    /// A hard upper bound on the lifetime of every token issued, whatever lifetime was requested, so that a
+    /// misconfigured override (such as from `UserDatabase::token_lifetime`) can't mint tokens that last for years.
+    /// A user's tokens invalidated by a password change are only remembered for this long (or for
+    /// `auth_token_lifetime`, if unset), so set it if any tokens may outlive `auth_token_lifetime`.
+    pub max_token_lifetime: Option<Duration>,
+    /// The user database, which requests reach concurrently (see [`UserDatabase`]).
+    pub database_connection: Arc<dyn UserDatabase>,
+    /// Whether the register and login routes also accept `application/x-www-form-urlencoded` bodies,
+    /// as sent by plain HTML forms. JSON bodies are always accepted.
+    pub accept_form_bodies: bool,
+    /// Whether the login route also accepts credentials in an `Authorization: Basic` header in place of a body, for
+    /// scripts and command line tools. Usernames containing a colon, or scoped to a tenant, can't login this way.
+    pub accept_basic_auth_login: bool,
+    /// Whether the auth routes also match their paths with a trailing slash, such as `/users/login/`. If not, such
+    /// paths are rejected as not found, and left to any other routes. Defaults to true.
+    pub accept_trailing_slash: bool,
+    /// Where the login route places the issued auth token.
+    pub token_delivery: TokenDelivery,
+    /// The attributes of the auth cookie, when tokens are delivered by `TokenDelivery::Cookie`. Login sets the cookie
+    /// and logout clears it with these same attributes, as browsers only delete a cookie that matches them.
+    pub cookie_attributes: CookieAttributes,
+    /// The name of a cookie to deliver a CSRF token in, alongside the auth cookie, and also in the `csrf_token` field
+    /// of the response body. Unlike the auth cookie, scripts can read it, so that they can echo it back in the
+    /// `X-CSRF-Token` header, which `with_csrf_protection` checks. The CSRF token is bound to the session, as it is
+    /// stamped into the auth token. Only used with `TokenDelivery::Cookie`; if `None`, no CSRF token is issued.
+    pub csrf_cookie: Option<String>,
+    /// Scopes granted to every token issued at login. Delegated tokens may only narrow these.
+    pub default_scopes: Vec<String>,
+    /// How long to wait on any single database operation before failing the request with a 503.
+    /// If `None`, database operations may wait indefinitely.
+    pub db_operation_timeout: Option<Duration>,
+    /// Delivers magic link tokens to users out of band, for instance by emailing them a link containing the token.
+    /// The sender should not block; spawn a task for any slow delivery.
+    /// The magic link routes are only mounted if a sender is configured.
+    pub magic_link_sender: Option<MagicLinkSender>,
+    /// How long a magic link token remains valid for. Each magic link token may only be used once.
+    pub magic_link_lifetime: Duration,
+    /// Delivers password reset tokens to users out of band, for instance by emailing them a link containing the token.
+    /// The sender should not block; spawn a task for any slow delivery.
+    /// The forgot password and reset routes are only mounted if a sender is configured.
+    pub password_reset_sender: Option<PasswordResetSender>,
+    /// How long a password reset token remains valid for. Each password reset token may only be used once.
+    pub password_reset_lifetime: Duration,
+    /// Delivers email verification tokens to users out of band, for instance by emailing them a link containing the
+    /// token, when they register and whenever they ask for another. The sender should not block; spawn a task for any
+    /// slow delivery. The verification routes are only mounted if a sender is configured.
+    pub verification_sender: Option<VerificationSender>,
+    /// How long an email verification token remains valid for. Each verification token may only be used once.
+    pub verification_lifetime: Duration,
+    /// Whether users must verify their email before they can login (or change their password, which logs them in
+    /// afresh). If enabled, registration responds `202 Accepted`, with `verification_pending` set, and logins are
+    /// refused with [`AuthError::VerificationRequired`] until the user follows their verification link. Requires a
+    /// `verification_sender`, without which no user could ever login, so `Auth::try_new` refuses a config that
+    /// lacks one.
+    pub require_verification: bool,
+    /// How many verification tokens may be resent to an identifier, and over how long, before further requests are
+    /// refused with [`AuthError::TooManyRequests`]. Requests are counted in the `throttle_store`.
+    pub verification_resend_limit: ResendLimit,
+    /// If set, issued tokens are stamped with this audience, and only tokens issued for it are accepted.
+    pub auth_token_audience: Option<String>,
+    /// The audiences clients may ask for tokens to be issued for, by passing an `audience` to the login route, for a
+    /// login shared by several services. The requested audience takes the place of the `auth_token_audience` in the
+    /// issued token, so if that is set, tokens issued for other audiences are only accepted by those services.
+    /// Requests for any other audience are refused with [`AuthError::AudienceNotAllowed`].
+    pub login_audiences: Vec<String>,
+    /// Verify tokens against the keys published by an external identity provider (such as Auth0 or Keycloak)
+    /// instead of the auth_token_secret, so that `with_auth` can guard routes without this crate issuing tokens.
+    /// Tokens must still match the auth_token_issuer, which should be set to the provider's issuer.
+    #[cfg(feature = "oidc")]
+    pub external_jwks: Option<Arc<RemoteJwks>>,
+    /// Verify tokens against a fixed set of public keys instead of the auth_token_secret, as `external_jwks` does but
+    /// without fetching them, for verifiers that can't reach the provider. Takes precedence over `external_jwks`.
+    pub static_jwks: Option<Arc<StaticJwks>>,
+    /// The claim from which `with_auth` takes the user id. Defaults to `sub`, but tokens from external providers
+    /// may carry the id in a different claim, such as `oid` or `email`.
+    pub user_id_claim: String,
+    /// Whether new users may register themselves via the `/users/register` route.
+    /// If disabled, the route is not mounted, and users must be created by the application.
+    pub registration_enabled: bool,
+    /// Whether the `/users/available` route is mounted, which reports whether a username is free to register, for
+    /// signup forms. Disabled by default, as it lets anyone discover which usernames exist.
+    pub username_availability_enabled: bool,
+    /// Whether the `/users/me` route is mounted, which describes the authenticated user (their id, username and
+    /// roles), so that clients can adapt their UI without decoding the token.
+    pub me_route_enabled: bool,
+    /// Whether the `/auth/config` route is mounted, which describes the non-secret parts of this config (the issuer,
+    /// token lifetime, algorithms, enabled login methods and password policy), so that clients can configure
+    /// themselves, and check new passwords before submitting them.
+    pub public_config_enabled: bool,
+    /// The claims a token must satisfy to call `/auth/revoke-all`, which revokes every token issued so far (including
+    /// the caller's own), as a mass logout in an incident. If `None`, the route is not mounted; tokens can still be
+    /// revoked with `Auth::revoke_tokens_issued_before`.
+    pub revoke_all_requirements: Option<Vec<ClaimRequirement>>,
+    /// How long after a token expires it may still be exchanged for a fresh one via `/users/refresh`.
+    /// Beyond this, the user must login again. Only the refresh route honours this grace period. Each token may only be
+    /// refreshed once, as refreshing it revokes it.
+    pub refresh_grace_period: Duration,
+    /// Whether to reject tokens whose `iat` is further in the future than the leeway allowed for clock skew, which
+    /// suggests a badly skewed clock or a forgery. Tokens without an `iat` are unaffected.
+    pub reject_future_iat: bool,
+    /// The minimum time a login attempt or password reset request takes, successful or not. Padding these out to a
+    /// fixed duration hides timing differences (such as in the database lookup) that could reveal whether a username exists.
+    pub min_login_duration: Option<Duration>,
+    /// The upper bound of a random delay added on top of `min_login_duration`, so that the time taken is noisy as well
+    /// as padded, and the padding itself can't be told apart from the work it hides. If `None`, there is no jitter.
+    pub login_duration_jitter: Option<Duration>,
+    /// A secret key mixed into every password hash by argon2 itself. Unlike the salt, it is never stored
+    /// alongside the hashes, so a leaked database can't be attacked without also stealing this secret.
+    /// If the secret changes (or is added or removed), all previously-stored passwords can no longer be authenticated.
+    pub argon2_secret: Option<String>,
+    /// Whether usernames are only unique within a tenant, for multi-tenant apps. If enabled, the register, login and
+    /// magic link routes require a `tenant` alongside the username, the database is accessed through its tenant-aware
+    /// methods, and issued tokens carry a `tenant` claim.
+    pub tenant_scoped_usernames: bool,
+    /// How many previous passwords a user may not reuse when changing their password, in addition to the current one.
+    pub password_history_length: usize,
+    /// The minimum Shannon entropy, in bits, of new passwords: their length times the entropy of each character,
+    /// judged by how often it appears in the password. This rejects long passwords with little variety, such as
+    /// `aaaaaaaaaaaa`, which length rules alone accept. If `None`, any password is accepted.
+    pub min_password_entropy: Option<f64>,
+    /// The minimum length, in characters, of new passwords. If `None`, passwords of any length are accepted.
+    pub min_password_length: Option<usize>,
+    /// Whether to reject new passwords that are empty or entirely whitespace, which are accepted by everything else
+    /// short of a full password policy. Enabled by default.
+    pub reject_blank_passwords: bool,
+    /// The maximum number of sessions each user may have active at once. Every token issued at login starts a
+    /// session, which ends when the token expires, is revoked at logout, or is refreshed. If `None`, sessions aren't tracked.
+    pub max_sessions: Option<usize>,
+    /// What to do when a login would exceed `max_sessions`.
+    pub session_limit_policy: SessionLimitPolicy,
+    /// The maximum number of entries kept in each of the in-memory stores (those counted by `Auth::store_sizes`), as
+    /// a backstop against them growing without bound. Entries are never evicted before they expire, as that would
+    /// accept tokens that should be refused. Instead, while the stores a token would be recorded in (or the store of
+    /// revoked tokens) are full, no more tokens are issued, and single-use tokens, throttled attempts and stored
+    /// tokens beyond a full store are refused, all with [`AuthError::StoreFull`]. Revocations and invalidations are
+    /// always kept, even beyond a full store, and users beyond a full last-seen store are reported on every request.
+    /// If `None`, entries are only forgotten once they expire (see `Auth::spawn_cleanup`).
+    pub max_store_entries: Option<usize>,
+    /// Supplies the token secrets at runtime in place of the static `auth_token_secret`, so that they can be rotated
+    /// without a restart.
+    pub secret_provider: Option<Arc<dyn SecretProvider>>,
+    /// Adapt the cost of hashing new passwords to the load on the server. If `None`, argon2's default cost is always used.
+    pub adaptive_hash_cost: Option<Arc<AdaptiveHashCost>>,
+    /// How many password hashes (for login, registration, password changes and resets) may be computed at once; the
+    /// rest wait their turn, which is only held while hashing. Defaults to the number of CPUs.
+    pub max_concurrent_hashes: usize,
+    /// How many requests may wait to hash passwords before further ones are refused with [`AuthError::Overloaded`],
+    /// so that a flood of logins is shed with a 503 rather than queueing without bound. If `None`, requests always
+    /// wait.
+    pub max_queued_hashes: Option<usize>,
+    /// Namespaces the subject of issued tokens, for issuers shared between apps. The prefix is prepended to the
+    /// user id in the `sub` claim, and stripped again on verification; tokens without it are rejected.
+    pub sub_prefix: Option<String>,
+    /// Whether session tokens carry a random pseudonym in their `sub` claim in place of the user id, so that parties
+    /// the token is shown to can't correlate a user across tokens. Pseudonyms are resolved back to the user id on
+    /// verification, from a table held in memory: tokens can't be verified by any other service (or instance of
+    /// this one), and every outstanding token is invalidated when the server restarts.
+    pub pseudonymous_subjects: bool,
+    /// Called when a user logs in from a browser with an anonymous session (see `with_anonymous_session`), with the
+    /// session id and the user id, so that anything gathered during the anonymous session can be linked to the user.
+    pub anonymous_session_linker: Option<AnonymousSessionLinker>,
+    /// The minimum estimated strength (from 0 to 4) a password must have to register. Registration always returns
+    /// the estimated score and suggestions for a stronger password; if `None`, weak passwords are still accepted.
+    #[cfg(feature = "password-strength")]
+    pub min_password_score: Option<u8>,
+    /// Compress the claims of issued tokens whose claims would otherwise exceed this many bytes, to keep tokens with
+    /// many custom claims within cookie and header size limits. Compressed claims are carried in a `zip` claim,
+    /// alongside only the claims needed to validate the token, so other verifiers must also support compression.
+    /// Uncompressed tokens are always accepted. If `None`, tokens are never compressed.
+    #[cfg(feature = "compression")]
+    pub token_compression_threshold: Option<usize>,
+    /// Headers added to every successful response from the auth routes, such as `Strict-Transport-Security` or
+    /// `X-Content-Type-Options`, unless the route sets the same header itself. Responses carrying a token are always
+    /// sent with `Cache-Control: no-store` and `Pragma: no-cache`.
+    pub response_headers: HeaderMap,
+    /// The shape of the JSON body of error responses, when recovering with `auth_error_handler`.
+    pub error_format: ErrorFormat,
+    /// The status of the response to registering an identifier that is already taken, when recovering with
+    /// `auth_error_handler`. Defaults to 409 Conflict; some API style guides call for 422 Unprocessable Entity.
+    pub username_taken_status: StatusCode,
+    /// How the login and register routes respond to a request that already carries a valid auth token. If `None`,
+    /// such requests are processed as usual.
+    pub already_authenticated_response: Option<AlreadyAuthenticatedResponse>,
+    /// Transforms password hashes into the form stored by the database, and back. If `None`, the argon2-encoded
+    /// hash is stored as is.
+    pub password_encoding: Option<Arc<dyn PasswordEncoding>>,
+    /// Whether issued tokens carry the user's username in the `preferred_username` claim, so that handlers can
+    /// display it (via `with_principal`) without a database lookup. The username is captured when the token is
+    /// issued, so if it changes, tokens carry the old one until the user logs in again.
+    pub username_claim: bool,
+    /// Lock a username out of logging in (and changing its password) after repeated failed attempts.
+    /// If `None`, failed attempts aren't limited.
+    pub lockout: Option<LockoutPolicy>,
+    /// Where failed login attempts are counted for `lockout`. Defaults to the memory of this process; share a store
+    /// between instances so that lockouts apply across all of them.
+    pub throttle_store: Arc<dyn ThrottleStore>,
+    /// Password hashing schemes by version, for migrating stored hashes between schemes (see [`PasswordHasher`]).
+    /// Versions without a registered hasher use the built-in argon2 hasher.
+    pub password_hashers: HashMap<String, Arc<dyn PasswordHasher>>,
+    /// The version new password hashes are created with. Hashes of any other version are transparently rehashed
+    /// with this one when the user next logs in (if the database supports `update_password`). If `None`, hashes are
+    /// created unversioned by the built-in argon2 hasher.
+    pub password_hash_version: Option<String>,
+    /// Verifies stored hashes in formats that neither a registered hasher nor argon2 can parse, such as those left
+    /// over from a migration from another system. Hashes it verifies are upgraded at login if `password_hash_version`
+    /// is set. If `None`, such hashes fail to verify, with a warning.
+    pub legacy_password_verifier: Option<LegacyPasswordVerifier>,
+    /// The `kid` header of issued tokens, identifying the key they were signed with, unless the `secret_provider`
+    /// supplies one. If `None`, tokens carry no `kid`.
+    pub auth_token_key_id: Option<String>,
+    /// The algorithms tokens are accepted with, which must all be HMAC algorithms (`HS256`, `HS384` or `HS512`).
+    /// Issued tokens are signed with the first. Tokens are only ever verified against this list, never against the
+    /// algorithm named in their own header, so an attacker can't downgrade to a weaker one.
+    pub auth_token_algorithms: Vec<Algorithm>,
+    /// The `typ` header of issued tokens, such as `at+jwt` for access tokens as per RFC 9068. Defaults to `JWT`.
+    pub auth_token_header_type: String,
+    /// The profile issued session tokens conform to.
+    pub token_profile: TokenProfile,
+    /// How many registrations each client address may make, and over how long, before further registrations are
+    /// refused with [`AuthError::RegistrationThrottled`]. Registrations are counted in the `throttle_store`, whether
+    /// or not they succeed. If `None`, registrations aren't limited.
+    pub registration_limit: Option<RegistrationLimit>,
+    /// A challenge, such as a CAPTCHA, that every registration must pass. If `None`, registrations aren't challenged.
+    pub registration_challenge: Option<Arc<dyn RegistrationChallenge>>,
+    /// Users and client addresses to refuse, even with valid credentials or tokens. If `None`, nobody is blocked.
+    pub block_list: Option<Arc<dyn BlockList>>,
+    /// The request field users are identified by when they register and login, such as `email`, and how it is
+    /// normalized. Whatever the field, the identifier is stored in the database as the username.
+    pub login_identifier: LoginIdentifier,
+    /// Normalize login identifiers to Unicode NFKC before they reach the database, so that names written with composed
+    /// or decomposed accents, or with compatibility characters such as fullwidth letters, are the same user. If the
+    /// `login_identifier` is case-insensitive, identifiers are also case-folded beyond ASCII. Existing identifiers must
+    /// already be normalized.
+    #[cfg(feature = "unicode-normalization")]
+    pub unicode_normalized_identifiers: bool,
+    /// Names that can't be registered, such as `admin`, `support` or the app's own name, so that users can't pass
+    /// themselves off as its staff. They are normalized as login identifiers are, so if the `login_identifier` is
+    /// case-insensitive, `Admin` is reserved along with `admin`. Registrations of a reserved name are refused with
+    /// [`AuthError::InvalidUsername`].
+    pub reserved_usernames: Vec<String>,
+    /// A key to hash login identifiers with (HMAC-SHA256) before they reach the database, so that it only ever holds
+    /// opaque identifiers, such as for deployments that mustn't store email addresses in plaintext. The database
+    /// receives the hex digest in place of the username, and the same key must be kept to look users up again. If
+    /// `None`, identifiers are stored as given.
+    pub identifier_hash_key: Option<String>,
+    /// The header a TLS-terminating proxy passes the client certificate's thumbprint in (base64url-encoded SHA-256,
+    /// as in RFC 8705), to bind tokens to the certificate. If set, tokens issued at login over a connection with a
+    /// client certificate carry its thumbprint in their `cnf` claim, and are only accepted (and refreshed) from requests
+    /// with the same thumbprint. The proxy must strip this header from incoming requests. If `None`, tokens aren't bound.
+    pub client_cert_thumbprint_header: Option<String>,
+    /// Store issued session tokens server-side, and give clients only an opaque reference to them, for deployments
+    /// that want small tokens whose claims clients can't read, and that can be revoked at once by removing them from
+    /// the store. Every request is then authenticated with a lookup in the store, and only references are accepted.
+    /// Magic link, password reset and verification tokens are still issued in full. If `None`, tokens are
+    /// self-contained JWTs.
+    pub token_store: Option<Arc<dyn TokenStore>>,
+    /// Called whenever a request presents a token that fails verification, for security monitoring. It is told why
+    /// the token was refused and the client address, but never the token itself, and can't affect the response. It is
+    /// called on a thread of its own, one failure at a time, once the request has been refused, so it may be slow, but
+    /// reports may arrive after the response has been sent. Failures that arrive while 1024 are still waiting to be
+    /// reported are dropped.
+    pub on_auth_failure: Option<AuthFailureCallback>,
+    /// Called with the claims of every token that passes the built-in checks, as JSON, to apply rules of the app's
+    /// own, such as refusing tokens for suspended tenants. Returning an error vetoes the token with the given reason,
+    /// rejecting it as `AuthError::TokenRejected`.
+    pub token_validator: Option<TokenValidator>,
+    /// Called with the time an authenticated request was made, for "active now" indicators and cleaning up idle
+    /// accounts. Calls are coalesced so that each user is reported at most once per `last_seen_interval`, however
+    /// many requests they make, and so it is up to the callback to store the time (or to spawn a task that does).
+    pub last_seen_recorder: Option<LastSeenRecorder>,
+    /// The minimum time between reports of the same user to the `last_seen_recorder`. Defaults to a minute.
+    pub last_seen_interval: Duration,
+}
+
+/// Callback used to deliver a magic link token to the named user.
+pub type MagicLinkSender = Arc<dyn Fn(&Username, &str) + Send + Sync>;
+
+/// Callback used to associate an anonymous session id with the user who logged in from it.
+pub type AnonymousSessionLinker = Arc<dyn Fn(&str, &UserID) + Send + Sync>;
+
+/// Callback used to deliver a password reset token to the named user.
+pub type PasswordResetSender = Arc<dyn Fn(&Username, &str) + Send + Sync>;
+
+/// Callback used to deliver an email verification token to the named user.
+pub type VerificationSender = Arc<dyn Fn(&Username, &str) + Send + Sync>;
+
+/// Callback used to check a password against a stored hash in an unrecognised format.
+pub type LegacyPasswordVerifier = Arc<dyn Fn(&str, &HashedPassword) -> bool + Send + Sync>;
+
+/// Callback used to report a request whose auth token failed verification, with the client address if known.
+pub type AuthFailureCallback = Arc<dyn Fn(AuthFailureKind, Option<IpAddr>) + Send + Sync>;
+
+/// Callback used to record when a user was last seen making an authenticated request.
+pub type LastSeenRecorder = Arc<dyn Fn(&UserID, SystemTime) + Send + Sync>;
+
+/// Callback used to apply custom validation to the claims of a token, returning the reason if the token is refused.
+pub type TokenValidator = Arc<dyn Fn(&Value) -> Result<(), String> + Send + Sync>;
+
+/// Why a request's auth token failed verification. Responses to the client don't distinguish between these, but they
+/// are reported to `AuthConfig::on_auth_failure` for logs and metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthFailureKind {
+    /// The signature doesn't match, as for a forged or tampered token.
+    InvalidSignature,
+    Expired,
+    /// The token was issued for a different audience.
+    InvalidAudience,
+    /// The token was issued by a different issuer.
+    InvalidIssuer,
+    /// The token lacks a required claim, such as `exp`, or a claim has the wrong type.
+    InvalidClaims,
+    /// The token is malformed, revoked, or otherwise unacceptable.
+    Invalid,
+    /// The user or client address is on the block list.
+    Blocked,
+}
+
+impl AuthFailureKind {
+    /// The kind of verification failure an error represents, such as one returned by [`Auth::authenticate`], or
+    /// `None` if it isn't a verification failure but, say, an outage.
+    pub fn of(error: &AuthError) -> Option<Self> {
+        match error {
+            AuthError::TokenError {
+                source: Some(source),
+            } => match source.kind() {
+                ErrorKind::InvalidSignature => Some(Self::InvalidSignature),
+                ErrorKind::ExpiredSignature => Some(Self::Expired),
+                ErrorKind::InvalidAudience => Some(Self::InvalidAudience),
+                ErrorKind::InvalidIssuer => Some(Self::InvalidIssuer),
+                ErrorKind::MissingRequiredClaim(_) => Some(Self::InvalidClaims),
+                ErrorKind::Json(error) if error.is_data() => Some(Self::InvalidClaims),
+                _ => Some(Self::Invalid),
+            },
+            AuthError::TokenError { source: None }
+            | AuthError::UnknownKeyId { .. }
+            | AuthError::TokenRejected { .. } => Some(Self::Invalid),
+            AuthError::Blocked => Some(Self::Blocked),
+            _ => None,
+        }
+    }
+}
+
+/// The request field users are identified by, and how it is normalized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginIdentifier {
+    /// The name of the field in request bodies (and query strings). Defaults to `username`.
+    pub field: String,
+    /// Whether identifiers are matched regardless of case, as is usual for email addresses. If enabled, identifiers
+    /// are trimmed and lowercased before they reach the database, so existing identifiers must already be lowercase,
+    /// unless they are brought in with `Auth::import_users`, which lowercases them too.
+    pub case_insensitive: bool,
+}
+
+impl Default for LoginIdentifier {
+    fn default() -> Self {
+        Self {
+            field: "username".into(),
+            case_insensitive: false,
+        }
+    }
+}
+
+/// The shape of issued session tokens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenProfile {
+    /// Only the claims this crate needs, with the `typ` header set by `AuthConfig::auth_token_header_type`.
+    Default,
+    /// Access tokens as per RFC 9068, for interoperability with API gateways: the `typ` header is `at+jwt`, and tokens
+    /// always carry the `client_id`, `iat`, `jti`, `aud` and `scope` claims. The audience is the
+    /// `auth_token_audience` if set, or else the `auth_token_issuer`, as the issuer's own API.
+    AccessToken {
+        /// The client the token is issued to.
+        client_id: String,
+    },
+}
+
+/// How often verification tokens may be resent to the same identifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResendLimit {
+    /// How many resends are allowed within the window.
+    pub max_resends: u32,
+    /// How long resends are counted for, from the first.
+    pub window: Duration,
+}
+
+/// How often the same client address may register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegistrationLimit {
+    /// How many registrations are allowed within the window.
+    pub max_registrations: u32,
+    /// How long registrations are counted for, from the first.
+    pub window: Duration,
+}
+
+/// When to lock a username out after failed login attempts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    /// How many failed attempts are allowed before further attempts are refused with [`AuthError::AccountLocked`].
+    pub max_failed_attempts: u32,
+    /// How long failed attempts are counted for, from the first. The lockout lifts once this has passed.
+    pub lockout_duration: Duration,
+}
+
+/// An active session, started by a login.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    /// The id (`jti`) of the session's token.
+    pub id: String,
+    pub expires_at: SystemTime,
+}
+
+/// The number of entries in each of the in-memory stores, as reported by `Auth::store_sizes`, for metrics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreSizes {
+    /// Ids of tokens revoked before they expired, at logout or to make room within `max_sessions`.
+    pub revoked_tokens: usize,
+    /// Ids of single-use (magic link, password reset and verification) tokens that have been used.
+    pub used_tokens: usize,
+    /// Active sessions across all users, if `max_sessions` is set.
+    pub sessions: usize,
+    /// Pseudonymous subjects, if `pseudonymous_subjects` is enabled.
+    pub pseudonyms: usize,
+    /// Devices that tokens are bound to.
+    pub device_bindings: usize,
+    /// Users whose earlier tokens were invalidated, such as by a password change, while those tokens may still be
+    /// unexpired.
+    pub not_before: usize,
+    /// Users reported to the `last_seen_recorder` within the `last_seen_interval`.
+    pub last_seen: usize,
+    /// Keys attempts are counted against in the `throttle_store`, if it is kept in memory.
+    pub throttle_keys: Option<usize>,
+    /// Tokens held in the `token_store`, if there is one and it is kept in memory.
+    pub stored_tokens: Option<usize>,
+}
+
+/// What happens when a user with the maximum number of active sessions logs in again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionLimitPolicy {
+    /// End the user's oldest session, revoking its token.
+    EvictOldest,
+    /// Refuse the new login with [`AuthError::TooManySessions`].
+    RejectNew,
+}
+
+/// How an issued auth token is returned to the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenDelivery {
+    /// In the `token` field of the JSON response body.
+    Body,
+    /// In the named response header only, e.g. `X-Auth-Token`.
+    Header(String),
+    /// In both the JSON response body and the named response header.
+    BodyAndHeader(String),
+    /// In an HttpOnly cookie with the given name, for browser clients. Authenticated routes accept the token from
+    /// this cookie when the request has no authorization header, and logout clears it.
+    Cookie(String),
+}
+
+impl TokenDelivery {
+    pub(crate) fn in_body(&self) -> bool {
+        matches!(self, Self::Body | Self::BodyAndHeader(_))
+    }
+
+    pub(crate) fn header(&self) -> Option<&str> {
+        match self {
+            Self::Header(name) | Self::BodyAndHeader(name) => Some(name),
+            Self::Body | Self::Cookie(_) => None,
+        }
+    }
+
+    pub(crate) fn cookie(&self) -> Option<&str> {
+        match self {
+            Self::Cookie(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+/// The attributes of the auth cookie. The cookie is always `HttpOnly`, so that scripts can't read the token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CookieAttributes {
+    pub path: String,
+    /// The domain the cookie is sent to, including its subdomains. If unset, it is only sent to the host that set it.
+    pub domain: Option<String>,
+    pub secure: bool,
+    pub same_site: SameSite,
+}
+
+impl Default for CookieAttributes {
+    fn default() -> Self {
+        Self {
+            path: "/".into(),
+            domain: None,
+            secure: true,
+            same_site: SameSite::Strict,
+        }
+    }
+}
+
+impl CookieAttributes {
+    // The Set-Cookie header value that sets the named cookie, or clears it if max_age is zero
+    pub(crate) fn set_cookie(&self, name: &str, value: &str, max_age: u64) -> String {
+        self.cookie(name, value, max_age, true)
+    }
+
+    // As set_cookie, but for a cookie scripts can read, such as the CSRF token
+    pub(crate) fn set_readable_cookie(&self, name: &str, value: &str, max_age: u64) -> String {
+        self.cookie(name, value, max_age, false)
+    }
+
+    fn cookie(&self, name: &str, value: &str, max_age: u64, http_only: bool) -> String {
+        let mut cookie = format!("{name}={value}; Max-Age={max_age}; Path={}", self.path);
+        if let Some(domain) = &self.domain {
+            cookie.push_str(&format!("; Domain={domain}"));
+        }
+        if http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+        cookie.push_str(&format!("; SameSite={}", self.same_site.as_str()));
+        cookie
+    }
+}
+
+/// Whether browsers send the auth cookie on cross-site requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// Requires `CookieAttributes::secure`.
+    None,
+}
+
+impl SameSite {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Strict => "Strict",
+            Self::Lax => "Lax",
+            Self::None => "None",
+        }
+    }
+}
+
+/// The response to a login or register request from a client that is already authenticated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlreadyAuthenticatedResponse {
+    /// 200 OK, with the JSON body `{"already_authenticated": true}`.
+    Ok,
+    /// 303 See Other, redirecting to the given location, such as the app's home page.
+    Redirect(String),
+}
+
+/// The shape of the JSON body of error responses, for compatibility with the error handling of different frontends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// `{"error": "<message>"}`
+    Error,
+    /// `{"code": "<machine-readable code>", "detail": "<message>"}`
+    CodeDetail,
+    /// RFC 7807 problem details, `{"type", "title", "detail", "status"}`, served as `application/problem+json`.
+    Problem,
+}
+
+impl AuthConfig {
+    /// Create a config from the required settings, with every optional feature left at its default.
+    pub fn new(
+        password_salt: impl Into<String>,
+        auth_token_issuer: impl Into<String>,
+        auth_token_secret: impl Into<String>,
+        auth_token_lifetime: Duration,
+        database_connection: Arc<dyn UserDatabase>,
+    ) -> Self {
+        Self {
+            password_salt: password_salt.into(),
+            auth_token_issuer: auth_token_issuer.into(),
+            auth_token_secret: auth_token_secret.into(),
+            auth_token_lifetime,
+            max_token_lifetime: None,
+            database_connection,
+            accept_form_bodies: false,
+            accept_basic_auth_login: false,
+            accept_trailing_slash: true,
+            token_delivery: TokenDelivery::Body,
+            cookie_attributes: CookieAttributes::default(),
+            csrf_cookie: None,
+            default_scopes: vec![],
+            db_operation_timeout: None,
+            magic_link_sender: None,
+            magic_link_lifetime: Duration::from_secs(15 * 60),
+            password_reset_sender: None,
+            password_reset_lifetime: Duration::from_secs(15 * 60),
+            verification_sender: None,
+            verification_lifetime: Duration::from_secs(24 * 60 * 60),
+            require_verification: false,
+            verification_resend_limit: ResendLimit {
+                max_resends: 3,
+                window: Duration::from_secs(60 * 60),
+            },
+            auth_token_audience: None,
+            login_audiences: vec![],
+            #[cfg(feature = "oidc")]
+            external_jwks: None,
+            static_jwks: None,
+            user_id_claim: "sub".into(),
+            registration_enabled: true,
+            username_availability_enabled: false,
+            me_route_enabled: false,
+            public_config_enabled: false,
+            revoke_all_requirements: None,
+            refresh_grace_period: Duration::ZERO,
+            reject_future_iat: false,
+            min_login_duration: None,
+            login_duration_jitter: None,
+            argon2_secret: None,
+            tenant_scoped_usernames: false,
+            password_history_length: 0,
+            min_password_entropy: None,
+            min_password_length: None,
+            reject_blank_passwords: true,
+            max_sessions: None,
+            max_store_entries: None,
+            session_limit_policy: SessionLimitPolicy::EvictOldest,
+            secret_provider: None,
+            adaptive_hash_cost: None,
+            max_concurrent_hashes: default_max_concurrent_hashes(),
+            max_queued_hashes: None,
+            sub_prefix: None,
+            pseudonymous_subjects: false,
+            anonymous_session_linker: None,
+            on_auth_failure: None,
+            token_validator: None,
+            last_seen_recorder: None,
+            last_seen_interval: Duration::from_secs(60),
+            #[cfg(feature = "password-strength")]
+            min_password_score: None,
+            #[cfg(feature = "compression")]
+            token_compression_threshold: None,
+            response_headers: HeaderMap::new(),
+            error_format: ErrorFormat::Error,
+            username_taken_status: StatusCode::CONFLICT,
+            already_authenticated_response: None,
+            password_encoding: None,
+            username_claim: false,
+            lockout: None,
+            throttle_store: Arc::new(MemoryThrottleStore::default()),
+            password_hashers: HashMap::new(),
+            password_hash_version: None,
+            legacy_password_verifier: None,
+            auth_token_key_id: None,
+            auth_token_algorithms: vec![Algorithm::HS256],
+            auth_token_header_type: "JWT".into(),
+            token_profile: TokenProfile::Default,
+            registration_limit: None,
+            registration_challenge: None,
+            block_list: None,
+            login_identifier: LoginIdentifier::default(),
+            #[cfg(feature = "unicode-normalization")]
+            unicode_normalized_identifiers: false,
+            reserved_usernames: vec![],
+            identifier_hash_key: None,
+            client_cert_thumbprint_header: None,
+            token_store: None,
+        }
+    }
+
+    /// Check for obviously insecure settings, such as the placeholder secrets from the examples, and for settings that
+    /// can't work together. Returns a description of each problem found.
+    pub fn insecure_settings(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        if self.auth_token_secret.len() < MIN_SECRET_LENGTH {
+            problems.push(format!(
+                "auth_token_secret should be at least {MIN_SECRET_LENGTH} bytes long"
+            ));
+        }
+        if self.password_salt.len() < MIN_SALT_LENGTH {
+            problems.push(format!(
+                "password_salt should be at least {MIN_SALT_LENGTH} bytes long"
+            ));
+        }
+        if self.auth_token_algorithms.is_empty()
+            || self
+                .auth_token_algorithms
+                .iter()
+                .any(|algorithm| !HMAC_ALGORITHMS.contains(algorithm))
+        {
+            problems.push("auth_token_algorithms should list only HS256, HS384 or HS512".into());
+        }
+        if self.auth_token_lifetime.is_zero() {
+            problems.push("auth_token_lifetime should be greater than zero".into());
+        }
+        if self.token_delivery.cookie().is_some() && !self.cookie_attributes.secure {
+            problems.push(
+                "cookie_attributes should be secure, so the auth cookie is only sent over HTTPS"
+                    .into(),
+            );
+        }
+        if self.require_verification && self.verification_sender.is_none() {
+            problems.push(
+                "require_verification needs a verification_sender, or no user can verify their email to login"
+                    .into(),
+            );
+        }
+
+        problems
+    }
+}
+
+// Cheaply reject anything that isn't shaped like a JWT (three base64url segments) before decoding it
+fn check_token_shape(token: &str) -> Result<(), AuthError> {
+    let well_formed = token.len() <= MAX_TOKEN_LENGTH
+        && token.split('.').count() == 3
+        && token
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.'));
+
+    if !well_formed {
+        return Err(AuthError::TokenError { source: None });
+    }
+
+    Ok(())
+}
+
+// Scopes are carried in a single space-delimited claim, per RFC 8693
+fn join_scopes<S: AsRef<str>>(scopes: &[S]) -> Option<String> {
+    if scopes.is_empty() {
+        None
+    } else {
+        Some(
+            scopes
+                .iter()
+                .map(AsRef::as_ref)
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    }
+}
+
+/// The `acr` assurance level of tokens issued after authenticating with a single factor, such as a password.
+pub const ACR_SINGLE_FACTOR: u32 = 1;
+/// The `acr` assurance level of tokens issued after authenticating with two or more factors.
+pub const ACR_MULTI_FACTOR: u32 = 2;
+
+// RFC 8176 authentication method references, plus one for possession of the user's email, as proven by following
+// a magic link or password reset link
+pub(crate) const AMR_PASSWORD: &str = "pwd";
+pub(crate) const AMR_EMAIL: &str = "email";
+
+// Each distinct authentication method counts as a factor
+fn acr_level(amr: &[String]) -> u32 {
+    if amr.len() > 1 {
+        ACR_MULTI_FACTOR
+    } else {
+        ACR_SINGLE_FACTOR
+    }
+}
+
+const MAGIC_TOKEN_TYPE: &str = "magic";
+const RESET_TOKEN_TYPE: &str = "reset";
+const VERIFICATION_TOKEN_TYPE: &str = "verify";
+// The typ header of RFC 9068 access tokens
+const ACCESS_TOKEN_HEADER_TYPE: &str = "at+jwt";
+
+// Matches the leeway jsonwebtoken applies to expiry by default
+const TOKEN_LEEWAY_SECS: u64 = 60;
+
+// Far larger than any token this crate issues, while bounding the work spent on garbage
+const MAX_TOKEN_LENGTH: usize = 8 * 1024;
+
+const HMAC_ALGORITHMS: &[Algorithm] = &[Algorithm::HS256, Algorithm::HS384, Algorithm::HS512];
+
+const MIN_SECRET_LENGTH: usize = 32;
+const MIN_SALT_LENGTH: usize = 16;
+
+// A newly issued session token, with its expiry time and the CSRF token bound to it if one was issued
+pub(crate) struct IssuedToken {
+    pub(crate) token: String,
+    pub(crate) expires_at: u64,
+    pub(crate) csrf_token: Option<String>,
+}
+
+// What the client asked of a session token: the device and client certificate to bind it to, and the audience to
+// issue it for in place of the configured one
+#[derive(Default)]
+pub(crate) struct SessionRequest<'a> {
+    pub(crate) device_id: Option<&'a str>,
+    pub(crate) cert_thumbprint: Option<&'a str>,
+    pub(crate) audience: Option<&'a str>,
+    // the deadline of the token being refreshed, past which the new one may not last either
+    pub(crate) deadline: Option<u64>,
+}
+
+// A token presented to authenticate a request, as far as it can be made out without holding the lock
+pub(crate) enum PresentedToken {
+    // issued by an external provider, and already decoded and checked
+    External(Box<Claims>),
+    // issued here, with any reference to it resolved, still to be verified by verify_session_token
+    Session(String),
+}
+
+// The user a single-use (magic link, password reset or verification) token was issued to, with the token's id and
+// expiry time for marking it as used
+pub(crate) struct SingleUseSubject {
+    pub(crate) user_id: UserID,
+    pub(crate) username: Option<Username>,
+    pub(crate) tenant: Option<Tenant>,
+    jti: String,
+    exp: u64,
+}
+
+// The parts of the auth module that are fixed once it is built: its config, and the account operations built on it.
+// Handlers clone it to do the slow parts of a request, such as hashing passwords, without holding the lock on the
+// token state in AuthInternal
+#[derive(Clone)]
+pub(crate) struct AuthCore {
+    config: Arc<AuthConfig>,
+    secrets: Arc<dyn SecretProvider>,
+    // hash verified in place of a real one when the user doesn't exist, so that both cases cost the same. It is
+    // made at startup with the current hashing parameters, unless they are unusable
+    dummy_hash: Option<HashedPassword>,
+    passwords: PasswordManager,
+    // turns to hash passwords in, shared by every request
+    hash_limiter: Arc<HashLimiter>,
+    // the queue to on_auth_failure, if it is set
+    failure_reporter: Option<FailureReporter>,
 }
 
 #[derive(Clone)]
 pub(crate) struct AuthInternal {
-    config: AuthConfig,
+    core: AuthCore,
+    // ids of single-use (magic link and password reset) tokens that have already been used, until they expire
+    used_tokens: ExpiringMap<()>,
+    // ids of tokens revoked at logout or replaced by refreshing them, until they can no longer be refreshed
+    revoked_tokens: ExpiringMap<()>,
+    // ids and expiry times of the tokens of each user's active sessions, oldest first, if sessions are limited
+    sessions: HashMap<String, VecDeque<(String, u64)>>,
+    // the user each of those sessions belongs to, by token id, until its token expires
+    session_owners: ExpiringMap<String>,
+    // the time (in whole seconds) up to and including which tokens issued to each user are no longer accepted, set
+    // when their password changes, until every token issued by then can no longer be refreshed
+    not_before: ExpiringMap<u64>,
+    // the time (in whole seconds) up to and including which no token is accepted, whoever it was issued to
+    global_not_before: Option<u64>,
+    // the subject each pseudonym issued in place of a token's subject stands for, until the token can no longer be
+    // refreshed
+    pseudonyms: ExpiringMap<String>,
+    // the device each device-bound token was issued to, by token id, until the token can no longer be refreshed
+    device_bindings: ExpiringMap<String>,
+    // the users reported to the last-seen recorder, until the last-seen interval has passed since
+    last_seen: ExpiringMap<(), Instant>,
+}
+
+impl Deref for AuthInternal {
+    type Target = AuthCore;
+
+    fn deref(&self) -> &AuthCore {
+        &self.core
+    }
 }
 
 impl AuthInternal {
-    pub fn hash(&self, password: &str) -> String {
-        argon2::hash_encoded(
-            password.as_bytes(),
-            self.config.password_salt.as_bytes(),
-            &Default::default(),
-        )
-        .unwrap()
+    pub fn core(&self) -> AuthCore {
+        self.core.clone()
     }
+}
 
-    pub fn verify_hash(&self, password: &str, hash: &HashedPassword) -> bool {
-        argon2::verify_encoded(&hash.0, password.as_bytes()).unwrap()
+impl AuthCore {
+    pub fn config(&self) -> &AuthConfig {
+        &self.config
     }
 
-    pub fn generate_token(&self, userid: &UserID) -> Result<String, AuthError> {
-        let exp = SystemTime::now() + self.config.auth_token_lifetime;
+    pub fn passwords(&self) -> &PasswordManager {
+        &self.passwords
+    }
 
-        let claims = Claims {
-            exp: exp.duration_since(UNIX_EPOCH).unwrap().as_secs(),
-            iss: self.config.auth_token_issuer.clone(),
-            sub: userid.0.clone(),
-        };
+    // Hash a password once it is this request's turn to, so that a flood of requests queues (or is shed) here. The
+    // turn is only held while hashing, not for the rest of the request
+    pub async fn hash_password(&self, password: &str) -> Result<HashedPassword, AuthError> {
+        let _permit = self.hash_limiter.acquire().await?;
+        self.passwords.hash_blocking(password).await
+    }
 
-        let token = encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(self.config.auth_token_secret.as_ref()),
-        )?;
+    // As hash_password, but to check a password against a hash
+    async fn verify_password(
+        &self,
+        password: &str,
+        hash: &HashedPassword,
+    ) -> Result<bool, AuthError> {
+        let _permit = self.hash_limiter.acquire().await?;
+        Ok(self.passwords.verify_blocking(password, hash).await)
+    }
 
-        Ok(token)
+    // Rehash the password with the current version if it was hashed with any other. Failing to is not fatal,
+    // as the old hash still verifies
+    async fn upgrade_hash(&self, user_id: &UserID, password: &str, hash: &HashedPassword) {
+        if !self.passwords.needs_rehash(hash) {
+            return;
+        }
+
+        let upgraded = match self.hash_password(password).await {
+            Ok(upgraded) => upgraded,
+            Err(error) => {
+                tracing::warn!(
+                    "unable to upgrade the password hash of user {}: {error}",
+                    user_id.0
+                );
+                return;
+            }
+        };
+
+        if let Err(error) = self.update_password(user_id, &upgraded).await {
+            tracing::warn!(
+                "unable to upgrade the password hash of user {}: {error}",
+                user_id.0
+            );
+        }
     }
 
-    pub fn verify_token(&self, token: &str) -> Result<Claims, AuthError> {
-        let mut validation = Validation::default();
-        validation.set_issuer(&[&self.config.auth_token_issuer]);
+    // Check the user's password, refusing to if the username is locked out after too many failed attempts
+    pub async fn check_credentials(
+        &self,
+        tenant: Option<&Tenant>,
+        username: &Username,
+        password: &str,
+    ) -> Result<UserID, AuthError> {
+        let Some(lockout) = self.config.lockout else {
+            return self.verify_credentials(tenant, username, password).await;
+        };
 
-        let token = decode::<Claims>(
-            token,
-            &DecodingKey::from_secret(self.config.auth_token_secret.as_ref()),
-            &validation,
-        )?;
+        // count attempts against unknown usernames too, so that lockouts don't reveal which usernames exist
+        let key = match tenant {
+            Some(tenant) => format!("login:{}:{}", tenant.0, username.0),
+            None => format!("login:{}", username.0),
+        };
+        let store = &self.config.throttle_store;
+
+        if self.with_db_timeout(store.attempts(&key)).await? >= lockout.max_failed_attempts {
+            return Err(AuthError::AccountLocked);
+        }
+
+        let result = self.verify_credentials(tenant, username, password).await;
+        match &result {
+            Ok(_) => self.with_db_timeout(store.reset(&key)).await?,
+            Err(AuthError::LoginFailed) => {
+                self.with_db_timeout(store.record_attempt(&key, lockout.lockout_duration))
+                    .await?;
+            }
+            Err(_) => {}
+        }
 
-        Ok(token.claims)
+        result
     }
 
-    pub async fn create_user_if_not_exists(
+    // Verify the user's password, verifying against a dummy hash if the user doesn't exist,
+    // so that the time taken doesn't reveal whether the username exists
+    async fn verify_credentials(
         &self,
-        user_id: &UserID,
+        tenant: Option<&Tenant>,
         username: &Username,
-        hashed_password: &HashedPassword,
+        password: &str,
     ) -> Result<UserID, AuthError> {
-        let user_id = self
-            .config
-            .database_connection
-            .lock()
-            .await
-            .create_user_if_not_exists(user_id, username, hashed_password)
-            .await?;
+        let (user_id, hashed_password) = match self.retreive_user(tenant, username).await {
+            Ok(user) => user,
+            Err(AuthError::DatabaseTimeout) => return Err(AuthError::DatabaseTimeout),
+            Err(_) => {
+                if let Some(dummy_hash) = &self.dummy_hash {
+                    self.verify_password(password, dummy_hash).await?;
+                }
+
+                return Err(AuthError::LoginFailed);
+            }
+        };
+
+        if !self.verify_password(password, &hashed_password).await? {
+            return Err(AuthError::LoginFailed);
+        }
+
+        self.upgrade_hash(&user_id, password, &hashed_password)
+            .await;
 
         Ok(user_id)
     }
 
-    pub async fn retreive_user(
+    // Reject a new password that matches the current password (if known) or any of the recent ones in the user's history
+    pub async fn check_password_reuse(
         &self,
-        username: &Username,
-    ) -> Result<(UserID, HashedPassword), AuthError> {
-        let (user_id, hashed_password) = self
-            .config
-            .database_connection
-            .lock()
-            .await
-            .retreive_user(username)
-            .await?;
+        user_id: &UserID,
+        current_password: Option<&str>,
+        new_password: &str,
+    ) -> Result<(), AuthError> {
+        let recent = match self.config.password_history_length {
+            0 => vec![],
+            length => {
+                let mut history = self.password_history(user_id).await?;
+                history.truncate(length);
+                history
+            }
+        };
+
+        let mut reused = current_password == Some(new_password);
+        for hashed_password in &recent {
+            reused = reused || self.verify_password(new_password, hashed_password).await?;
+        }
 
-        Ok((user_id, hashed_password))
+        if reused {
+            return Err(AuthError::WeakPassword {
+                reasons: vec!["reused".into()],
+            });
+        }
+
+        Ok(())
     }
-}
 
-#[derive(Clone)]
-pub struct Auth {
-    pub(crate) internal: Arc<Mutex<AuthInternal>>,
-}
+    // Wait out the remainder of the configured minimum login duration
+    pub async fn pad_login_duration(&self, started: Instant) {
+        let mut padded = self.config.min_login_duration.unwrap_or_default();
+
+        if let Some(jitter) = self.config.login_duration_jitter {
+            let jitter_nanos = jitter.as_nanos().max(1);
+            padded += Duration::from_nanos((Uuid::new_v4().as_u128() % jitter_nanos) as u64);
+        }
+
+        tokio::time::sleep_until((started + padded).into()).await;
+    }
+
+    // Report a request refused for its token to on_auth_failure, if it is set and the error is a verification failure
+    // rather than, say, an outage
+    pub fn report_auth_failure(&self, error: &AuthError, ip: Option<IpAddr>) {
+        if let (Some(reporter), Some(kind)) = (&self.failure_reporter, AuthFailureKind::of(error)) {
+            reporter.report(kind, ip);
+        }
+    }
+
+    // Refuse requests from a blocked user or client address, if either is known
+    pub async fn check_not_blocked(
+        &self,
+        user_id: Option<&UserID>,
+        ip: Option<IpAddr>,
+    ) -> Result<(), AuthError> {
+        let Some(block_list) = &self.config.block_list else {
+            return Ok(());
+        };
+
+        if let Some(ip) = ip {
+            if self.with_db_timeout(block_list.is_ip_blocked(ip)).await? {
+                return Err(AuthError::Blocked);
+            }
+        }
+
+        if let Some(user_id) = user_id {
+            if self
+                .with_db_timeout(block_list.is_user_blocked(user_id))
+                .await?
+            {
+                return Err(AuthError::Blocked);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Read the login identifier from the configured field of a request, normalizing it if configured
+    pub fn login_identifier(
+        &self,
+        username: String,
+        other_fields: &Map<String, Value>,
+    ) -> Result<Username, AuthError> {
+        let login_identifier = &self.config.login_identifier;
+
+        let identifier = match login_identifier.field.as_str() {
+            "username" => Some(username),
+            field => other_fields
+                .get(field)
+                .and_then(Value::as_str)
+                .map(str::to_owned),
+        }
+        .filter(|identifier| !identifier.is_empty())
+        .ok_or(AuthError::MissingIdentifier)?;
+
+        Ok(self.normalize_identifier(identifier))
+    }
+
+    // Normalize an identifier as configured, so that identifiers that differ only as allowed are the same user
+    fn normalize_identifier(&self, identifier: String) -> Username {
+        let login_identifier = &self.config.login_identifier;
+
+        let identifier = if login_identifier.case_insensitive {
+            identifier.trim().to_owned()
+        } else {
+            identifier
+        };
+
+        #[cfg(feature = "unicode-normalization")]
+        if self.config.unicode_normalized_identifiers {
+            return Username(unicode_folding::fold_identifier(
+                &identifier,
+                login_identifier.case_insensitive,
+            ));
+        }
+
+        if login_identifier.case_insensitive {
+            Username(identifier.to_lowercase())
+        } else {
+            Username(identifier)
+        }
+    }
+
+    // Refuse to register any of the reserved usernames, however they are written
+    pub fn check_not_reserved(&self, username: &Username) -> Result<(), AuthError> {
+        let reserved = self
+            .config
+            .reserved_usernames
+            .iter()
+            .any(|reserved| self.normalize_identifier(reserved.clone()).0 == username.0);
+
+        if reserved {
+            return Err(AuthError::InvalidUsername {
+                reasons: vec!["reserved".into()],
+            });
+        }
+
+        Ok(())
+    }
+
+    // Resolve the tenant a request applies to, which is required only if usernames are tenant-scoped
+    pub fn tenant(&self, requested: Option<String>) -> Result<Option<Tenant>, AuthError> {
+        if !self.config.tenant_scoped_usernames {
+            return Ok(None);
+        }
+
+        requested
+            .map(|tenant| Some(Tenant(tenant)))
+            .ok_or(AuthError::TenantRequired)
+    }
+
+    // The audience a client asked for a token to be issued for, if any, provided it is allowed
+    pub fn login_audience(&self, requested: Option<String>) -> Result<Option<String>, AuthError> {
+        match requested {
+            Some(audience) if !self.config.login_audiences.contains(&audience) => {
+                Err(AuthError::AudienceNotAllowed { audience })
+            }
+            requested => Ok(requested),
+        }
+    }
+}
+
+impl AuthCore {
+    // Issue a session token for the user, with their current custom claims and token lifetime, recording the
+    // methods they authenticated with. The user is read from the database, and the token stored, without holding the
+    // lock, which is only taken to record the token
+    pub async fn issue_session_token(
+        &self,
+        auth: &Mutex<AuthInternal>,
+        userid: &UserID,
+        username: Option<&Username>,
+        tenant: Option<&Tenant>,
+        amr: &[String],
+        request: SessionRequest<'_>,
+    ) -> Result<IssuedToken, AuthError> {
+        let custom_claims = self.custom_claims(userid).await?;
+        let created_at = self.account_created_at(userid).await?;
+        let lifetime = self
+            .token_lifetime(userid)
+            .await?
+            .unwrap_or(self.config.auth_token_lifetime);
+
+        let (token, claims) = {
+            let mut auth = auth.lock().await;
+
+            let mut claims = auth.new_claims(userid, username, lifetime);
+            claims.tenant = tenant.map(|tenant| tenant.0.clone());
+            claims.created_at = created_at.map(|created_at| {
+                created_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs()
+            });
+            claims.scope = join_scopes(&self.config.default_scopes);
+            if !amr.is_empty() {
+                claims.amr = Some(amr.to_vec());
+                claims.acr = Some(acr_level(amr).to_string());
+            }
+            claims.extra.extend(
+                custom_claims
+                    .into_iter()
+                    .filter(|(name, _)| !Claims::is_registered(name)),
+            );
+            if let Some(audience) = request.audience {
+                claims.extra.insert("aud".into(), audience.into());
+            }
+            auth.apply_token_profile(&mut claims);
+            if self.config.csrf_cookie.is_some() && self.config.token_delivery.cookie().is_some() {
+                claims.csrf = Some(Uuid::new_v4().to_string());
+            }
+            claims.cnf = request.cert_thumbprint.map(|thumbprint| Confirmation {
+                x5t_s256: thumbprint.into(),
+            });
+            if let Some(deadline) = request.deadline {
+                claims.exp = claims.exp.min(deadline);
+                claims.deadline = Some(deadline);
+            }
+
+            auth.check_room_for_token(request.device_id.is_some())?;
+            auth.start_session(userid, &claims)?;
+            if let Some(device_id) = request.device_id {
+                auth.bind_to_device(&claims, device_id);
+            }
+            auth.pseudonymize(&mut claims);
+
+            (auth.encode_token(&claims)?, claims)
+        };
+
+        Ok(IssuedToken {
+            token: self.store_reference(token, claims.exp).await?,
+            expires_at: claims.exp,
+            csrf_token: claims.csrf,
+        })
+    }
+
+    // Issue a session token for the user that expires at the deadline rather than after a lifetime, though no later
+    // than the maximum token lifetime allows
+    pub async fn generate_token_until(
+        &self,
+        auth: &Mutex<AuthInternal>,
+        userid: &UserID,
+        deadline: SystemTime,
+    ) -> Result<String, AuthError> {
+        let lifetime = deadline
+            .duration_since(SystemTime::now())
+            .map_err(|_| AuthError::ExpiryInPast)?;
+        let custom_claims = self.custom_claims(userid).await?;
+
+        let (token, exp) = {
+            let mut auth = auth.lock().await;
+
+            let mut claims = auth.new_claims(userid, None, lifetime);
+            // exp would otherwise be a second late whenever the clock ticks over between here and new_claims
+            claims.exp = claims
+                .exp
+                .min(deadline.duration_since(UNIX_EPOCH).unwrap().as_secs());
+            claims.deadline = Some(claims.exp);
+            claims.scope = join_scopes(&self.config.default_scopes);
+            claims.extra.extend(
+                custom_claims
+                    .into_iter()
+                    .filter(|(name, _)| !Claims::is_registered(name)),
+            );
+            auth.apply_token_profile(&mut claims);
+            auth.check_room_for_token(false)?;
+            auth.pseudonymize(&mut claims);
+
+            (auth.encode_token(&claims)?, claims.exp)
+        };
+
+        self.store_reference(token, exp).await
+    }
+
+    pub async fn exchange_token(
+        &self,
+        auth: &Mutex<AuthInternal>,
+        parent_token: &str,
+        actor: &str,
+        scopes: &[&str],
+        lifetime: Duration,
+    ) -> Result<String, AuthError> {
+        let parent_token = self.resolve_reference(parent_token).await?;
+
+        let (token, exp) = {
+            let mut auth = auth.lock().await;
+            let parent = auth.verify_token(&parent_token)?;
+
+            let granted = parent.scopes().collect::<Vec<_>>();
+            if !scopes.iter().all(|scope| granted.contains(scope)) {
+                return Err(AuthError::InsufficientPermissions);
+            }
+
+            let mut claims = auth.new_claims(&UserID(parent.sub), None, lifetime);
+            // the delegated token may never outlive its parent
+            claims.exp = claims.exp.min(parent.exp);
+            claims.scope = join_scopes(scopes);
+            claims.act = Some(Actor { sub: actor.into() });
+            claims.tenant = parent.tenant;
+            claims.created_at = parent.created_at;
+            claims.preferred_username = parent.preferred_username;
+            auth.apply_token_profile(&mut claims);
+            auth.check_room_for_token(false)?;
+            auth.pseudonymize(&mut claims);
+
+            (auth.encode_token(&claims)?, claims.exp)
+        };
+
+        self.store_reference(token, exp).await
+    }
+
+    // Store an issued session token, returning an opaque reference to it in its place, if tokens are stored. The
+    // token is kept for as long as it could be refreshed
+    async fn store_reference(&self, token: String, exp: u64) -> Result<String, AuthError> {
+        let Some(token_store) = &self.config.token_store else {
+            return Ok(token);
+        };
+
+        let reference = Uuid::new_v4().simple().to_string();
+        let kept_for = TOKEN_LEEWAY_SECS + self.config.refresh_grace_period.as_secs();
+        let expires_at = UNIX_EPOCH + Duration::from_secs(exp + kept_for);
+
+        self.with_db_timeout(token_store.insert(&reference, &token, expires_at))
+            .await?;
+
+        Ok(reference)
+    }
+
+    // Look up the session token a client presented a reference to, if tokens are stored. References that aren't in
+    // the store, such as those that have been removed to revoke them, are rejected
+    pub async fn resolve_reference(&self, token: &str) -> Result<String, AuthError> {
+        let Some(token_store) = &self.config.token_store else {
+            return Ok(token.to_owned());
+        };
+
+        self.with_db_timeout(token_store.get(token))
+            .await?
+            .ok_or(AuthError::TokenError { source: None })
+    }
+}
+
+impl AuthInternal {
+    pub fn generate_magic_token(
+        &self,
+        userid: &UserID,
+        username: &Username,
+        tenant: Option<&Tenant>,
+    ) -> Result<String, AuthError> {
+        self.generate_single_use_token(
+            userid,
+            username,
+            tenant,
+            MAGIC_TOKEN_TYPE,
+            self.config.magic_link_lifetime,
+        )
+    }
+
+    pub fn consume_magic_token(&mut self, token: &str) -> Result<SingleUseSubject, AuthError> {
+        self.consume_single_use_token(token, MAGIC_TOKEN_TYPE)
+    }
+
+    pub fn generate_reset_token(
+        &self,
+        userid: &UserID,
+        username: &Username,
+        tenant: Option<&Tenant>,
+    ) -> Result<String, AuthError> {
+        self.generate_single_use_token(
+            userid,
+            username,
+            tenant,
+            RESET_TOKEN_TYPE,
+            self.config.password_reset_lifetime,
+        )
+    }
+
+    // Verify a password reset token without using it up, which is left until the new password is stored
+    pub fn check_reset_token(&self, token: &str) -> Result<SingleUseSubject, AuthError> {
+        self.check_single_use_token(token, RESET_TOKEN_TYPE)
+    }
+
+    pub fn generate_verification_token(
+        &self,
+        userid: &UserID,
+        username: &Username,
+        tenant: Option<&Tenant>,
+    ) -> Result<String, AuthError> {
+        self.generate_single_use_token(
+            userid,
+            username,
+            tenant,
+            VERIFICATION_TOKEN_TYPE,
+            self.config.verification_lifetime,
+        )
+    }
+
+    // Verify an email verification token without using it up, which is left until the user is marked verified
+    pub fn check_verification_token(&self, token: &str) -> Result<SingleUseSubject, AuthError> {
+        self.check_single_use_token(token, VERIFICATION_TOKEN_TYPE)
+    }
+}
+
+impl AuthCore {
+    // Refuse users who haven't yet verified their email, if verification is required
+    pub async fn check_verified(&self, user_id: &UserID) -> Result<(), AuthError> {
+        if self.config.require_verification && !self.is_verified(user_id).await? {
+            return Err(AuthError::VerificationRequired);
+        }
+
+        Ok(())
+    }
+
+    // Count a registration from the client address, refusing it once the address has made too many, and check the
+    // registration challenge, if any. Registrations from unknown addresses are only challenged
+    pub async fn check_registration_allowed(
+        &self,
+        ip: Option<IpAddr>,
+        challenge_response: Option<&str>,
+    ) -> Result<(), AuthError> {
+        if let (Some(limit), Some(ip)) = (self.config.registration_limit, ip) {
+            let key = format!("register:{ip}");
+            let registrations = self
+                .with_db_timeout(
+                    self.config
+                        .throttle_store
+                        .record_attempt(&key, limit.window),
+                )
+                .await?;
+
+            if registrations > limit.max_registrations {
+                return Err(AuthError::RegistrationThrottled);
+            }
+        }
+
+        if let Some(challenge) = &self.config.registration_challenge {
+            if !self
+                .with_db_timeout(challenge.verify(challenge_response, ip))
+                .await?
+            {
+                return Err(AuthError::ChallengeFailed);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Count a request to resend a verification token, refusing it once the identifier has had too many. Requests for
+    // unknown identifiers are counted too, so that the limit doesn't reveal which exist
+    pub async fn check_resend_limit(
+        &self,
+        tenant: Option<&Tenant>,
+        username: &Username,
+    ) -> Result<(), AuthError> {
+        let limit = self.config.verification_resend_limit;
+        let key = match tenant {
+            Some(tenant) => format!("verify-resend:{}:{}", tenant.0, username.0),
+            None => format!("verify-resend:{}", username.0),
+        };
+
+        let resends = self
+            .with_db_timeout(
+                self.config
+                    .throttle_store
+                    .record_attempt(&key, limit.window),
+            )
+            .await?;
+
+        if resends > limit.max_resends {
+            return Err(AuthError::TooManyRequests);
+        }
+
+        Ok(())
+    }
+}
+
+impl AuthInternal {
+    fn generate_single_use_token(
+        &self,
+        userid: &UserID,
+        username: &Username,
+        tenant: Option<&Tenant>,
+        typ: &str,
+        lifetime: Duration,
+    ) -> Result<String, AuthError> {
+        let mut claims = self.new_claims(userid, Some(username), lifetime);
+        claims.tenant = tenant.map(|tenant| tenant.0.clone());
+        claims.typ = Some(typ.into());
+
+        self.encode_token(&claims)
+    }
+
+    // Verify a single-use token of the given type, and mark it as used so that it can't be used again
+    fn consume_single_use_token(
+        &mut self,
+        token: &str,
+        typ: &str,
+    ) -> Result<SingleUseSubject, AuthError> {
+        let subject = self.check_single_use_token(token, typ)?;
+        self.mark_token_used(&subject)?;
+
+        Ok(subject)
+    }
+
+    // Verify a single-use token of the given type that hasn't been used yet, without marking it as used
+    fn check_single_use_token(
+        &self,
+        token: &str,
+        typ: &str,
+    ) -> Result<SingleUseSubject, AuthError> {
+        let claims = self.verify_token_of_type(token, Some(typ))?;
+        let jti = claims.jti.ok_or(AuthError::TokenError { source: None })?;
+
+        if self.used_tokens.contains_key(&jti) {
+            return Err(AuthError::TokenError { source: None });
+        }
+
+        Ok(SingleUseSubject {
+            user_id: UserID(claims.sub),
+            username: claims.preferred_username.map(Username),
+            tenant: claims.tenant.map(Tenant),
+            jti,
+            exp: claims.exp,
+        })
+    }
+
+    // Mark a checked single-use token as used, refusing it if another request has used it in the meantime, or if there
+    // is no room left to remember that it was used
+    pub fn mark_token_used(&mut self, subject: &SingleUseSubject) -> Result<(), AuthError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        self.used_tokens.forget_expired(now);
+
+        if self.used_tokens.contains_key(&subject.jti) {
+            return Err(AuthError::TokenError { source: None });
+        }
+        if !self.used_tokens.has_room_for(&subject.jti) {
+            return Err(AuthError::StoreFull);
+        }
+        self.used_tokens
+            .insert(subject.jti.clone(), (), subject.exp + TOKEN_LEEWAY_SECS);
+
+        Ok(())
+    }
+
+    // Let a single-use token be used again, because what it was used for failed
+    pub fn unmark_token_used(&mut self, subject: &SingleUseSubject) {
+        self.used_tokens.remove(&subject.jti);
+    }
+
+    // Revoke a session token, so that it is no longer accepted even before it expires, ending its session. The
+    // revocation is kept for as long as the token could be refreshed, even beyond the store's capacity.
+    // Tokens without an id (such as some issued by external providers) can't be revoked
+    pub fn revoke_token(&mut self, claims: &Claims) {
+        let Some(jti) = &claims.jti else {
+            return;
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        // expired tokens may still be refreshed within the grace period
+        let kept_for = TOKEN_LEEWAY_SECS + self.config.refresh_grace_period.as_secs();
+        self.revoked_tokens.forget_expired(now);
+        self.revoked_tokens
+            .insert(jti.clone(), (), claims.exp + kept_for);
+
+        self.forget_session(jti);
+    }
+
+    // Refuse to issue another token while any store it would be recorded in is full, as would be the store of
+    // revocations it may come to need. Entries are never evicted to make room, as that would accept tokens that
+    // should be refused
+    fn check_room_for_token(&mut self, binds_device: bool) -> Result<(), AuthError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        self.revoked_tokens.forget_expired(now);
+        self.forget_expired_sessions(now);
+        self.pseudonyms.forget_expired(now);
+        self.device_bindings.forget_expired(now);
+
+        let full = self.revoked_tokens.is_full()
+            || (self.config.max_sessions.is_some() && self.session_owners.is_full())
+            || (self.config.pseudonymous_subjects && self.pseudonyms.is_full())
+            || (binds_device && self.device_bindings.is_full());
+        if full {
+            return Err(AuthError::StoreFull);
+        }
+
+        Ok(())
+    }
+
+    // Record a new session for the user, making room for it within the session limit if necessary. There must be
+    // room in the session store (see check_room_for_token)
+    fn start_session(&mut self, userid: &UserID, claims: &Claims) -> Result<(), AuthError> {
+        let Some(max_sessions) = self.config.max_sessions else {
+            return Ok(());
+        };
+        let jti = claims
+            .jti
+            .clone()
+            .ok_or(AuthError::TokenError { source: None })?;
+
+        // expired tokens may still be refreshed within the grace period
+        let kept_for = TOKEN_LEEWAY_SECS + self.config.refresh_grace_period.as_secs();
+
+        let sessions = self.sessions.entry(userid.0.clone()).or_default();
+        while sessions.len() >= max_sessions {
+            match self.core.config.session_limit_policy {
+                SessionLimitPolicy::RejectNew => return Err(AuthError::TooManySessions),
+                SessionLimitPolicy::EvictOldest => {
+                    if let Some((jti, exp)) = sessions.pop_front() {
+                        self.session_owners.remove(&jti);
+                        self.revoked_tokens.insert(jti, (), exp + kept_for);
+                    }
+                }
+            }
+        }
+
+        self.session_owners.insert(
+            jti.clone(),
+            userid.0.clone(),
+            claims.exp + TOKEN_LEEWAY_SECS,
+        );
+        sessions.push_back((jti, claims.exp));
+
+        Ok(())
+    }
+
+    // Forget the session a token belongs to, if it has one
+    fn forget_session(&mut self, jti: &str) {
+        let Some(userid) = self.session_owners.remove(jti) else {
+            return;
+        };
+
+        if let Some(sessions) = self.sessions.get_mut(&userid) {
+            sessions.retain(|(session, _)| session != jti);
+            if sessions.is_empty() {
+                self.sessions.remove(&userid);
+            }
+        }
+    }
+
+    // Forget the sessions whose tokens have expired, without looking at the rest
+    fn forget_expired_sessions(&mut self, now: u64) {
+        for (jti, userid) in self.session_owners.forget_expired(now) {
+            if let Some(sessions) = self.sessions.get_mut(&userid) {
+                sessions.retain(|(session, _)| *session != jti);
+                if sessions.is_empty() {
+                    self.sessions.remove(&userid);
+                }
+            }
+        }
+    }
+
+    // The user's unexpired, unrevoked sessions, oldest first
+    pub fn sessions(&self, userid: &UserID) -> Vec<Session> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        self.sessions
+            .get(&userid.0)
+            .into_iter()
+            .flatten()
+            .filter(|(_, exp)| *exp + TOKEN_LEEWAY_SECS >= now)
+            .map(|(jti, exp)| Session {
+                id: jti.clone(),
+                expires_at: UNIX_EPOCH + Duration::from_secs(*exp),
+            })
+            .collect()
+    }
+
+    // Invalidate every token issued to the user so far, for instance because their password changed. Tokens are only
+    // stamped with the second they were issued in, so every token issued within the current second is invalidated,
+    // and tokens issued from now on are stamped with the next (see new_claims). The invalidation is kept until those
+    // tokens can no longer be refreshed, even beyond the store's capacity
+    pub fn invalidate_tokens(&mut self, userid: &UserID) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        // tokens issued before now expire within the longest lifetime, and may still be refreshed within the grace
+        // period after that
+        let longest_lifetime = self
+            .core
+            .config
+            .max_token_lifetime
+            .unwrap_or(self.core.config.auth_token_lifetime);
+        let forget_after = now
+            + longest_lifetime.as_secs()
+            + TOKEN_LEEWAY_SECS
+            + self.core.config.refresh_grace_period.as_secs();
+
+        self.not_before.forget_expired(now);
+        self.not_before.insert(userid.0.clone(), now, forget_after);
+
+        for (jti, _) in self.sessions.remove(&userid.0).unwrap_or_default() {
+            self.session_owners.remove(&jti);
+        }
+    }
+
+    // Invalidate every token issued before the cutoff, to whichever user, along with any issued within the cutoff's
+    // second. A cutoff in the future is brought forward to now
+    pub fn revoke_tokens_issued_before(&mut self, cutoff: SystemTime) {
+        let now = SystemTime::now();
+        let cutoff = cutoff
+            .min(now)
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        self.global_not_before = Some(cutoff);
+        self.sessions.clear();
+        self.session_owners.clear();
+    }
+
+    // Forget every stored token id, session, pseudonym and device binding that has expired, rather than waiting for
+    // the next of its kind to be stored, along with invalidations that no unexpired token predates and last-seen
+    // reports that no longer hold back the next one
+    pub fn purge_expired(&mut self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        self.used_tokens.forget_expired(now);
+        self.revoked_tokens.forget_expired(now);
+        self.forget_expired_sessions(now);
+        self.pseudonyms.forget_expired(now);
+        self.device_bindings.forget_expired(now);
+        self.not_before.forget_expired(now);
+        self.last_seen.forget_expired(Instant::now());
+    }
+
+    pub fn store_sizes(&self) -> StoreSizes {
+        StoreSizes {
+            revoked_tokens: self.revoked_tokens.len(),
+            used_tokens: self.used_tokens.len(),
+            sessions: self.session_owners.len(),
+            pseudonyms: self.pseudonyms.len(),
+            device_bindings: self.device_bindings.len(),
+            not_before: self.not_before.len(),
+            last_seen: self.last_seen.len(),
+            throttle_keys: self.config.throttle_store.entries(),
+            stored_tokens: self
+                .config
+                .token_store
+                .as_ref()
+                .and_then(|token_store| token_store.entries()),
+        }
+    }
+
+    // Note that the user was seen, returning the time to report to the last-seen recorder, unless there is none or
+    // they have already been reported within the last-seen interval. The caller reports it once the lock is released,
+    // so that a slow recorder holds up no other request
+    pub fn note_last_seen(&mut self, userid: &UserID) -> Option<SystemTime> {
+        self.core.config.last_seen_recorder.as_ref()?;
+
+        let now = Instant::now();
+        self.last_seen.forget_expired(now);
+        if self.last_seen.contains_key(&userid.0) {
+            return None;
+        }
+
+        // a user there is no room to remember is simply reported again next time
+        if self.last_seen.has_room_for(&userid.0) {
+            self.last_seen.insert(
+                userid.0.clone(),
+                (),
+                now + self.core.config.last_seen_interval,
+            );
+        }
+
+        Some(SystemTime::now())
+    }
+
+    fn check_not_revoked(&self, claims: &Claims) -> Result<(), AuthError> {
+        match &claims.jti {
+            Some(jti) if self.revoked_tokens.contains_key(jti) => {
+                Err(AuthError::TokenError { source: None })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Replace the subject of a token with a fresh pseudonym, if configured. There must be room in the pseudonym store
+    // (see check_room_for_token)
+    fn pseudonymize(&mut self, claims: &mut Claims) {
+        if !self.config.pseudonymous_subjects {
+            return;
+        }
+
+        // expired tokens may still be refreshed within the grace period
+        let kept_for = TOKEN_LEEWAY_SECS + self.config.refresh_grace_period.as_secs();
+
+        let pseudonym = Uuid::new_v4().to_string();
+        let sub = std::mem::replace(&mut claims.sub, pseudonym.clone());
+        self.pseudonyms
+            .insert(pseudonym, sub, claims.exp + kept_for);
+    }
+
+    // Remember the device a session token was issued to, so that only that device may refresh it. There must be room
+    // in the device binding store (see check_room_for_token)
+    fn bind_to_device(&mut self, claims: &Claims, device_id: &str) {
+        let Some(jti) = &claims.jti else {
+            return;
+        };
+
+        // expired tokens may still be refreshed within the grace period
+        let kept_for = TOKEN_LEEWAY_SECS + self.config.refresh_grace_period.as_secs();
+        self.device_bindings
+            .insert(jti.clone(), device_id.into(), claims.exp + kept_for);
+    }
+
+    // Reject a device-bound token presented from any other device (or none)
+    pub fn check_device_binding(
+        &self,
+        claims: &Claims,
+        device_id: Option<&str>,
+    ) -> Result<(), AuthError> {
+        let bound_to = claims
+            .jti
+            .as_ref()
+            .and_then(|jti| self.device_bindings.get(jti));
+
+        match bound_to {
+            Some(bound_to) if Some(bound_to.as_str()) != device_id => {
+                Err(AuthError::TokenError { source: None })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Reject a certificate-bound token presented with any other client certificate (or none)
+    pub fn check_cert_binding(
+        &self,
+        claims: &Claims,
+        cert_thumbprint: Option<&str>,
+    ) -> Result<(), AuthError> {
+        match &claims.cnf {
+            Some(cnf) if Some(cnf.x5t_s256.as_str()) != cert_thumbprint => {
+                Err(AuthError::TokenError { source: None })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Reject tokens issued before the user's tokens (or everyone's) were last invalidated
+    fn check_not_invalidated(&self, claims: &Claims) -> Result<(), AuthError> {
+        let not_before = self
+            .not_before
+            .get(&claims.sub)
+            .max(self.global_not_before.as_ref());
+
+        match (not_before, claims.iat) {
+            (Some(not_before), Some(iat)) if iat > *not_before => Ok(()),
+            (Some(_), _) => Err(AuthError::TokenError { source: None }),
+            (None, _) => Ok(()),
+        }
+    }
+
+    fn new_claims(
+        &self,
+        userid: &UserID,
+        username: Option<&Username>,
+        lifetime: Duration,
+    ) -> Claims {
+        let now = SystemTime::now();
+        let lifetime = match self.config.max_token_lifetime {
+            Some(max_lifetime) => lifetime.min(max_lifetime),
+            None => lifetime,
+        };
+        let exp = now + lifetime;
+
+        // a token issued within the second the user's tokens were invalidated would be invalidated along with them,
+        // so it is stamped with the next second instead
+        let not_before = self
+            .not_before
+            .get(&userid.0)
+            .max(self.global_not_before.as_ref());
+        let now = now.duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let iat = match not_before {
+            Some(not_before) => now.max(not_before + 1),
+            None => now,
+        };
+
+        let mut extra = Map::new();
+        if let Some(audience) = &self.config.auth_token_audience {
+            extra.insert("aud".into(), audience.clone().into());
+        }
+
+        Claims {
+            exp: exp.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            iss: self.config.auth_token_issuer.clone(),
+            sub: format!(
+                "{}{}",
+                self.config.sub_prefix.as_deref().unwrap_or_default(),
+                userid.0
+            ),
+            iat: Some(iat),
+            scope: None,
+            act: None,
+            typ: None,
+            jti: Some(Uuid::new_v4().to_string()),
+            tenant: None,
+            created_at: None,
+            preferred_username: username
+                .filter(|_| self.config.username_claim)
+                .map(|username| username.0.clone()),
+            client_id: None,
+            amr: None,
+            acr: None,
+            csrf: None,
+            cnf: None,
+            deadline: None,
+            extra,
+        }
+    }
+
+    // Add the claims required by the token profile to a session token
+    fn apply_token_profile(&self, claims: &mut Claims) {
+        if let TokenProfile::AccessToken { client_id } = &self.config.token_profile {
+            claims.client_id = Some(client_id.clone());
+            claims.scope.get_or_insert_with(String::new);
+            claims
+                .extra
+                .entry("aud")
+                .or_insert_with(|| self.config.auth_token_issuer.clone().into());
+        }
+    }
+
+    fn encode_token(&self, claims: &Claims) -> Result<String, AuthError> {
+        // single-use tokens carry a typ claim, and aren't access tokens
+        let typ = match &self.config.token_profile {
+            TokenProfile::AccessToken { .. } if claims.typ.is_none() => ACCESS_TOKEN_HEADER_TYPE,
+            _ => &self.config.auth_token_header_type,
+        };
+
+        let header = Header {
+            alg: self
+                .config
+                .auth_token_algorithms
+                .first()
+                .copied()
+                .unwrap_or_default(),
+            typ: Some(typ.into()),
+            kid: self
+                .secrets
+                .signing_key_id()
+                .or_else(|| self.config.auth_token_key_id.clone()),
+            ..Default::default()
+        };
+
+        #[cfg(feature = "compression")]
+        let compressed = self.compress_claims(claims)?;
+        #[cfg(feature = "compression")]
+        let claims = compressed.as_ref().unwrap_or(claims);
+
+        let token = encode(
+            &header,
+            claims,
+            &EncodingKey::from_secret(self.secrets.signing_secret().as_ref()),
+        )?;
+
+        Ok(token)
+    }
+
+    // Compress the claims into a zip claim if they exceed the configured threshold, keeping only those needed to
+    // validate the token alongside
+    #[cfg(feature = "compression")]
+    fn compress_claims(&self, claims: &Claims) -> Result<Option<Claims>, AuthError> {
+        let Some(threshold) = self.config.token_compression_threshold else {
+            return Ok(None);
+        };
+
+        let serialized = serde_json::to_vec(claims).map_err(jsonwebtoken::errors::Error::from)?;
+        if serialized.len() <= threshold {
+            return Ok(None);
+        }
+
+        let mut extra = Map::new();
+        if let Some(audience) = claims.extra.get("aud") {
+            extra.insert("aud".into(), audience.clone());
+        }
+        extra.insert("zip".into(), compression::compress(&serialized).into());
+
+        Ok(Some(Claims {
+            exp: claims.exp,
+            iss: claims.iss.clone(),
+            sub: claims.sub.clone(),
+            iat: None,
+            scope: None,
+            act: None,
+            typ: None,
+            jti: None,
+            tenant: None,
+            created_at: None,
+            preferred_username: None,
+            client_id: None,
+            amr: None,
+            acr: None,
+            csrf: None,
+            cnf: None,
+            deadline: None,
+            extra,
+        }))
+    }
+
+    pub fn verify_token(&self, token: &str) -> Result<Claims, AuthError> {
+        self.verify_token_of_type(token, None)
+    }
+
+    // Verify a token presented to authenticate a request, given what read_presented_token made of it
+    pub fn verify_session_token(&self, presented: PresentedToken) -> Result<Claims, AuthError> {
+        match presented {
+            PresentedToken::External(claims) => {
+                self.check_not_revoked(&claims)?;
+                Ok(*claims)
+            }
+            PresentedToken::Session(token) => self.verify_token(&token),
+        }
+    }
+
+    // Verify a session token presented for refresh, which may have expired within the grace period
+    pub fn verify_refreshable_token(&self, token: &str) -> Result<Claims, AuthError> {
+        let mut validation = self.validation();
+        validation.leeway += self.config.refresh_grace_period.as_secs();
+
+        let claims = self.decode_token(token, &validation, None)?;
+
+        // delegated tokens are deliberately narrow, and must not be refreshed into a full session token
+        if claims.act.is_some() {
+            return Err(AuthError::TokenError { source: None });
+        }
+
+        // nor may a token issued until a deadline be refreshed past it, grace period or not
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        if claims.deadline.is_some_and(|deadline| deadline <= now) {
+            return Err(AuthError::TokenError { source: None });
+        }
+
+        Ok(claims)
+    }
+
+    // Verify a token, and that it was issued for the given purpose. Session tokens don't carry a type
+    fn verify_token_of_type(&self, token: &str, typ: Option<&str>) -> Result<Claims, AuthError> {
+        self.decode_token(token, &self.validation(), typ)
+    }
+
+    fn decode_token(
+        &self,
+        token: &str,
+        validation: &Validation,
+        typ: Option<&str>,
+    ) -> Result<Claims, AuthError> {
+        check_token_shape(token)?;
+
+        let mut claims = self.decode_with_any_secret(token, validation)?.claims;
+
+        #[cfg(feature = "compression")]
+        if let Some(Value::String(compressed)) = claims.extra.get("zip") {
+            claims = serde_json::from_slice(&compression::decompress(compressed)?)
+                .map_err(jsonwebtoken::errors::Error::from)?;
+        }
+
+        if claims.typ.as_deref() != typ {
+            return Err(AuthError::TokenError { source: None });
+        }
+
+        // only session tokens, which have no typ, are issued with pseudonyms
+        if self.config.pseudonymous_subjects && claims.typ.is_none() {
+            claims.sub = self
+                .pseudonyms
+                .get(&claims.sub)
+                .cloned()
+                .ok_or(AuthError::TokenError { source: None })?;
+        }
+
+        if let Some(prefix) = &self.config.sub_prefix {
+            claims.sub = claims
+                .sub
+                .strip_prefix(prefix.as_str())
+                .ok_or(AuthError::TokenError { source: None })?
+                .to_owned();
+        }
+
+        self.check_not_revoked(&claims)?;
+        self.check_not_invalidated(&claims)?;
+        self.check_not_issued_in_future(&claims)?;
+        self.check_token_validator(&claims)?;
+
+        Ok(claims)
+    }
+
+    // Try each of the accepted secrets in turn, moving on only if the signature doesn't match
+    fn decode_with_any_secret(
+        &self,
+        token: &str,
+        validation: &Validation,
+    ) -> Result<TokenData<Claims>, AuthError> {
+        let mut result = Err(AuthError::TokenError { source: None });
+
+        for secret in self.secrets.verification_secrets() {
+            match decode::<Claims>(
+                token,
+                &DecodingKey::from_secret(secret.as_ref()),
+                validation,
+            ) {
+                Err(error) if *error.kind() == ErrorKind::InvalidSignature => {
+                    result = Err(error.into());
+                }
+                result => return Ok(result?),
+            }
+        }
+
+        result
+    }
+}
+
+impl AuthCore {
+    // Make out a token presented to authenticate a request, decoding it if it was issued by an external provider,
+    // whose keys may need fetching, or otherwise resolving any reference to it from the token store. Either may take a
+    // while, so is done without holding the lock, leaving verify_session_token to finish the job
+    pub async fn read_presented_token(&self, token: &str) -> Result<PresentedToken, AuthError> {
+        match self.decode_external_token(token).await? {
+            Some(claims) => Ok(PresentedToken::External(Box::new(claims))),
+            None => Ok(PresentedToken::Session(
+                self.resolve_reference(token).await?,
+            )),
+        }
+    }
+
+    // Decode a token if it was issued by an external provider. Tokens issued here decode to None
+    async fn decode_external_token(&self, token: &str) -> Result<Option<Claims>, AuthError> {
+        if let Some(jwks) = &self.config.static_jwks {
+            check_token_shape(token)?;
+            let claims = jwks.decode::<Claims>(token, self.validation())?.claims;
+            self.check_not_issued_in_future(&claims)?;
+            self.check_token_validator(&claims)?;
+            return Ok(Some(claims));
+        }
+
+        #[cfg(feature = "oidc")]
+        if let Some(jwks) = &self.config.external_jwks {
+            check_token_shape(token)?;
+            // external providers may use the typ claim for their own purposes, so it isn't checked here
+            let claims = jwks
+                .decode::<Claims>(token, self.validation())
+                .await?
+                .claims;
+            self.check_not_issued_in_future(&claims)?;
+            self.check_token_validator(&claims)?;
+            return Ok(Some(claims));
+        }
+
+        Ok(None)
+    }
+
+    // Reject tokens claiming to be issued later than clock skew can explain, if configured
+    fn check_not_issued_in_future(&self, claims: &Claims) -> Result<(), AuthError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        match claims.iat {
+            Some(iat) if self.config.reject_future_iat && iat > now + TOKEN_LEEWAY_SECS => {
+                Err(AuthError::TokenError { source: None })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Let the app's own validator veto the token, if one is configured
+    fn check_token_validator(&self, claims: &Claims) -> Result<(), AuthError> {
+        match &self.config.token_validator {
+            Some(validator) => {
+                validator(&json!(claims)).map_err(|reason| AuthError::TokenRejected { reason })
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn validation(&self) -> Validation {
+        let mut validation = Validation::default();
+        validation.algorithms = self.config.auth_token_algorithms.clone();
+        validation.set_issuer(&[&self.config.auth_token_issuer]);
+
+        if let Some(audience) = &self.config.auth_token_audience {
+            validation.set_audience(&[audience]);
+            validation.required_spec_claims.insert("aud".into());
+        }
+
+        validation
+    }
+}
+
+impl AuthCore {
+    pub async fn create_user_if_not_exists(
+        &self,
+        tenant: Option<&Tenant>,
+        user_id: &UserID,
+        username: &Username,
+        hashed_password: &HashedPassword,
+    ) -> Result<UserID, AuthError> {
+        let hashed_password = &self.encode_password(hashed_password.clone());
+        let username = &self.stored_username(username);
+
+        let user_id = self
+            .with_db_timeout(async {
+                let db = &self.config.database_connection;
+                match tenant {
+                    Some(tenant) => {
+                        db.create_tenant_user_if_not_exists(
+                            tenant,
+                            user_id,
+                            username,
+                            hashed_password,
+                        )
+                        .await
+                    }
+                    None => {
+                        db.create_user_if_not_exists(user_id, username, hashed_password)
+                            .await
+                    }
+                }
+            })
+            .await?;
+
+        Ok(user_id)
+    }
+
+    pub async fn retreive_user(
+        &self,
+        tenant: Option<&Tenant>,
+        username: &Username,
+    ) -> Result<(UserID, HashedPassword), AuthError> {
+        let username = &self.stored_username(username);
+        let (user_id, hashed_password) = self
+            .with_db_timeout(async {
+                let db = &self.config.database_connection;
+                match tenant {
+                    Some(tenant) => db.retreive_tenant_user(tenant, username).await,
+                    None => db.retreive_user(username).await,
+                }
+            })
+            .await?;
+
+        Ok((user_id, self.decode_password(hashed_password)?))
+    }
+
+    pub async fn update_password(
+        &self,
+        user_id: &UserID,
+        hashed_password: &HashedPassword,
+    ) -> Result<(), AuthError> {
+        let hashed_password = &self.encode_password(hashed_password.clone());
+
+        self.with_db_timeout(async {
+            self.config
+                .database_connection
+                .update_password(user_id, hashed_password)
+                .await
+        })
+        .await
+    }
+
+    pub async fn password_version(&self, user_id: &UserID) -> Result<Option<String>, AuthError> {
+        self.with_db_timeout(async {
+            self.config
+                .database_connection
+                .password_version(user_id)
+                .await
+        })
+        .await
+    }
+
+    // Replace the password only if it hasn't changed since its version was read, if the database versions passwords
+    pub async fn update_password_if_version(
+        &self,
+        user_id: &UserID,
+        hashed_password: &HashedPassword,
+        expected_version: Option<&str>,
+    ) -> Result<(), AuthError> {
+        let Some(expected_version) = expected_version else {
+            return self.update_password(user_id, hashed_password).await;
+        };
+
+        let hashed_password = &self.encode_password(hashed_password.clone());
+
+        let updated = self
+            .with_db_timeout(async {
+                self.config
+                    .database_connection
+                    .update_password_if_version(user_id, hashed_password, expected_version)
+                    .await
+            })
+            .await?;
+
+        if !updated {
+            return Err(AuthError::PasswordChangeConflict);
+        }
+
+        Ok(())
+    }
+
+    pub async fn password_history(
+        &self,
+        user_id: &UserID,
+    ) -> Result<Vec<HashedPassword>, AuthError> {
+        let history = self
+            .with_db_timeout(async {
+                self.config
+                    .database_connection
+                    .password_history(user_id)
+                    .await
+            })
+            .await?;
+
+        history
+            .into_iter()
+            .map(|hashed_password| self.decode_password(hashed_password))
+            .collect()
+    }
+
+    // The form of a login identifier the database stores, which is its keyed hash if configured
+    fn stored_username(&self, username: &Username) -> Username {
+        let Some(key) = &self.config.identifier_hash_key else {
+            return username.clone();
+        };
+
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+        let digest = hmac::sign(&key, username.0.as_bytes());
+        Username(
+            digest
+                .as_ref()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect(),
+        )
+    }
+
+    fn encode_password(&self, hashed_password: HashedPassword) -> HashedPassword {
+        match &self.config.password_encoding {
+            Some(encoding) => encoding.encode(hashed_password),
+            None => hashed_password,
+        }
+    }
+
+    fn decode_password(
+        &self,
+        stored_password: HashedPassword,
+    ) -> Result<HashedPassword, AuthError> {
+        match &self.config.password_encoding {
+            Some(encoding) => Ok(encoding.decode(stored_password)?),
+            None => Ok(stored_password),
+        }
+    }
+
+    pub async fn token_lifetime(&self, user_id: &UserID) -> Result<Option<Duration>, AuthError> {
+        self.with_db_timeout(async {
+            self.config
+                .database_connection
+                .token_lifetime(user_id)
+                .await
+        })
+        .await
+    }
+
+    pub async fn account_created_at(
+        &self,
+        user_id: &UserID,
+    ) -> Result<Option<SystemTime>, AuthError> {
+        self.with_db_timeout(async {
+            self.config
+                .database_connection
+                .account_created_at(user_id)
+                .await
+        })
+        .await
+    }
+
+    pub async fn roles(&self, user_id: &UserID) -> Result<Vec<Role>, AuthError> {
+        self.with_db_timeout(async { self.config.database_connection.roles(user_id).await })
+            .await
+    }
+
+    pub async fn is_verified(&self, user_id: &UserID) -> Result<bool, AuthError> {
+        self.with_db_timeout(async { self.config.database_connection.is_verified(user_id).await })
+            .await
+    }
+
+    pub async fn mark_verified(&self, user_id: &UserID) -> Result<(), AuthError> {
+        self.with_db_timeout(async { self.config.database_connection.mark_verified(user_id).await })
+            .await
+    }
+
+    pub async fn list_users(
+        &self,
+        after: Option<&UserID>,
+        limit: usize,
+    ) -> Result<Vec<(UserID, Username)>, AuthError> {
+        self.with_db_timeout(async {
+            self.config
+                .database_connection
+                .list_users(after, limit)
+                .await
+        })
+        .await
+    }
+
+    pub async fn custom_claims(&self, user_id: &UserID) -> Result<Map<String, Value>, AuthError> {
+        let claims = self
+            .with_db_timeout(async { self.config.database_connection.custom_claims(user_id).await })
+            .await?;
+
+        Ok(claims)
+    }
+
+    // Bound a database operation (including waiting for the database lock) by the configured timeout
+    async fn with_db_timeout<T>(
+        &self,
+        operation: impl Future<Output = Result<T, Box<dyn Error + Send + Sync>>>,
+    ) -> Result<T, AuthError> {
+        let result = match self.config.db_operation_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, operation)
+                .await
+                .map_err(|_| AuthError::DatabaseTimeout)?,
+            None => operation.await,
+        };
+
+        // the in-memory stores fail with auth errors of their own, such as when they are full
+        result.map_err(|source| match source.downcast::<AuthError>() {
+            Ok(error) => *error,
+            Err(source) => AuthError::DatabaseError { source },
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Auth {
+    pub(crate) config: Arc<AuthConfig>,
+    pub(crate) internal: Arc<Mutex<AuthInternal>>,
+}
+
+impl Auth {
+    /// Create the auth module, logging a warning for each insecure setting in the config.
+    pub fn new(config: AuthConfig) -> Self {
+        for problem in config.insecure_settings() {
+            tracing::warn!("insecure auth config: {problem}");
+        }
+
+        Self::from_config(config)
+    }
+
+    /// Create the auth module, refusing any config with insecure settings.
+    pub fn try_new(config: AuthConfig) -> Result<Self, AuthError> {
+        let problems = config.insecure_settings();
+        if !problems.is_empty() {
+            return Err(AuthError::InsecureConfig { problems });
+        }
+
+        Ok(Self::from_config(config))
+    }
+
+    /// Perform a throwaway password hash, so that argon2's large memory allocation is made at startup rather than
+    /// slowing the first login, and so that hashing parameters that can't work (such as a `password_salt` outside
+    /// argon2's 8 to 48 bytes) are caught at startup rather than failing the first registration. Call it
+    /// once, before serving the auth routes.
+    pub async fn warmup(&self) -> Result<(), AuthError> {
+        self.internal.lock().await.passwords().warmup()
+    }
+
+    /// Exchange a user's token for a delegated token that lets `actor` act on the user's behalf.
+    /// The delegated token carries only the requested `scopes`, which must all have been granted to the
+    /// parent token, and expires after `lifetime` or when the parent does, whichever is sooner.
+    pub async fn exchange_token(
+        &self,
+        parent_token: &str,
+        actor: &str,
+        scopes: &[&str],
+        lifetime: Duration,
+    ) -> Result<String, AuthError> {
+        let core = self.internal.lock().await.core();
+        core.exchange_token(&self.internal, parent_token, actor, scopes, lifetime)
+            .await
+    }
+
+    /// Issue a session token for the user that expires at a fixed time, such as the end of a billing period, rather
+    /// than after `auth_token_lifetime`. The token is capped by `max_token_lifetime` like any other, and carries the
+    /// user's custom claims and the default scopes. Tokens refreshed from it expire no later than the deadline either.
+    /// Fails with [`AuthError::ExpiryInPast`] if the deadline has passed.
+    pub async fn generate_token_until(
+        &self,
+        user_id: &UserID,
+        deadline: SystemTime,
+    ) -> Result<String, AuthError> {
+        let core = self.internal.lock().await.core();
+        core.generate_token_until(&self.internal, user_id, deadline)
+            .await
+    }
+
+    /// Authenticate a request from any transport (such as gRPC metadata) given the value of its authorization
+    /// header or equivalent, which may be either `Bearer <token>` or the bare token, as `with_auth` does for HTTP.
+    pub async fn authenticate(&self, header_value: &str) -> Result<UserID, AuthError> {
+        let header_value = header_value.trim().to_owned();
+        let token = header_value
+            .strip_prefix_ignore_ascii_case("bearer ")
+            .unwrap_or(&header_value);
+
+        // the provider's keys or the stored token may need fetching, which is done without holding the lock
+        let core = self.internal.lock().await.core();
+        let presented = core.read_presented_token(token).await?;
+        let claims = self.internal.lock().await.verify_session_token(presented)?;
+
+        claims
+            .user_id(&self.config.user_id_claim)
+            .ok_or(AuthError::TokenError { source: None })
+    }
+
+    /// Import existing users in bulk, such as when migrating from another system, and return how many were created.
+    /// Users whose username is already taken are skipped. Passwords must already be hashed, as encoded argon2 hashes
+    /// (`$argon2id$v=19$...`); the salt is read from each hash, but any `argon2_secret` must match the one they were
+    /// hashed with. Usernames are normalized as login identifiers are, so they needn't already be lowercase where
+    /// identifiers are case-insensitive. The import isn't subject to `db_operation_timeout`.
+    pub async fn import_users(
+        &self,
+        users: impl Stream<Item = (UserID, Username, HashedPassword)> + Send,
+    ) -> Result<usize, AuthError> {
+        // stored in the same form as registration stores them
+        let core = self.internal.lock().await.core();
+        let users = users.map(move |(user_id, username, hashed_password)| {
+            (
+                user_id,
+                core.stored_username(&core.normalize_identifier(username.0)),
+                core.encode_password(hashed_password),
+            )
+        });
+
+        let created = self
+            .config
+            .database_connection
+            .import_users(Box::pin(users))
+            .await?;
+
+        Ok(created)
+    }
+
+    /// List up to `limit` users, ordered by user id, starting after the given id. Pass the last id of each page as
+    /// the next `after` to page through every user exactly once.
+    pub async fn list_users(
+        &self,
+        after: Option<&UserID>,
+        limit: usize,
+    ) -> Result<Vec<(UserID, Username)>, AuthError> {
+        let core = self.internal.lock().await.core();
+        core.list_users(after, limit).await
+    }
+
+    /// Revoke every token issued before the cutoff, whoever it was issued to, as a mass logout in an incident. Tokens
+    /// only record the second they were issued in, so those issued within the cutoff's second are revoked too. Use
+    /// `SystemTime::now()` to revoke every token issued so far.
+    pub async fn revoke_tokens_issued_before(&self, cutoff: SystemTime) {
+        self.internal
+            .lock()
+            .await
+            .revoke_tokens_issued_before(cutoff);
+    }
+
+    /// List the user's active sessions, oldest first. Sessions are only tracked if `max_sessions` is set.
+    pub async fn sessions(&self, user_id: &UserID) -> Vec<Session> {
+        self.internal.lock().await.sessions(user_id)
+    }
+
+    /// Report how many entries each of the in-memory stores of revoked tokens, sessions and so on holds, for metrics.
+    pub async fn store_sizes(&self) -> StoreSizes {
+        self.internal.lock().await.store_sizes()
+    }
+
+    /// Forget the expired entries in each of the in-memory stores. Expired entries are otherwise only forgotten as
+    /// new entries of the same kind are stored, so that a store that stops growing keeps them indefinitely.
+    pub async fn purge_expired(&self) {
+        self.internal.lock().await.purge_expired();
+    }
+
+    /// Spawn a task on the current tokio runtime that purges expired entries every `interval`, as
+    /// [`purge_expired`](Self::purge_expired). The task ends once this auth module and every filter built from it
+    /// have been dropped.
+    pub fn spawn_cleanup(&self, interval: Duration) -> JoinHandle<()> {
+        let internal = Arc::downgrade(&self.internal);
+
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+
+                let Some(internal) = internal.upgrade() else {
+                    return;
+                };
+                internal.lock().await.purge_expired();
+            }
+        })
+    }
+
+    fn from_config(config: AuthConfig) -> Self {
+        let config = Arc::new(config);
+        let secrets = config.secret_provider.clone().unwrap_or_else(|| {
+            Arc::new(StaticSecret(config.auth_token_secret.clone())) as Arc<dyn SecretProvider>
+        });
+        let passwords = PasswordManager::from(&*config);
+        let dummy_hash = passwords.try_hash(&Uuid::new_v4().to_string()).ok();
+        let hash_limiter = Arc::new(HashLimiter::new(
+            config.max_concurrent_hashes,
+            config.max_queued_hashes,
+        ));
+        let failure_reporter = config.on_auth_failure.clone().map(FailureReporter::new);
+        let capacity = config.max_store_entries;
+        if let Some(capacity) = capacity {
+            config.throttle_store.limit_entries(capacity);
+            if let Some(token_store) = &config.token_store {
+                token_store.limit_entries(capacity);
+            }
+        }
 
-impl Auth {
-    pub fn new(config: AuthConfig) -> Self {
         Self {
-            internal: Arc::new(Mutex::new(AuthInternal { config })),
+            config: config.clone(),
+            internal: Arc::new(Mutex::new(AuthInternal {
+                core: AuthCore {
+                    config,
+                    secrets,
+                    dummy_hash,
+                    passwords,
+                    hash_limiter,
+                    failure_reporter,
+                },
+                used_tokens: ExpiringMap::new(capacity),
+                revoked_tokens: ExpiringMap::new(capacity),
+                sessions: HashMap::new(),
+                session_owners: ExpiringMap::new(capacity),
+                not_before: ExpiringMap::new(capacity),
+                global_not_before: None,
+                pseudonyms: ExpiringMap::new(capacity),
+                device_bindings: ExpiringMap::new(capacity),
+                last_seen: ExpiringMap::new(capacity),
+            })),
         }
     }
 }
@@ -0,0 +1,36 @@
+use warp::{
+    http::{
+        header::{CACHE_CONTROL, VARY},
+        HeaderValue,
+    },
+    reply::Response,
+    Reply,
+};
+
+use crate::types::{UserID, Username};
+
+/// The authenticated user, along with what their authentication implies for the reply. Handlers consult it to
+/// learn who the user is, and wrap their reply with [`AuthContext::finish`] so that it is marked as private to them.
+#[derive(Debug, Clone)]
+pub struct AuthContext {
+    pub user_id: UserID,
+    /// The username at the time the token was issued, if `AuthConfig::username_claim` is enabled.
+    pub username: Option<Username>,
+}
+
+impl AuthContext {
+    /// Apply the headers implied by the authentication to a reply. As the reply was produced for this user, it is
+    /// marked `Cache-Control: private`, so that shared caches (such as CDNs) don't serve it to anyone else, unless
+    /// the handler already set its own `Cache-Control`.
+    pub fn finish(&self, reply: impl Reply) -> Response {
+        let mut response = reply.into_response();
+        let headers = response.headers_mut();
+
+        if !headers.contains_key(CACHE_CONTROL) {
+            headers.insert(CACHE_CONTROL, HeaderValue::from_static("private"));
+        }
+        headers.append(VARY, HeaderValue::from_static("Authorization"));
+
+        response
+    }
+}
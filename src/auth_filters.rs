@@ -0,0 +1,135 @@
+use std::{convert::Infallible, error::Error, future::Future, time::Duration};
+
+use serde_json::{Map, Value};
+use warp::{Filter, Rejection, Reply};
+
+use crate::{
+    auth::Auth,
+    auth_context::AuthContext,
+    claim_requirement::ClaimRequirement,
+    routes::{
+        build_api_route_filter, with_account_age, with_acr, with_auth, with_auth_context,
+        with_auth_guarded, with_auth_loading, with_auth_matching_param, with_auth_requiring,
+        with_auth_roles, with_csrf_protection, with_optional_auth, with_principal,
+    },
+    types::{Principal, Role, UserID},
+};
+
+/// The auth filters bundled with the [`Auth`] they share, for apps that split their routes across modules. Hand each
+/// module a clone (which is cheap, as `Auth` is) rather than threading the `Auth` through every route builder, and
+/// build filters from it with the methods named after the free functions they delegate to. See the `modules` example.
+#[derive(Clone)]
+pub struct AuthFilters {
+    auth: Auth,
+}
+
+impl AuthFilters {
+    pub fn new(auth: &Auth) -> Self {
+        Self { auth: auth.clone() }
+    }
+
+    /// The shared auth module, for anything the filters don't cover.
+    pub fn auth(&self) -> &Auth {
+        &self.auth
+    }
+
+    /// The auth routes themselves, as [`build_api_route_filter`].
+    pub fn api_routes(&self) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+        build_api_route_filter(&self.auth)
+    }
+
+    /// As [`with_auth`].
+    pub fn with_auth(&self) -> impl Filter<Extract = (UserID,), Error = Rejection> + Clone {
+        with_auth(&self.auth)
+    }
+
+    /// As [`with_optional_auth`].
+    pub fn with_optional_auth(
+        &self,
+    ) -> impl Filter<Extract = (Option<UserID>,), Error = Infallible> + Clone {
+        with_optional_auth(&self.auth)
+    }
+
+    /// As [`with_principal`].
+    pub fn with_principal(&self) -> impl Filter<Extract = (Principal,), Error = Rejection> + Clone {
+        with_principal(&self.auth)
+    }
+
+    /// As [`with_auth_roles`].
+    pub fn with_auth_roles(
+        &self,
+    ) -> impl Filter<Extract = (UserID, Vec<Role>), Error = Rejection> + Clone {
+        with_auth_roles(&self.auth)
+    }
+
+    /// As [`with_auth_guarded`].
+    pub fn with_auth_guarded<P, F>(
+        &self,
+        predicate: P,
+    ) -> impl Filter<Extract = (UserID,), Error = Rejection> + Clone
+    where
+        P: Fn(UserID, Map<String, Value>) -> F + Clone + Send + Sync + 'static,
+        F: Future<Output = Result<(), String>> + Send,
+    {
+        with_auth_guarded(&self.auth, predicate)
+    }
+
+    /// As [`with_auth_loading`].
+    pub fn with_auth_loading<L, F, C, E>(
+        &self,
+        loader: L,
+    ) -> impl Filter<Extract = (UserID, C), Error = Rejection> + Clone
+    where
+        L: Fn(UserID) -> F + Clone + Send + Sync + 'static,
+        F: Future<Output = Result<C, E>> + Send,
+        C: Send,
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        with_auth_loading(&self.auth, loader)
+    }
+
+    /// As [`with_auth_context`].
+    pub fn with_auth_context(
+        &self,
+    ) -> impl Filter<Extract = (AuthContext,), Error = Rejection> + Clone {
+        with_auth_context(&self.auth)
+    }
+
+    /// As [`with_auth_requiring`].
+    pub fn with_auth_requiring(
+        &self,
+        requirements: &[ClaimRequirement],
+    ) -> impl Filter<Extract = (UserID,), Error = Rejection> + Clone {
+        with_auth_requiring(&self.auth, requirements)
+    }
+
+    /// As [`with_auth_matching_param`].
+    pub fn with_auth_matching_param(
+        &self,
+    ) -> impl Filter<Extract = (UserID,), Error = Rejection> + Clone {
+        with_auth_matching_param(&self.auth)
+    }
+
+    /// As [`with_account_age`].
+    pub fn with_account_age(
+        &self,
+        min_age: Duration,
+    ) -> impl Filter<Extract = (UserID,), Error = Rejection> + Clone {
+        with_account_age(&self.auth, min_age)
+    }
+
+    /// As [`with_acr`].
+    pub fn with_acr(
+        &self,
+        min_level: u32,
+    ) -> impl Filter<Extract = (UserID,), Error = Rejection> + Clone {
+        with_acr(&self.auth, min_level)
+    }
+
+    /// As [`with_csrf_protection`].
+    pub fn with_csrf_protection(
+        &self,
+    ) -> impl Filter<Extract = (UserID,), Error = Rejection> + Clone {
+        with_csrf_protection(&self.auth)
+    }
+}
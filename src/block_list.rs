@@ -0,0 +1,25 @@
+use std::{error::Error, net::IpAddr};
+
+use async_trait::async_trait;
+
+use crate::types::UserID;
+
+/// Blocks specific users or client addresses outright, for abuse mitigation, even if they hold a valid token. It is
+/// consulted by `with_auth` (and every other filter that authenticates a request) and by the login route, which
+/// reject blocked requests with [`AuthError::Blocked`](crate::AuthError::Blocked). Implementations may keep the list
+/// in memory or in a database, and match addresses by range as they see fit.
+#[async_trait]
+pub trait BlockList: Send + Sync + 'static {
+    /// Whether the user is blocked.
+    async fn is_user_blocked(
+        &self,
+        _user_id: &UserID,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        Ok(false)
+    }
+
+    /// Whether requests from the client address are blocked.
+    async fn is_ip_blocked(&self, _ip: IpAddr) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        Ok(false)
+    }
+}
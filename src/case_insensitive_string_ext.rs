@@ -4,11 +4,8 @@ pub trait CaseInsensitiveStringExt {
 
 impl CaseInsensitiveStringExt for String {
     fn strip_prefix_ignore_ascii_case<'a>(&'a self, prefix: &str) -> Option<&'a str> {
-        if self.len() < prefix.len() {
-            return None;
-        }
-
-        let my_prefix = &self[0..prefix.len()];
+        // get() rather than indexing, as the prefix length may fall inside a multi-byte character
+        let my_prefix = self.get(0..prefix.len())?;
 
         if my_prefix.eq_ignore_ascii_case(prefix) {
             Some(&self[prefix.len()..])
@@ -17,3 +14,40 @@ impl CaseInsensitiveStringExt for String {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::CaseInsensitiveStringExt;
+
+    #[test]
+    fn prefix_ending_inside_multibyte_character() {
+        assert_eq!("bé".to_string().strip_prefix_ignore_ascii_case("be"), None);
+    }
+
+    #[test]
+    fn prefix_matches_ignoring_case() {
+        assert_eq!(
+            "BeArEr token"
+                .to_string()
+                .strip_prefix_ignore_ascii_case("bearer "),
+            Some("token")
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn never_panics(value in any::<String>(), prefix in "[a-zA-Z ]{0,8}") {
+            let _ = value.strip_prefix_ignore_ascii_case(&prefix);
+        }
+
+        #[test]
+        fn stripped_value_is_suffix(value in any::<String>()) {
+            if let Some(rest) = value.strip_prefix_ignore_ascii_case("bearer ") {
+                prop_assert!(value.ends_with(rest));
+                prop_assert_eq!(rest.len() + "bearer ".len(), value.len());
+            }
+        }
+    }
+}
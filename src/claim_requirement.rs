@@ -0,0 +1,41 @@
+use serde_json::Value;
+
+/// A declarative condition on the claims of an auth token, for use with `with_auth_requiring`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClaimRequirement {
+    /// The named claim must be present, with any value.
+    Present(String),
+    /// The named claim must equal the given value.
+    Equals(String, Value),
+    /// The named claim must contain the given value, either as an element of an array claim,
+    /// or as one of the words in a space-delimited string claim such as `scope`.
+    Contains(String, Value),
+}
+
+impl ClaimRequirement {
+    pub fn present(claim: impl Into<String>) -> Self {
+        Self::Present(claim.into())
+    }
+
+    pub fn equals(claim: impl Into<String>, value: impl Into<Value>) -> Self {
+        Self::Equals(claim.into(), value.into())
+    }
+
+    pub fn contains(claim: impl Into<String>, value: impl Into<Value>) -> Self {
+        Self::Contains(claim.into(), value.into())
+    }
+
+    pub(crate) fn is_satisfied_by(&self, claims: &Value) -> bool {
+        match self {
+            Self::Present(claim) => claims.get(claim).is_some(),
+            Self::Equals(claim, expected) => claims.get(claim) == Some(expected),
+            Self::Contains(claim, expected) => match (claims.get(claim), expected) {
+                (Some(Value::Array(values)), _) => values.contains(expected),
+                (Some(Value::String(words)), Value::String(expected)) => {
+                    words.split_whitespace().any(|word| word == expected)
+                }
+                _ => false,
+            },
+        }
+    }
+}
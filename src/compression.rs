@@ -0,0 +1,31 @@
+use std::io::{Read, Write};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::error::AuthError;
+
+// Far larger than the claims of any token this crate issues, so that decompression is bounded
+const MAX_DECOMPRESSED_LENGTH: u64 = 1024 * 1024;
+
+// DEFLATE the serialized claims, and encode them to be carried in a claim of their own
+pub(crate) fn compress(claims: &[u8]) -> String {
+    let mut encoder = DeflateEncoder::new(vec![], Compression::best());
+    // writing to a Vec can't fail
+    encoder.write_all(claims).unwrap();
+    URL_SAFE_NO_PAD.encode(encoder.finish().unwrap())
+}
+
+pub(crate) fn decompress(compressed: &str) -> Result<Vec<u8>, AuthError> {
+    let compressed = URL_SAFE_NO_PAD
+        .decode(compressed)
+        .map_err(|_| AuthError::TokenError { source: None })?;
+
+    let mut claims = vec![];
+    DeflateDecoder::new(compressed.as_slice())
+        .take(MAX_DECOMPRESSED_LENGTH)
+        .read_to_end(&mut claims)
+        .map_err(|_| AuthError::TokenError { source: None })?;
+
+    Ok(claims)
+}
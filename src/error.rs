@@ -2,8 +2,8 @@ use warp::reject::Reject;
 
 #[derive(thiserror::Error, Debug)]
 pub enum AuthError {
-    #[error("an account with that username already exists")]
-    UsernameAlreadyTaken,
+    #[error("an account with that {field} already exists")]
+    AlreadyExists { field: String },
     #[error("username or password incorrect")]
     LoginFailed,
     #[error("error during database operation")]
@@ -11,11 +11,76 @@ pub enum AuthError {
         #[from]
         source: Box<dyn std::error::Error + Send + Sync>,
     },
+    #[error("unable to hash password")]
+    HashingError {
+        #[from]
+        source: argon2::password_hash::Error,
+    },
+    #[error("timed out waiting for database operation")]
+    DatabaseTimeout,
+    #[error("too many requests are waiting to hash passwords")]
+    Overloaded,
+    #[error("too many entries are stored in memory to take another")]
+    StoreFull,
+    #[error("timed out waiting for the request handler")]
+    HandlerTimeout,
+    #[error("no auth token was provided")]
+    MissingToken,
     #[error("error with token")]
     TokenError {
         #[from]
         source: Option<jsonwebtoken::errors::Error>,
     },
+    #[error("unable to fetch the key set used to verify tokens")]
+    KeySetUnavailable {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("no key in the key set matches the token's key id {kid}")]
+    UnknownKeyId { kid: String },
+    #[error("token rejected: {reason}")]
+    TokenRejected { reason: String },
+    #[error("insufficient permissions")]
+    InsufficientPermissions,
+    #[error("access denied: {reason}")]
+    AccessDenied { reason: String },
+    #[error("the password was changed by another request")]
+    PasswordChangeConflict,
+    #[error("too many active sessions")]
+    TooManySessions,
+    #[error("the user or client address is blocked")]
+    Blocked,
+    #[error("too many failed login attempts")]
+    AccountLocked,
+    #[error("too many requests")]
+    TooManyRequests,
+    #[error("too many registrations from this client address")]
+    RegistrationThrottled,
+    #[error("the registration challenge was not passed")]
+    ChallengeFailed,
+    #[error("the user has not verified their email")]
+    VerificationRequired,
+    #[error("account is too new")]
+    AccountTooNew,
+    #[error("stronger authentication is required")]
+    InsufficientAuthentication,
+    #[error("missing or mismatched CSRF token")]
+    CsrfTokenMismatch,
+    #[error("method not allowed")]
+    MethodNotAllowed,
+    #[error("a login identifier is required")]
+    MissingIdentifier,
+    #[error("a tenant is required")]
+    TenantRequired,
+    #[error("tokens can't be issued for the audience {audience}")]
+    AudienceNotAllowed { audience: String },
+    #[error("username is not allowed: {}", .reasons.join(", "))]
+    InvalidUsername { reasons: Vec<String> },
+    #[error("password does not meet requirements: {}", .reasons.join(", "))]
+    WeakPassword { reasons: Vec<String> },
+    #[error("the requested token expiry has already passed")]
+    ExpiryInPast,
+    #[error("insecure configuration: {}", .problems.join(", "))]
+    InsecureConfig { problems: Vec<String> },
 }
 
 impl Reject for AuthError {}
@@ -0,0 +1,104 @@
+use std::collections::{BTreeSet, HashMap};
+
+// Entries that may each be forgotten once a given time has passed, indexed by that time so that forgetting the ones
+// whose time has passed never scans the rest. The map may be given a capacity, but never evicts an entry to make room
+// within it: what to do when it is full is up to the caller
+#[derive(Clone)]
+pub(crate) struct ExpiringMap<V, T = u64> {
+    entries: HashMap<String, (V, T)>,
+    by_expiry: BTreeSet<(T, String)>,
+    capacity: Option<usize>,
+}
+
+impl<V, T: Ord + Copy> ExpiringMap<V, T> {
+    pub fn new(capacity: Option<usize>) -> Self {
+        Self {
+            entries: HashMap::new(),
+            by_expiry: BTreeSet::new(),
+            capacity,
+        }
+    }
+
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity;
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.capacity
+            .is_some_and(|capacity| self.entries.len() >= capacity)
+    }
+
+    // Whether the entry could be stored without exceeding the capacity, either because it replaces one or there is
+    // room for another
+    pub fn has_room_for(&self, key: &str) -> bool {
+        self.entries.contains_key(key) || !self.is_full()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    // The entry, with the time it may be forgotten after
+    pub fn get_with_expiry(&self, key: &str) -> Option<(&V, T)> {
+        self.entries
+            .get(key)
+            .map(|(value, expires_at)| (value, *expires_at))
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        self.entries.get_mut(key).map(|(value, _)| value)
+    }
+
+    // Store the entry, replacing any under the same key, whether or not there is room for it
+    pub fn insert(&mut self, key: String, value: V, expires_at: T) {
+        if let Some((_, replaced)) = self.entries.get(&key) {
+            self.by_expiry.remove(&(*replaced, key.clone()));
+        }
+        self.by_expiry.insert((expires_at, key.clone()));
+        self.entries.insert(key, (value, expires_at));
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        let (value, expires_at) = self.entries.remove(key)?;
+        self.by_expiry.remove(&(expires_at, key.to_owned()));
+
+        Some(value)
+    }
+
+    // Forget the entries whose time passed before now, returning them
+    pub fn forget_expired(&mut self, now: T) -> Vec<(String, V)> {
+        let mut forgotten = vec![];
+
+        while let Some((expires_at, _)) = self.by_expiry.first() {
+            if *expires_at >= now {
+                break;
+            }
+
+            let (_, key) = self.by_expiry.pop_first().unwrap();
+            if let Some((value, _)) = self.entries.remove(&key) {
+                forgotten.push((key, value));
+            }
+        }
+
+        forgotten
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.by_expiry.clear();
+    }
+}
+
+impl<V, T: Ord + Copy> Default for ExpiringMap<V, T> {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
@@ -0,0 +1,43 @@
+use std::{
+    net::IpAddr,
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc::{self, SyncSender},
+    thread,
+};
+
+use crate::auth::{AuthFailureCallback, AuthFailureKind};
+
+// How many failures may wait to be reported before more are dropped
+const MAX_QUEUED_FAILURES: usize = 1024;
+
+// Reports verification failures to the callback one at a time, from a worker thread of its own, so that a slow
+// callback holds up no request. Failures queue for the worker up to a bound, past which they are dropped rather than
+// piling up while the callback can't keep up
+#[derive(Clone)]
+pub(crate) struct FailureReporter {
+    queue: SyncSender<(AuthFailureKind, Option<IpAddr>)>,
+}
+
+impl FailureReporter {
+    pub fn new(on_auth_failure: AuthFailureCallback) -> Self {
+        let (queue, failures) = mpsc::sync_channel(MAX_QUEUED_FAILURES);
+
+        // the worker stops once every reporter is dropped along with the auth module
+        thread::Builder::new()
+            .name("auth-failure-reporter".into())
+            .spawn(move || {
+                for (kind, ip) in failures {
+                    // a panic in the callback is confined to the one report
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| on_auth_failure(kind, ip)));
+                }
+            })
+            .expect("failed to start the auth failure reporter");
+
+        Self { queue }
+    }
+
+    // Queue the failure to be reported, dropping it if too many are already waiting
+    pub fn report(&self, kind: AuthFailureKind, ip: Option<IpAddr>) {
+        let _ = self.queue.try_send((kind, ip));
+    }
+}
@@ -0,0 +1,75 @@
+use std::{sync::Mutex, time::Duration};
+
+/// Adapts the argon2 time cost (the number of passes over memory) of new password hashes to the observed hashing
+/// latency, so that under load the server backs off towards a cheaper hash rather than exhausting its CPU, and
+/// under light load returns to the stronger one. This trades some security margin for availability during
+/// spikes. Hashes record their own cost, so passwords hashed at any cost continue to verify.
+pub struct AdaptiveHashCost {
+    min_time_cost: u32,
+    max_time_cost: u32,
+    target_latency: Duration,
+    state: Mutex<CostState>,
+}
+
+struct CostState {
+    time_cost: u32,
+    // moving average of recent hashing latency at the current cost, in seconds
+    average_latency: Option<f64>,
+}
+
+// Weight given to each new latency sample in the moving average
+const SMOOTHING: f64 = 0.2;
+
+impl AdaptiveHashCost {
+    /// Keep the time cost between the given bounds, starting at the maximum, and aim for hashes to take no longer
+    /// than the target latency. The cost only rises again once hashes take less than half the target.
+    pub fn new(min_time_cost: u32, max_time_cost: u32, target_latency: Duration) -> Self {
+        let min_time_cost = min_time_cost.max(1);
+        let max_time_cost = max_time_cost.max(min_time_cost);
+
+        Self {
+            min_time_cost,
+            max_time_cost,
+            target_latency,
+            state: Mutex::new(CostState {
+                time_cost: max_time_cost,
+                average_latency: None,
+            }),
+        }
+    }
+
+    /// The time cost currently applied to new hashes, for reporting as a metric.
+    pub fn time_cost(&self) -> u32 {
+        self.state.lock().unwrap().time_cost
+    }
+
+    /// Record how long a hash at the current cost took, adjusting the cost if hashing is persistently too slow or fast.
+    pub fn record_latency(&self, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+
+        let average = match state.average_latency {
+            Some(average) => average + SMOOTHING * (latency.as_secs_f64() - average),
+            None => latency.as_secs_f64(),
+        };
+
+        let target = self.target_latency.as_secs_f64();
+        let time_cost = if average > target {
+            state.time_cost.saturating_sub(1).max(self.min_time_cost)
+        } else if average < target / 2.0 {
+            (state.time_cost + 1).min(self.max_time_cost)
+        } else {
+            state.time_cost
+        };
+
+        if time_cost != state.time_cost {
+            tracing::debug!("adjusted password hashing time cost to {time_cost}");
+            // latency at the old cost says little about the new one
+            *state = CostState {
+                time_cost,
+                average_latency: None,
+            };
+        } else {
+            state.average_latency = Some(average);
+        }
+    }
+}
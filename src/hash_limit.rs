@@ -0,0 +1,72 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::error::AuthError;
+
+// Bounds the requests that hash passwords, so that a flood of them queues (or is shed) here rather than piling up
+pub(crate) struct HashLimiter {
+    permits: Arc<Semaphore>,
+    waiting: AtomicUsize,
+    max_waiting: Option<usize>,
+}
+
+impl HashLimiter {
+    pub fn new(max_concurrent: usize, max_waiting: Option<usize>) -> Self {
+        Self {
+            // a limit of zero would never let anything through
+            permits: Arc::new(Semaphore::new(max_concurrent.max(1))),
+            waiting: AtomicUsize::new(0),
+            max_waiting,
+        }
+    }
+
+    // Wait for a slot to hash in, unless too many requests are already waiting
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit, AuthError> {
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+
+        let waiting = Waiting::enter(&self.waiting);
+        if self
+            .max_waiting
+            .is_some_and(|max_waiting| waiting.ahead >= max_waiting)
+        {
+            return Err(AuthError::Overloaded);
+        }
+
+        // the semaphore is never closed
+        Ok(self.permits.clone().acquire_owned().await.unwrap())
+    }
+}
+
+// Counts a waiting request for as long as it waits, even if it is abandoned
+struct Waiting<'a> {
+    count: &'a AtomicUsize,
+    // how many requests were already waiting
+    ahead: usize,
+}
+
+impl<'a> Waiting<'a> {
+    fn enter(count: &'a AtomicUsize) -> Self {
+        let ahead = count.fetch_add(1, Ordering::SeqCst);
+        Self { count, ahead }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// One hash at a time per CPU, as argon2 is CPU-bound
+pub(crate) fn default_max_concurrent_hashes() -> usize {
+    thread::available_parallelism().map_or(1, |parallelism| parallelism.get())
+}
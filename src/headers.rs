@@ -0,0 +1,187 @@
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    sync::{Mutex, OnceLock},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use warp::{
+    filters::BoxedFilter,
+    http::{header::COOKIE, HeaderMap},
+    Filter,
+};
+
+use crate::case_insensitive_string_ext::CaseInsensitiveStringExt;
+
+// All header access goes through these helpers, so that header names are always matched
+// case-insensitively, as required by the HTTP spec
+
+// Extract the named header as a string, if present and visible ASCII. If the header is repeated, the first value is
+// returned
+pub(crate) fn optional_header(
+    name: &'static str,
+) -> impl Filter<Extract = (Option<String>,), Error = Infallible> + Clone {
+    // warp refuses a value that isn't visible ASCII, which is treated as though the header were missing
+    warp::header::optional::<String>(name)
+        .or(warp::any().map(|| None))
+        .unify()
+}
+
+// Extract the header of a configured name as a string, if one is configured and the request carries it
+pub(crate) fn optional_configured_header(name: Option<String>) -> BoxedFilter<(Option<String>,)> {
+    match name {
+        Some(name) => optional_header(static_header_name(name)).boxed(),
+        None => warp::any().map(|| None).boxed(),
+    }
+}
+
+// warp only looks headers up by static name, so each configured name is leaked, though only once however many
+// filters are built with it
+fn static_header_name(name: String) -> &'static str {
+    static NAMES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+
+    let mut names = NAMES.get_or_init(Default::default).lock().unwrap();
+    match names.get(name.as_str()) {
+        Some(name) => name,
+        None => {
+            let name = Box::leak(name.into_boxed_str());
+            names.insert(name);
+            name
+        }
+    }
+}
+
+// Extract the value of the named cookie, if the request carries it
+pub(crate) fn optional_cookie(
+    name: String,
+) -> impl Filter<Extract = (Option<String>,), Error = Infallible> + Clone {
+    warp::header::headers_cloned()
+        .map(move |headers: HeaderMap| cookie_value(&headers, &name).map(str::to_owned))
+}
+
+// Read the named cookie from any of the cookie headers in a header map. Cookie names are case-sensitive.
+pub(crate) fn cookie_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .find_map(|cookie| {
+            let (cookie_name, value) = cookie.trim().split_once('=')?;
+            (cookie_name == name).then_some(value)
+        })
+}
+
+// Decode the username and password from the value of a Basic authorization header. Returns None if the header
+// isn't Basic auth, or is malformed. The password may contain colons, but the username may not
+pub(crate) fn basic_credentials(authorization: String) -> Option<(String, String)> {
+    let encoded = authorization.strip_prefix_ignore_ascii_case("basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+
+    Some((username.to_owned(), password.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use warp::http::{HeaderMap, HeaderName, HeaderValue};
+
+    use super::{basic_credentials, cookie_value, optional_configured_header, optional_header};
+
+    fn headers(name: &str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes()).unwrap(),
+            HeaderValue::from_str(value).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn cookie_value_from_any_cookie_header() {
+        let mut headers = headers("Cookie", "theme=dark; auth_token=abc.def=");
+        headers.append("cookie", HeaderValue::from_static("session=123"));
+
+        assert_eq!(cookie_value(&headers, "auth_token"), Some("abc.def="));
+        assert_eq!(cookie_value(&headers, "session"), Some("123"));
+        assert_eq!(cookie_value(&headers, "Session"), None);
+        assert_eq!(cookie_value(&headers, "missing"), None);
+    }
+
+    #[test]
+    fn basic_credentials_decoded() {
+        // base64 of "Sam I Am:green:eggs"
+        assert_eq!(
+            basic_credentials("Basic U2FtIEkgQW06Z3JlZW46ZWdncw==".into()),
+            Some(("Sam I Am".into(), "green:eggs".into()))
+        );
+        assert_eq!(
+            basic_credentials("basic U2FtIEkgQW06Z3JlZW46ZWdncw==".into()),
+            Some(("Sam I Am".into(), "green:eggs".into()))
+        );
+
+        assert_eq!(
+            basic_credentials("Bearer U2FtIEkgQW06Z3JlZW46ZWdncw==".into()),
+            None
+        );
+        assert_eq!(basic_credentials("Basic not base64!".into()), None);
+        // base64 of "no colon"
+        assert_eq!(basic_credentials("Basic bm8gY29sb24=".into()), None);
+    }
+
+    #[tokio::test]
+    async fn filter_ignores_name_case() {
+        for sent in ["Authorization", "authorization", "AUTHORIZATION"] {
+            for requested in ["Authorization", "authorization", "AUTHORIZATION"] {
+                let request = || warp::test::request().header(sent, "Bearer token");
+
+                assert_eq!(
+                    request()
+                        .filter(&optional_header(requested))
+                        .await
+                        .unwrap()
+                        .as_deref(),
+                    Some("Bearer token"),
+                    "sent {sent}, requested {requested}"
+                );
+                assert_eq!(
+                    request()
+                        .filter(&optional_configured_header(Some(requested.into())))
+                        .await
+                        .unwrap()
+                        .as_deref(),
+                    Some("Bearer token"),
+                    "sent {sent}, requested {requested}"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn filter_missing_or_opaque() {
+        let filter = optional_header("authorization");
+
+        assert_eq!(warp::test::request().filter(&filter).await.unwrap(), None);
+        assert_eq!(
+            warp::test::request()
+                .header(
+                    "authorization",
+                    HeaderValue::from_bytes(b"Bearer \xff").unwrap()
+                )
+                .filter(&filter)
+                .await
+                .unwrap(),
+            None
+        );
+
+        // with no header configured, nothing is read
+        assert_eq!(
+            warp::test::request()
+                .header("authorization", "Bearer token")
+                .filter(&optional_configured_header(None))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use jsonwebtoken::{
+    decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, TokenData, Validation,
+};
+use serde::de::DeserializeOwned;
+
+use crate::error::AuthError;
+
+/// A JSON Web Key Set published by an external identity provider (such as Auth0 or Keycloak),
+/// used to verify the tokens it issues. Keys are fetched on first use and cached, and the cached keys are kept in use
+/// for as long as the provider can't be reached to refresh them.
+pub struct RemoteJwks {
+    url: String,
+    algorithms: Vec<Algorithm>,
+    max_age: Duration,
+    min_refetch_interval: Duration,
+    fetch_timeout: Duration,
+    client: reqwest::Client,
+    cache: Mutex<KeyCache>,
+    // held while fetching, so that requests that find the cache out of date wait for one fetch between them
+    fetching: tokio::sync::Mutex<()>,
+}
+
+#[derive(Default)]
+struct KeyCache {
+    keys: Option<CachedKeys>,
+    last_attempt: Option<Instant>,
+}
+
+#[derive(Clone)]
+struct CachedKeys {
+    keys: Arc<JwkSet>,
+    fetched_at: Instant,
+}
+
+impl RemoteJwks {
+    /// Verify tokens against the key set published at the given url, typically the `jwks_uri`
+    /// from the provider's OpenID configuration.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            algorithms: vec![Algorithm::RS256],
+            max_age: Duration::from_secs(60 * 60),
+            min_refetch_interval: Duration::from_secs(10),
+            fetch_timeout: Duration::from_secs(10),
+            client: reqwest::Client::new(),
+            cache: Mutex::default(),
+            fetching: tokio::sync::Mutex::default(),
+        }
+    }
+
+    /// The signing algorithms to accept. Defaults to RS256 only.
+    pub fn with_algorithms(mut self, algorithms: Vec<Algorithm>) -> Self {
+        self.algorithms = algorithms;
+        self
+    }
+
+    /// How long fetched keys are cached before being refreshed. Defaults to an hour.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// When a token is signed by a key that isn't in the cached set (such as after the provider rotates
+    /// its keys), the set is fetched again, but no more often than this. Defaults to 10 seconds.
+    pub fn with_min_refetch_interval(mut self, min_refetch_interval: Duration) -> Self {
+        self.min_refetch_interval = min_refetch_interval;
+        self
+    }
+
+    /// How long to wait for the provider when fetching the key set, before giving up on it. Until the key set has
+    /// been fetched once, tokens are rejected when a fetch fails; after that the cached keys are used, and the fetch
+    /// retried no more often than the `min_refetch_interval`. Defaults to 10 seconds.
+    pub fn with_fetch_timeout(mut self, fetch_timeout: Duration) -> Self {
+        self.fetch_timeout = fetch_timeout;
+        self
+    }
+
+    pub(crate) async fn decode<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        mut validation: Validation,
+    ) -> Result<TokenData<T>, AuthError> {
+        let kid = decode_header(token)?
+            .kid
+            .ok_or(AuthError::TokenError { source: None })?;
+
+        let key = self.key(&kid).await?;
+
+        validation.algorithms = self.algorithms.clone();
+
+        Ok(decode::<T>(token, &key, &validation)?)
+    }
+
+    async fn key(&self, kid: &str) -> Result<DecodingKey, AuthError> {
+        let mut cached = self.cached_keys(kid);
+
+        if cached.is_none() {
+            let _fetching = self.fetching.lock().await;
+
+            // another request may have fetched the keys while this one waited its turn
+            cached = self.cached_keys(kid);
+            if cached.is_none() {
+                cached = Some(self.refresh().await?);
+            }
+        }
+
+        let jwk = cached
+            .as_ref()
+            .and_then(|cached| cached.keys.find(kid))
+            .ok_or_else(|| AuthError::UnknownKeyId { kid: kid.into() })?;
+
+        Ok(DecodingKey::from_jwk(jwk)?)
+    }
+
+    // The cached keys, unless they are due to be fetched again
+    fn cached_keys(&self, kid: &str) -> Option<CachedKeys> {
+        let cache = self.cache.lock().unwrap();
+        let cached = cache.keys.as_ref()?;
+
+        let age = cached.fetched_at.elapsed();
+        let stale = age > self.max_age
+            || (cached.keys.find(kid).is_none() && age > self.min_refetch_interval);
+        let rested = cache
+            .last_attempt
+            .is_none_or(|attempt| attempt.elapsed() > self.min_refetch_interval);
+
+        if stale && rested {
+            return None;
+        }
+
+        Some(cached.clone())
+    }
+
+    // Fetch the keys afresh, falling back to the cached ones if the provider can't be reached
+    async fn refresh(&self) -> Result<CachedKeys, AuthError> {
+        let fetched = self.fetch().await;
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.last_attempt = Some(Instant::now());
+
+        match (fetched, &cache.keys) {
+            (Ok(keys), _) => {
+                let cached = CachedKeys {
+                    keys: Arc::new(keys),
+                    fetched_at: Instant::now(),
+                };
+                cache.keys = Some(cached.clone());
+                Ok(cached)
+            }
+            (Err(error), Some(cached)) => {
+                tracing::warn!("keeping the cached key set, as it couldn't be refreshed: {error}");
+                Ok(cached.clone())
+            }
+            (Err(error), None) => Err(error),
+        }
+    }
+
+    async fn fetch(&self) -> Result<JwkSet, AuthError> {
+        let fetch = async {
+            self.client
+                .get(&self.url)
+                .timeout(self.fetch_timeout)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<JwkSet>()
+                .await
+        };
+
+        fetch.await.map_err(|source| AuthError::KeySetUnavailable {
+            source: Box::new(source),
+        })
+    }
+}
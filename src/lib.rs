@@ -1,10 +1,68 @@
+mod anonymous_session;
 mod auth;
+mod auth_context;
+mod auth_filters;
+mod block_list;
 mod case_insensitive_string_ext;
+mod claim_requirement;
+#[cfg(feature = "compression")]
+mod compression;
 mod error;
+mod expiring_map;
+mod failure_reporter;
+mod hash_cost;
+mod hash_limit;
+mod headers;
+#[cfg(feature = "oidc")]
+mod jwks;
+#[cfg(feature = "test-util")]
+mod null_database;
+mod password_encoding;
+mod password_hasher;
+mod password_manager;
+#[cfg(feature = "password-strength")]
+mod password_strength;
+#[cfg(feature = "phc")]
+mod phc_hasher;
+mod registration_challenge;
 mod routes;
+mod secrets;
+mod settings;
+mod static_jwks;
+mod throttle;
+mod token_store;
 mod types;
+#[cfg(feature = "unicode-normalization")]
+mod unicode_folding;
 
+pub use anonymous_session::*;
 pub use auth::*;
+pub use auth_context::*;
+pub use auth_filters::*;
+pub use block_list::*;
+pub use claim_requirement::*;
 pub use error::*;
+pub use hash_cost::*;
+#[cfg(feature = "oidc")]
+pub use jwks::*;
+#[cfg(feature = "test-util")]
+pub use null_database::*;
+pub use password_encoding::*;
+pub use password_hasher::*;
+pub use password_manager::*;
+#[cfg(feature = "password-strength")]
+pub use password_strength::*;
+#[cfg(feature = "phc")]
+pub use phc_hasher::*;
+pub use registration_challenge::*;
 pub use routes::*;
+pub use secrets::*;
+pub use settings::*;
+pub use static_jwks::*;
+pub use throttle::*;
+pub use token_store::*;
 pub use types::*;
+
+pub use jsonwebtoken::Algorithm;
+#[cfg(feature = "phc")]
+pub use password_hash;
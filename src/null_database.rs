@@ -0,0 +1,114 @@
+use std::{
+    error::Error,
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use serde_json::{Map, Value};
+
+use crate::{
+    auth::{UserDatabase, UserImportStream},
+    types::{HashedPassword, Tenant, UserID, Username},
+};
+
+/// The error every [`NullUserDatabase`] method returns.
+pub const NO_DATABASE_CONFIGURED: &str =
+    "no user database is configured (NullUserDatabase is only a placeholder)";
+
+/// A placeholder user database, for wiring up a server before its real database is ready, and for tests that only
+/// exercise tokens. Every method fails with [`NO_DATABASE_CONFIGURED`], including those that have harmless defaults,
+/// so that nothing silently depends on the missing database.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullUserDatabase;
+
+fn no_database<T>() -> Result<T, Box<dyn Error + Send + Sync>> {
+    Err(NO_DATABASE_CONFIGURED.into())
+}
+
+#[async_trait]
+impl UserDatabase for NullUserDatabase {
+    async fn create_user_if_not_exists(
+        &self,
+        _userid: &UserID,
+        _username: &Username,
+        _hashed_password: &HashedPassword,
+    ) -> Result<UserID, Box<dyn Error + Send + Sync>> {
+        no_database()
+    }
+
+    async fn retreive_user(
+        &self,
+        _username: &Username,
+    ) -> Result<(UserID, HashedPassword), Box<dyn Error + Send + Sync>> {
+        no_database()
+    }
+
+    async fn custom_claims(
+        &self,
+        _userid: &UserID,
+    ) -> Result<Map<String, Value>, Box<dyn Error + Send + Sync>> {
+        no_database()
+    }
+
+    async fn account_created_at(
+        &self,
+        _userid: &UserID,
+    ) -> Result<Option<SystemTime>, Box<dyn Error + Send + Sync>> {
+        no_database()
+    }
+
+    async fn token_lifetime(
+        &self,
+        _userid: &UserID,
+    ) -> Result<Option<Duration>, Box<dyn Error + Send + Sync>> {
+        no_database()
+    }
+
+    async fn update_password(
+        &self,
+        _userid: &UserID,
+        _hashed_password: &HashedPassword,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        no_database()
+    }
+
+    async fn password_history(
+        &self,
+        _userid: &UserID,
+    ) -> Result<Vec<HashedPassword>, Box<dyn Error + Send + Sync>> {
+        no_database()
+    }
+
+    async fn create_tenant_user_if_not_exists(
+        &self,
+        _tenant: &Tenant,
+        _userid: &UserID,
+        _username: &Username,
+        _hashed_password: &HashedPassword,
+    ) -> Result<UserID, Box<dyn Error + Send + Sync>> {
+        no_database()
+    }
+
+    async fn retreive_tenant_user(
+        &self,
+        _tenant: &Tenant,
+        _username: &Username,
+    ) -> Result<(UserID, HashedPassword), Box<dyn Error + Send + Sync>> {
+        no_database()
+    }
+
+    async fn list_users(
+        &self,
+        _after: Option<&UserID>,
+        _limit: usize,
+    ) -> Result<Vec<(UserID, Username)>, Box<dyn Error + Send + Sync>> {
+        no_database()
+    }
+
+    async fn import_users(
+        &self,
+        _users: UserImportStream<'_>,
+    ) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        no_database()
+    }
+}
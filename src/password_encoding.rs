@@ -0,0 +1,19 @@
+use std::error::Error;
+
+use crate::types::HashedPassword;
+
+/// Transforms password hashes on their way into and out of the database, for databases that can't store the
+/// argon2-encoded hash as is, for instance because it lives in a binary column, or must be base64-encoded.
+/// The hash is encoded before it is passed to the `UserDatabase` to be stored (at registration, password change and
+/// import), and decoded whenever it is retrieved (at login and when checking the password history).
+pub trait PasswordEncoding: Send + Sync + 'static {
+    /// Transform an argon2-encoded hash into the form stored by the database.
+    fn encode(&self, hashed_password: HashedPassword) -> HashedPassword;
+
+    /// Recover the argon2-encoded hash from the form stored by the database. This must exactly reverse `encode`,
+    /// or stored passwords can no longer be verified.
+    fn decode(
+        &self,
+        stored_password: HashedPassword,
+    ) -> Result<HashedPassword, Box<dyn Error + Send + Sync>>;
+}
@@ -0,0 +1,13 @@
+/// A password hashing scheme, registered by version in `AuthConfig::password_hashers`, so that stored hashes can be
+/// migrated from one scheme to another over time.
+///
+/// Versioned hashes are stored as `{version}${hash}`, and verified by the hasher registered for their version. As
+/// argon2 hashes already start with a `$`, versions hashed by the built-in argon2 hasher look like `v2$argon2id$...`. Hashes from before versioning was enabled start with a `$`, and are verified by
+/// the built-in argon2 hasher. Version names must not contain a `$`.
+pub trait PasswordHasher: Send + Sync + 'static {
+    /// Hash a password, returning the hash in an encoded string form.
+    fn hash(&self, password: &str) -> String;
+
+    /// Check a password against a hash produced by `hash`. Malformed hashes should fail to verify rather than panic.
+    fn verify(&self, password: &str, hash: &str) -> bool;
+}
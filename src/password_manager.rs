@@ -0,0 +1,314 @@
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use argon2::{
+    password_hash::{self, PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+
+use crate::{
+    auth::{AuthConfig, LegacyPasswordVerifier},
+    error::AuthError,
+    hash_cost::AdaptiveHashCost,
+    password_hasher::PasswordHasher,
+    types::HashedPassword,
+};
+
+/// This crate's password handling (argon2id hashing, versioned hashers, and the password policy) on its own, for use
+/// outside of the auth routes, such as in a command line tool that sets passwords. `Auth` delegates to one built from
+/// its config, so build this from the same `AuthConfig` for the hashes to verify at login, and vice versa.
+#[derive(Clone)]
+pub struct PasswordManager {
+    salt: String,
+    argon2_secret: Option<String>,
+    adaptive_hash_cost: Option<Arc<AdaptiveHashCost>>,
+    hashers: HashMap<String, Arc<dyn PasswordHasher>>,
+    hash_version: Option<String>,
+    legacy_verifier: Option<LegacyPasswordVerifier>,
+    min_entropy: Option<f64>,
+    min_length: Option<usize>,
+    reject_blank: bool,
+}
+
+impl PasswordManager {
+    /// Hash passwords with argon2id, using its default parameters and the given salt, which must be between 8 and 48
+    /// bytes, and accept any password that isn't blank.
+    pub fn new(salt: impl Into<String>) -> Self {
+        Self {
+            salt: salt.into(),
+            argon2_secret: None,
+            adaptive_hash_cost: None,
+            hashers: HashMap::new(),
+            hash_version: None,
+            legacy_verifier: None,
+            min_entropy: None,
+            min_length: None,
+            reject_blank: true,
+        }
+    }
+
+    /// A secret key mixed into every argon2 hash, as `AuthConfig::argon2_secret`.
+    pub fn with_argon2_secret(mut self, secret: impl Into<String>) -> Self {
+        self.argon2_secret = Some(secret.into());
+        self
+    }
+
+    /// Adapt the cost of new argon2 hashes to the load, as `AuthConfig::adaptive_hash_cost`.
+    pub fn with_adaptive_hash_cost(mut self, adaptive_hash_cost: Arc<AdaptiveHashCost>) -> Self {
+        self.adaptive_hash_cost = Some(adaptive_hash_cost);
+        self
+    }
+
+    /// Register a hashing scheme by version, as `AuthConfig::password_hashers`.
+    pub fn with_hasher(
+        mut self,
+        version: impl Into<String>,
+        hasher: Arc<dyn PasswordHasher>,
+    ) -> Self {
+        self.hashers.insert(version.into(), hasher);
+        self
+    }
+
+    /// The version new hashes are created with, as `AuthConfig::password_hash_version`.
+    pub fn with_hash_version(mut self, version: impl Into<String>) -> Self {
+        self.hash_version = Some(version.into());
+        self
+    }
+
+    /// Verify hashes in unrecognised formats, as `AuthConfig::legacy_password_verifier`.
+    pub fn with_legacy_verifier(mut self, verifier: LegacyPasswordVerifier) -> Self {
+        self.legacy_verifier = Some(verifier);
+        self
+    }
+
+    /// The minimum entropy of new passwords, as `AuthConfig::min_password_entropy`.
+    pub fn with_min_entropy(mut self, min_entropy: f64) -> Self {
+        self.min_entropy = Some(min_entropy);
+        self
+    }
+
+    /// The minimum length of new passwords, in characters, as `AuthConfig::min_password_length`.
+    pub fn with_min_length(mut self, min_length: usize) -> Self {
+        self.min_length = Some(min_length);
+        self
+    }
+
+    /// Accept empty and whitespace-only passwords, as `AuthConfig::reject_blank_passwords` when disabled.
+    pub fn allow_blank_passwords(mut self) -> Self {
+        self.reject_blank = false;
+        self
+    }
+
+    /// Hash a password with the current version. Panics if the argon2 parameters are unusable, which
+    /// [`warmup`](Self::warmup) checks for.
+    pub fn hash(&self, password: &str) -> HashedPassword {
+        self.try_hash(password)
+            .expect("argon2 parameters should be checked with Auth::warmup")
+    }
+
+    /// Hash a password with the current version, reporting unusable argon2 parameters rather than panicking.
+    pub fn try_hash(&self, password: &str) -> Result<HashedPassword, AuthError> {
+        let Some(version) = &self.hash_version else {
+            return Ok(HashedPassword(self.try_argon2_hash(password)?));
+        };
+
+        Ok(HashedPassword(match self.hashers.get(version) {
+            Some(hasher) => format!("{version}${}", hasher.hash(password)),
+            // the argon2 hash already starts with a separator
+            None => format!("{version}{}", self.try_argon2_hash(password)?),
+        }))
+    }
+
+    /// Check a password against a hash of any version. Hashes that are in no recognised format fail to verify (with
+    /// a warning) unless a legacy verifier accepts them.
+    pub fn verify(&self, password: &str, hash: &HashedPassword) -> bool {
+        let verified = match hash_version(hash) {
+            Some((version, versioned_hash)) => match self.hashers.get(version) {
+                Some(hasher) => return hasher.verify(password, versioned_hash),
+                None => self.argon2_verify(password, &format!("${versioned_hash}")),
+            },
+            None => self.argon2_verify(password, &hash.0),
+        };
+
+        // argon2 couldn't parse the hash, so it is in some other format entirely
+        verified.unwrap_or_else(|_| match &self.legacy_verifier {
+            Some(verify) => verify(password, hash),
+            None => {
+                tracing::warn!("stored password hash is in an unrecognised format");
+                false
+            }
+        })
+    }
+
+    /// Whether the hash was created with a version other than the current one, or by the built-in argon2 hasher with
+    /// a variant other than argon2id, and should be replaced by a fresh hash of the password once it has been verified.
+    pub fn needs_rehash(&self, hash: &HashedPassword) -> bool {
+        let version = hash_version(hash);
+        if version.map(|(version, _)| version) != self.hash_version.as_deref() {
+            return true;
+        }
+
+        // argon2i hashes, which the built-in hasher made before argon2id became its default
+        let argon2_hash = match version {
+            Some((version, _)) if self.hashers.contains_key(version) => return false,
+            Some((_, versioned_hash)) => versioned_hash,
+            None => hash.0.strip_prefix('$').unwrap_or_default(),
+        };
+        argon2_hash.starts_with("argon2") && !argon2_hash.starts_with("argon2id$")
+    }
+
+    // As try_hash, but on a blocking thread, so that slow hashes don't stall the async runtime's workers
+    pub(crate) async fn hash_blocking(&self, password: &str) -> Result<HashedPassword, AuthError> {
+        let passwords = self.clone();
+        let password = password.to_owned();
+        run_blocking(move || passwords.try_hash(&password)).await
+    }
+
+    // As verify, but on a blocking thread
+    pub(crate) async fn verify_blocking(&self, password: &str, hash: &HashedPassword) -> bool {
+        let passwords = self.clone();
+        let password = password.to_owned();
+        let hash = hash.clone();
+        run_blocking(move || passwords.verify(&password, &hash)).await
+    }
+
+    /// Reject new passwords that don't meet the password policy, with [`AuthError::WeakPassword`].
+    pub fn check_policy(&self, password: &str) -> Result<(), AuthError> {
+        if self.reject_blank && password.trim().is_empty() {
+            return Err(AuthError::WeakPassword {
+                reasons: vec!["blank".into()],
+            });
+        }
+
+        if let Some(min_length) = self.min_length {
+            if password.chars().count() < min_length {
+                return Err(AuthError::WeakPassword {
+                    reasons: vec!["too_short".into()],
+                });
+            }
+        }
+
+        if let Some(min_entropy) = self.min_entropy {
+            if shannon_entropy_bits(password) < min_entropy {
+                return Err(AuthError::WeakPassword {
+                    reasons: vec!["low_entropy".into()],
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Hash and verify a throwaway password with the argon2 parameters, reporting any that can't work.
+    pub fn warmup(&self) -> Result<(), AuthError> {
+        let hash = self.try_argon2_hash("warmup")?;
+        self.argon2_verify("warmup", &hash)?;
+
+        Ok(())
+    }
+
+    fn try_argon2_hash(&self, password: &str) -> Result<String, password_hash::Error> {
+        let adaptive_cost = self.adaptive_hash_cost.as_deref();
+        let time_cost = adaptive_cost.map_or(Params::DEFAULT_T_COST, |adaptive_cost| {
+            adaptive_cost.time_cost()
+        });
+
+        let salt = SaltString::encode_b64(self.salt.as_bytes())?;
+
+        let started = Instant::now();
+        let hash = self
+            .argon2(time_cost)?
+            .hash_password(password.as_bytes(), &salt)?
+            .to_string();
+
+        if let Some(adaptive_cost) = adaptive_cost {
+            adaptive_cost.record_latency(started.elapsed());
+        }
+
+        Ok(hash)
+    }
+
+    // Check a password against an argon2 hash of any variant, with the parameters recorded in the hash
+    fn argon2_verify(&self, password: &str, hash: &str) -> Result<bool, password_hash::Error> {
+        let hash = PasswordHash::new(hash)?;
+
+        match self
+            .argon2(Params::DEFAULT_T_COST)?
+            .verify_password(password.as_bytes(), &hash)
+        {
+            Ok(()) => Ok(true),
+            Err(password_hash::Error::Password) => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn argon2(&self, time_cost: u32) -> Result<Argon2<'_>, argon2::Error> {
+        let params = Params::new(
+            Params::DEFAULT_M_COST,
+            time_cost,
+            Params::DEFAULT_P_COST,
+            None,
+        )?;
+
+        match &self.argon2_secret {
+            Some(secret) => Argon2::new_with_secret(
+                secret.as_bytes(),
+                Algorithm::Argon2id,
+                Version::V0x13,
+                params,
+            ),
+            None => Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params)),
+        }
+    }
+}
+
+impl From<&AuthConfig> for PasswordManager {
+    fn from(config: &AuthConfig) -> Self {
+        Self {
+            salt: config.password_salt.clone(),
+            argon2_secret: config.argon2_secret.clone(),
+            adaptive_hash_cost: config.adaptive_hash_cost.clone(),
+            hashers: config.password_hashers.clone(),
+            hash_version: config.password_hash_version.clone(),
+            legacy_verifier: config.legacy_password_verifier.clone(),
+            min_entropy: config.min_password_entropy,
+            min_length: config.min_password_length,
+            reject_blank: config.reject_blank_passwords,
+        }
+    }
+}
+
+// Split a versioned hash into its version and the hash itself. Unversioned argon2 hashes start with a `$`
+fn hash_version(hash: &HashedPassword) -> Option<(&str, &str)> {
+    if hash.0.starts_with('$') {
+        return None;
+    }
+
+    hash.0.split_once('$')
+}
+
+// Run a hash or verify on tokio's blocking pool, passing on any panic as though it had happened here
+async fn run_blocking<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(work).await {
+        Ok(result) => result,
+        Err(error) => std::panic::resume_unwind(error.into_panic()),
+    }
+}
+
+// The Shannon entropy of the password as a whole, from the frequency of each character within it
+fn shannon_entropy_bits(password: &str) -> f64 {
+    let mut counts = HashMap::new();
+    for c in password.chars() {
+        *counts.entry(c).or_insert(0usize) += 1;
+    }
+
+    let length = counts.values().sum::<usize>() as f64;
+    let bits_per_char = counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / length;
+            -p * p.log2()
+        })
+        .sum::<f64>();
+
+    bits_per_char * length
+}
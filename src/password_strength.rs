@@ -0,0 +1,33 @@
+use serde::Serialize;
+
+/// An estimate of how hard a password would be to guess, with suggestions for making it stronger.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PasswordStrength {
+    /// From 0 (guessable within a thousand attempts) to 4 (needs more than ten billion attempts).
+    pub score: u8,
+    /// Actionable advice for choosing a stronger password. Empty for strong passwords.
+    pub suggestions: Vec<String>,
+}
+
+impl PasswordStrength {
+    /// Estimate the strength of a password, penalising any use of the user's own details, such as their username.
+    pub fn estimate(password: &str, user_inputs: &[&str]) -> Self {
+        let entropy = zxcvbn::zxcvbn(password, user_inputs);
+
+        let suggestions = entropy
+            .feedback()
+            .map(|feedback| {
+                feedback
+                    .suggestions()
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            score: entropy.score().into(),
+            suggestions,
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use password_hash::{rand_core::OsRng, PasswordHash, PasswordVerifier, SaltString};
+
+use crate::password_hasher::PasswordHasher;
+
+/// Adapts a hasher implementing the RustCrypto `password-hash` traits, such as `argon2::Argon2`, `scrypt::Scrypt` or
+/// `pbkdf2::Pbkdf2`, to be registered in `AuthConfig::password_hashers`. Hashes are PHC strings with a random salt.
+///
+/// The PHC format is the one the built-in argon2 hasher already uses, so with no `argon2_secret`, hashes it made still
+/// verify with `PhcHasher::new(argon2::Argon2::default())`, and vice versa.
+pub struct PhcHasher<H: password_hash::PasswordHasher> {
+    hasher: H,
+    params: Option<H::Params>,
+}
+
+impl<H: password_hash::PasswordHasher> PhcHasher<H> {
+    /// Hash new passwords as the hasher is configured, or with its default parameters.
+    pub fn new(hasher: H) -> Self {
+        Self {
+            hasher,
+            params: None,
+        }
+    }
+
+    /// Hash new passwords with the given parameters, such as a number of rounds. Existing hashes are always verified
+    /// with the parameters recorded in them.
+    pub fn with_params(hasher: H, params: H::Params) -> Self {
+        Self {
+            hasher,
+            params: Some(params),
+        }
+    }
+}
+
+impl<H> PasswordHasher for PhcHasher<H>
+where
+    H: password_hash::PasswordHasher + Send + Sync + 'static,
+    H::Params: Send + Sync,
+{
+    /// Hash a password with a fresh random salt. Panics if the hasher's parameters are unusable.
+    fn hash(&self, password: &str) -> String {
+        let salt = SaltString::generate(&mut OsRng);
+
+        let password = password.as_bytes();
+        match &self.params {
+            Some(params) => {
+                self.hasher
+                    .hash_password_customized(password, None, None, params.clone(), &salt)
+            }
+            None => self.hasher.hash_password(password, &salt),
+        }
+        .expect("PHC hasher parameters should be usable")
+        .to_string()
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> bool {
+        PasswordHash::new(hash).is_ok_and(|hash| {
+            self.hasher
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    }
+}
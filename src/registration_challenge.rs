@@ -0,0 +1,17 @@
+use std::{error::Error, net::IpAddr};
+
+use async_trait::async_trait;
+
+/// Checks the response to a challenge that registrations must pass, such as a CAPTCHA token (verified with the
+/// CAPTCHA provider) or a proof-of-work solution, to keep automated signups out. The response is read from the
+/// `challenge_response` field of registration requests, and registrations that fail the check are refused with
+/// [`AuthError::ChallengeFailed`](crate::AuthError::ChallengeFailed) before the password is hashed.
+#[async_trait]
+pub trait RegistrationChallenge: Send + Sync + 'static {
+    /// Whether the response passes the challenge. The response is `None` if the request didn't include one.
+    async fn verify(
+        &self,
+        response: Option<&str>,
+        ip: Option<IpAddr>,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>>;
+}
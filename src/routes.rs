@@ -1,60 +1,718 @@
-use std::{convert::Infallible, sync::Arc};
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use serde::{Deserialize, Serialize};
-use serde_json::json;
+use jsonwebtoken::Algorithm;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Map, Value};
 use tokio::sync::Mutex;
 use uuid::Uuid;
 use warp::{
-    hyper::{Response, StatusCode},
-    path, Filter, Rejection, Reply,
+    filters::BoxedFilter,
+    hyper::{
+        header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE, LOCATION, PRAGMA, SET_COOKIE},
+        Method, Response, StatusCode,
+    },
+    path,
+    path::FullPath,
+    reject::Reject,
+    Filter, Rejection, Reply,
 };
 
 use crate::{
-    auth::{Auth, AuthInternal},
+    anonymous_session::ANONYMOUS_SESSION_COOKIE,
+    auth::{
+        AlreadyAuthenticatedResponse, Auth, AuthConfig, AuthCore, AuthInternal, ErrorFormat,
+        IssuedToken, SessionRequest, AMR_EMAIL, AMR_PASSWORD,
+    },
+    auth_context::AuthContext,
     case_insensitive_string_ext::CaseInsensitiveStringExt,
+    claim_requirement::ClaimRequirement,
     error::AuthError,
-    types::{HashedPassword, UserID, Username},
+    headers::{basic_credentials, optional_configured_header, optional_cookie, optional_header},
+    types::{Claims, Principal, Role, Tenant, UserID, Username},
 };
 
+#[cfg(feature = "password-strength")]
+use crate::password_strength::PasswordStrength;
+
+/// Assemble the auth routes enabled by the config. Login, logout, password change and refresh are always available,
+/// registration unless disabled, username availability, the current user and the public config only if enabled, and
+/// magic link login, password reset and email verification only if their senders are configured.
 pub fn build_api_route_filter(
     auth: &Auth,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    let register = path!("users" / "register")
-        .and(warp::post())
-        .and(warp::body::json())
-        .and(with_auth_state(auth.internal.clone()))
-        .and_then(user_register);
+    let config = &auth.config;
 
     let login = path!("users" / "login")
-        .and(warp::post())
-        .and(warp::body::json())
+        .and(method_is(Method::POST))
+        .and(login_input(config))
+        .and(optional_cookie(ANONYMOUS_SESSION_COOKIE.into()))
+        .and(optional_header(DEVICE_ID_HEADER))
+        .and(client_cert_thumbprint(config))
+        .and(warp::addr::remote())
         .and(with_auth_state(auth.internal.clone()))
         .and_then(user_login);
 
-    register.or(login)
+    let mut routes = vec![];
+
+    if let Some(response) = &config.already_authenticated_response {
+        let login = path!("users" / "login")
+            .and(method_is(Method::POST))
+            .and(already_authenticated(auth, response.clone()));
+
+        routes.push(boxed_route(login));
+
+        if config.registration_enabled {
+            let register = path!("users" / "register")
+                .and(method_is(Method::POST))
+                .and(already_authenticated(auth, response.clone()));
+
+            routes.push(boxed_route(register));
+        }
+    }
+
+    routes.push(boxed_route(login));
+
+    if config.registration_enabled {
+        let register = path!("users" / "register")
+            .and(method_is(Method::POST))
+            .and(request_body(config.accept_form_bodies))
+            .and(warp::addr::remote())
+            .and(with_auth_state(auth.internal.clone()))
+            .and_then(user_register);
+
+        routes.push(boxed_route(register));
+    }
+
+    if config.username_availability_enabled {
+        let available = path!("users" / "available")
+            .and(method_is(Method::GET))
+            .and(warp::query())
+            .and(with_auth_state(auth.internal.clone()))
+            .and_then(user_available);
+
+        routes.push(boxed_route(available));
+    }
+
+    if config.me_route_enabled {
+        let me = path!("users" / "me")
+            .and(method_is(Method::GET))
+            .and(with_verified_claims(auth))
+            .and(with_auth_state(auth.internal.clone()))
+            .and_then(user_me);
+
+        routes.push(boxed_route(me));
+    }
+
+    if config.public_config_enabled {
+        let public_config = Arc::new(PublicConfigResponse::new(config));
+
+        let public_config = path!("auth" / "config")
+            .and(method_is(Method::GET))
+            .map(move || warp::reply::json(&*public_config));
+
+        routes.push(boxed_route(public_config));
+    }
+
+    if let Some(requirements) = &config.revoke_all_requirements {
+        let revoke_all = path!("auth" / "revoke-all")
+            .and(method_is(Method::POST))
+            .and(with_auth_requiring(auth, requirements))
+            .and(with_auth_state(auth.internal.clone()))
+            .and_then(revoke_all_tokens);
+
+        routes.push(boxed_route(revoke_all));
+    }
+
+    let change_password = path!("users" / "password")
+        .and(method_is(Method::POST))
+        .and(request_body(config.accept_form_bodies))
+        .and(with_auth_state(auth.internal.clone()))
+        .and_then(user_change_password);
+
+    routes.push(boxed_route(change_password));
+
+    let refresh = path!("users" / "refresh")
+        .and(method_is(Method::POST))
+        .and(request_token(auth))
+        .and(optional_header(DEVICE_ID_HEADER))
+        .and(client_cert_thumbprint(config))
+        .and(with_auth_state(auth.internal.clone()))
+        .and_then(user_refresh);
+
+    let logout = path!("users" / "logout")
+        .and(method_is(Method::POST))
+        .and(request_token(auth))
+        .and(with_auth_state(auth.internal.clone()))
+        .and_then(user_logout);
+
+    routes.push(boxed_route(refresh));
+    routes.push(boxed_route(logout));
+
+    if config.magic_link_sender.is_some() {
+        let magic_link = path!("users" / "magic-link")
+            .and(method_is(Method::POST))
+            .and(request_body(config.accept_form_bodies))
+            .and(with_auth_state(auth.internal.clone()))
+            .and_then(user_magic_link);
+
+        let magic_login = path!("users" / "magic-login")
+            .and(method_is(Method::GET))
+            .and(warp::query())
+            .and(with_auth_state(auth.internal.clone()))
+            .and_then(user_magic_login);
+
+        routes.push(boxed_route(magic_link));
+        routes.push(boxed_route(magic_login));
+    }
+
+    if config.password_reset_sender.is_some() {
+        let forgot = path!("users" / "forgot")
+            .and(method_is(Method::POST))
+            .and(request_body(config.accept_form_bodies))
+            .and(with_auth_state(auth.internal.clone()))
+            .and_then(user_forgot_password);
+
+        let reset = path!("users" / "reset")
+            .and(method_is(Method::POST))
+            .and(request_body(config.accept_form_bodies))
+            .and(with_auth_state(auth.internal.clone()))
+            .and_then(user_reset_password);
+
+        routes.push(boxed_route(forgot));
+        routes.push(boxed_route(reset));
+    }
+
+    if config.verification_sender.is_some() {
+        let verify = path!("users" / "verify")
+            .and(method_is(Method::POST))
+            .and(request_body(config.accept_form_bodies))
+            .and(with_auth_state(auth.internal.clone()))
+            .and_then(user_verify);
+
+        let resend = path!("users" / "verify" / "resend")
+            .and(method_is(Method::POST))
+            .and(request_body(config.accept_form_bodies))
+            .and(with_auth_state(auth.internal.clone()))
+            .and_then(user_resend_verification);
+
+        routes.push(boxed_route(verify));
+        routes.push(boxed_route(resend));
+    }
+
+    let response_headers = config.response_headers.clone();
+
+    let routes = routes
+        .into_iter()
+        .reduce(|routes, route| routes.or(route).unify().boxed())
+        .unwrap();
+
+    trailing_slash(config.accept_trailing_slash)
+        .and(routes)
+        .map(move |reply: Box<dyn Reply>| {
+            let mut response = reply.into_response();
+            let headers = response.headers_mut();
+
+            for name in response_headers.keys() {
+                if !headers.contains_key(name) {
+                    for value in response_headers.get_all(name) {
+                        headers.append(name, value.clone());
+                    }
+                }
+            }
+
+            response
+        })
 }
 
+/// The field of the current tracing span that the authenticated user id is recorded in.
+pub const USER_ID_SPAN_FIELD: &str = "user_id";
+
+/// Authenticate the request by its auth token, extracting the user id. Requests without a token are rejected with
+/// [`AuthError::MissingToken`], and those with an invalid one with [`AuthError::TokenError`].
+///
+/// The user id is recorded in the `user_id` field of the current tracing span (if it declares one), so that the logs
+/// of downstream handlers carry it; for instance, declare `user_id = tracing::field::Empty` in the span created by
+/// `warp::trace`. The same goes for every other filter that authenticates the request.
 pub fn with_auth(auth: &Auth) -> impl Filter<Extract = (UserID,), Error = Rejection> + Clone {
-    warp::header("authorization")
+    with_verified_claims(auth).map(|user_id: UserID, _claims: Claims| user_id)
+}
+
+/// Authenticate the request as with [`with_auth`] if it carries an auth token, extracting the user id if the token
+/// is valid. Unlike [`with_auth`], requests without a valid token are never rejected, for routes that serve anonymous
+/// users too.
+pub fn with_optional_auth(
+    auth: &Auth,
+) -> impl Filter<Extract = (Option<UserID>,), Error = Infallible> + Clone {
+    with_auth(auth)
+        .map(Some)
+        .or(warp::any().map(|| None))
+        .unify()
+}
+
+/// Authenticate the request as with [`with_auth`], extracting the username alongside the user id. The username is
+/// only available if `AuthConfig::username_claim` is enabled (or an external provider sets `preferred_username`),
+/// and reflects the username at the time the token was issued.
+pub fn with_principal(
+    auth: &Auth,
+) -> impl Filter<Extract = (Principal,), Error = Rejection> + Clone {
+    with_verified_claims(auth).map(|user_id: UserID, claims: Claims| Principal {
+        user_id,
+        username: claims.preferred_username.map(Username),
+    })
+}
+
+/// Authenticate the request as with [`with_auth`], and load the user's roles from
+/// [`UserDatabase::roles`](crate::UserDatabase::roles) in the same step, for apps that keep roles in the database
+/// rather than in token claims.
+pub fn with_auth_roles(
+    auth: &Auth,
+) -> impl Filter<Extract = (UserID, Vec<Role>), Error = Rejection> + Clone {
+    with_verified_claims(auth)
         .and(with_auth_state(auth.internal.clone()))
-        .and_then(user_auth_check)
+        .and_then(
+            |user_id: UserID, _claims: Claims, auth: Arc<Mutex<AuthInternal>>| async move {
+                let core = auth.lock().await.core();
+                let roles = core.roles(&user_id).await?;
+                Ok::<_, Rejection>((user_id, roles))
+            },
+        )
+        .untuple_one()
 }
 
-pub async fn handle_auth_errors(err: Rejection) -> Result<impl Reply, Rejection> {
-    if let Some(auth_error) = err.find::<AuthError>() {
-        let (status, message) = match &auth_error {
-            AuthError::UsernameAlreadyTaken => {
-                (StatusCode::CONFLICT, "a user with that name already exists")
+/// Authenticate the request as with [`with_auth`], then load the user's current custom claims from
+/// [`UserDatabase::custom_claims`](crate::UserDatabase::custom_claims) and let the predicate decide whether to allow
+/// the request, for authorization that depends on live state (such as whether a subscription is still active) rather
+/// than the snapshot taken when the token was issued. Requests the predicate denies are rejected with
+/// [`AuthError::AccessDenied`], carrying its reason.
+pub fn with_auth_guarded<P, F>(
+    auth: &Auth,
+    predicate: P,
+) -> impl Filter<Extract = (UserID,), Error = Rejection> + Clone
+where
+    P: Fn(UserID, Map<String, Value>) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = Result<(), String>> + Send,
+{
+    with_auth(auth)
+        .and(with_auth_state(auth.internal.clone()))
+        .and_then(move |user_id: UserID, auth: Arc<Mutex<AuthInternal>>| {
+            let predicate = predicate.clone();
+            async move {
+                let core = auth.lock().await.core();
+                let claims = core.custom_claims(&user_id).await?;
+
+                predicate(user_id.clone(), claims)
+                    .await
+                    .map_err(|reason| warp::reject::custom(AuthError::AccessDenied { reason }))?;
+
+                Ok::<_, Rejection>(user_id)
+            }
+        })
+}
+
+/// Authenticate the request as with [`with_auth`], then run the loader to fetch whatever else the handler needs to
+/// know about the user, such as their organisation or plan, extracting it alongside the user id. Loader errors are
+/// treated as database errors, failing the request with [`AuthError::DatabaseError`].
+pub fn with_auth_loading<L, F, C, E>(
+    auth: &Auth,
+    loader: L,
+) -> impl Filter<Extract = (UserID, C), Error = Rejection> + Clone
+where
+    L: Fn(UserID) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = Result<C, E>> + Send,
+    C: Send,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    with_auth(auth)
+        .and_then(move |user_id: UserID| {
+            let loader = loader.clone();
+            async move {
+                let context = loader(user_id.clone())
+                    .await
+                    .map_err(|error| warp::reject::custom(AuthError::from(error.into())))?;
+
+                Ok::<_, Rejection>((user_id, context))
+            }
+        })
+        .untuple_one()
+}
+
+/// Authenticate the request as with [`with_auth`], extracting an [`AuthContext`] in place of the user id. Handlers
+/// that reply with data belonging to the user should wrap their reply with [`AuthContext::finish`], which adds the
+/// headers the authentication implies (see the `profile` route in the simple example).
+pub fn with_auth_context(
+    auth: &Auth,
+) -> impl Filter<Extract = (AuthContext,), Error = Rejection> + Clone {
+    with_principal(auth).map(|principal: Principal| AuthContext {
+        user_id: principal.user_id,
+        username: principal.username,
+    })
+}
+
+/// Authenticate the request as with [`with_auth`], and additionally require the token's claims to satisfy
+/// every one of the given requirements. Requests that fail a requirement are rejected with
+/// [`AuthError::InsufficientPermissions`].
+pub fn with_auth_requiring(
+    auth: &Auth,
+    requirements: &[ClaimRequirement],
+) -> impl Filter<Extract = (UserID,), Error = Rejection> + Clone {
+    let requirements = Arc::new(requirements.to_vec());
+
+    with_verified_claims(auth).and_then(move |user_id: UserID, claims: Claims| {
+        let requirements = requirements.clone();
+        async move {
+            let claim_values = json!(claims);
+            if requirements
+                .iter()
+                .all(|requirement| requirement.is_satisfied_by(&claim_values))
+            {
+                Ok(user_id)
+            } else {
+                Err(warp::reject::custom(AuthError::InsufficientPermissions))
             }
-            AuthError::LoginFailed | AuthError::TokenError { .. } => {
-                (StatusCode::FORBIDDEN, "access denied")
+        }
+    })
+}
+
+/// Extract the next path segment as a user id, and authenticate the request as with [`with_auth`], requiring
+/// the token to belong to that same user. This restricts routes such as `/users/{id}/data` to the user they
+/// belong to. Requests for any other user's id are rejected with [`AuthError::InsufficientPermissions`].
+pub fn with_auth_matching_param(
+    auth: &Auth,
+) -> impl Filter<Extract = (UserID,), Error = Rejection> + Clone {
+    warp::path::param::<String>().and(with_auth(auth)).and_then(
+        |param: String, user_id: UserID| async move {
+            if user_id.0 == param {
+                Ok(user_id)
+            } else {
+                Err(warp::reject::custom(AuthError::InsufficientPermissions))
+            }
+        },
+    )
+}
+
+/// Authenticate the request as with [`with_auth`], and additionally require the user's account to have existed
+/// for at least `min_age`, as a brake on abuse by freshly created accounts. Relies on the `created_at` claim
+/// stamped at login from [`UserDatabase::account_created_at`](crate::UserDatabase::account_created_at); tokens
+/// without it are rejected. Requests from newer accounts are rejected with [`AuthError::AccountTooNew`].
+pub fn with_account_age(
+    auth: &Auth,
+    min_age: Duration,
+) -> impl Filter<Extract = (UserID,), Error = Rejection> + Clone {
+    with_verified_claims(auth).and_then(move |user_id: UserID, claims: Claims| async move {
+        let created_at = claims
+            .created_at
+            .ok_or(AuthError::AccountTooNew)
+            .map_err(warp::reject::custom)?;
+
+        let age = SystemTime::now()
+            .duration_since(UNIX_EPOCH + Duration::from_secs(created_at))
+            .unwrap_or_default();
+
+        if age < min_age {
+            return Err(warp::reject::custom(AuthError::AccountTooNew));
+        }
+
+        Ok(user_id)
+    })
+}
+
+/// Authenticate the request as with [`with_auth`], and additionally require the token's `acr` claim to show at least
+/// the given authentication assurance level, such as [`ACR_MULTI_FACTOR`](crate::ACR_MULTI_FACTOR) for sensitive
+/// routes. Tokens with a lower (or no) level are rejected with [`AuthError::InsufficientAuthentication`], telling the
+/// client to step up by logging in again with more factors.
+pub fn with_acr(
+    auth: &Auth,
+    min_level: u32,
+) -> impl Filter<Extract = (UserID,), Error = Rejection> + Clone {
+    with_verified_claims(auth).and_then(move |user_id: UserID, claims: Claims| async move {
+        match claims.acr_level() {
+            Some(level) if level >= min_level => Ok(user_id),
+            _ => Err(warp::reject::custom(AuthError::InsufficientAuthentication)),
+        }
+    })
+}
+
+/// Authenticate the request as with [`with_auth`], then run the handler with the user id, giving up if it takes
+/// longer than `timeout`, so that hung handlers can't tie up connections indefinitely. Requests that time out are
+/// rejected with [`AuthError::HandlerTimeout`], which is reported as 504 Gateway Timeout. The handler takes the place
+/// of the `then` that would otherwise follow [`with_auth`].
+pub fn with_auth_timeout<H, F, R>(
+    auth: &Auth,
+    timeout: Duration,
+    handler: H,
+) -> impl Filter<Extract = (R,), Error = Rejection> + Clone
+where
+    H: Fn(UserID) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = R> + Send,
+    R: Reply,
+{
+    with_auth(auth).and_then(move |user_id: UserID| {
+        let handler = handler.clone();
+        async move {
+            tokio::time::timeout(timeout, handler(user_id))
+                .await
+                .map_err(|_| warp::reject::custom(AuthError::HandlerTimeout))
+        }
+    })
+}
+
+/// The header [`with_csrf_protection`] expects the CSRF token in.
+pub const CSRF_TOKEN_HEADER: &str = "x-csrf-token";
+
+/// Authenticate the request as with [`with_auth`], and additionally require tokens that have a CSRF token bound to
+/// them (see `AuthConfig::csrf_cookie`) to be accompanied by that same CSRF token in the `X-CSRF-Token` header. Use
+/// this on state-changing routes when the auth token is delivered in a cookie, which browsers attach even to
+/// requests forged by other sites. Requests without a matching CSRF token are rejected with
+/// [`AuthError::CsrfTokenMismatch`].
+pub fn with_csrf_protection(
+    auth: &Auth,
+) -> impl Filter<Extract = (UserID,), Error = Rejection> + Clone {
+    with_verified_claims(auth)
+        .and(optional_header(CSRF_TOKEN_HEADER))
+        .and_then(
+            |user_id: UserID, claims: Claims, csrf_token: Option<String>| async move {
+                match claims.csrf {
+                    Some(expected) if csrf_token.as_deref() != Some(expected.as_str()) => {
+                        Err(warp::reject::custom(AuthError::CsrfTokenMismatch))
+                    }
+                    _ => Ok(user_id),
+                }
+            },
+        )
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: &'static str,
+    /// The request field the error relates to, such as the identifier that is already taken.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CodeDetailErrorResponse {
+    pub code: &'static str,
+    pub detail: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+// RFC 7807 problem details
+#[derive(Debug, Serialize)]
+pub struct ProblemResponse {
+    #[serde(rename = "type")]
+    pub problem_type: &'static str,
+    pub title: &'static str,
+    pub detail: &'static str,
+    pub status: u16,
+    // an extension member, as RFC 7807 allows
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+/// Recover from the rejections of the auth routes and filters, replying with a JSON error body of the default
+/// [`ErrorFormat::Error`] shape. Use [`auth_error_handler`] to reply in the shape set by `AuthConfig::error_format`.
+pub async fn handle_auth_errors(err: Rejection) -> Result<impl Reply, Rejection> {
+    handle_auth_errors_as(&ErrorFormat::Error, StatusCode::CONFLICT, err).await
+}
+
+/// Recover from the rejections of the auth routes and filters, replying with a JSON error body in the shape set by
+/// `AuthConfig::error_format`, and with the statuses set by the config (such as `AuthConfig::username_taken_status`).
+/// Pass the result to `recover` in place of [`handle_auth_errors`].
+pub fn auth_error_handler(
+    auth: &Auth,
+) -> impl Fn(
+    Rejection,
+) -> Pin<Box<dyn Future<Output = Result<warp::reply::Response, Rejection>> + Send>>
+       + Clone
+       + Send
+       + Sync
+       + 'static {
+    let format = auth.config.error_format.clone();
+    let username_taken_status = auth.config.username_taken_status;
+
+    move |err| {
+        let format = format.clone();
+        Box::pin(async move {
+            handle_auth_errors_as(&format, username_taken_status, err)
+                .await
+                .map(Reply::into_response)
+        })
+    }
+}
+
+async fn handle_auth_errors_as(
+    format: &ErrorFormat,
+    username_taken_status: StatusCode,
+    err: Rejection,
+) -> Result<warp::reply::Response, Rejection> {
+    // a wrong method takes precedence, as the request did match the path of an auth route
+    let wrong_method = err
+        .find::<WrongMethod>()
+        .map(|_| AuthError::MethodNotAllowed);
+
+    if let Some(auth_error) = wrong_method.as_ref().or_else(|| err.find::<AuthError>()) {
+        let (status, code, message) = match &auth_error {
+            AuthError::MissingToken => (
+                StatusCode::UNAUTHORIZED,
+                "authentication_required",
+                "authentication required",
+            ),
+            AuthError::AlreadyExists { .. } => (
+                username_taken_status,
+                "already_exists",
+                "an account with that identifier already exists",
+            ),
+            AuthError::AccountLocked => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "account_locked",
+                "too many failed login attempts, try again later",
+            ),
+            AuthError::TooManyRequests => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "too_many_requests",
+                "too many requests, try again later",
+            ),
+            AuthError::RegistrationThrottled => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "registration_throttled",
+                "too many registrations, try again later",
+            ),
+            AuthError::TooManySessions => (
+                StatusCode::CONFLICT,
+                "too_many_sessions",
+                "too many active sessions",
+            ),
+            AuthError::PasswordChangeConflict => (
+                StatusCode::CONFLICT,
+                "password_change_conflict",
+                "the password was changed concurrently, try again",
+            ),
+            AuthError::LoginFailed
+            | AuthError::TokenError { .. }
+            | AuthError::UnknownKeyId { .. }
+            | AuthError::TokenRejected { .. }
+            | AuthError::AccessDenied { .. }
+            | AuthError::InsufficientPermissions => {
+                (StatusCode::FORBIDDEN, "access_denied", "access denied")
             }
+            AuthError::Blocked => (StatusCode::FORBIDDEN, "blocked", "access denied"),
+            AuthError::CsrfTokenMismatch => (
+                StatusCode::FORBIDDEN,
+                "csrf_mismatch",
+                "missing or mismatched CSRF token",
+            ),
+            AuthError::ChallengeFailed => (
+                StatusCode::FORBIDDEN,
+                "challenge_failed",
+                "the registration challenge was not passed",
+            ),
+            AuthError::VerificationRequired => (
+                StatusCode::FORBIDDEN,
+                "verification_required",
+                "the email address must be verified first",
+            ),
+            AuthError::AccountTooNew => (
+                StatusCode::FORBIDDEN,
+                "account_too_new",
+                "the account is too new to perform this action",
+            ),
+            // as RFC 9470 step-up authentication, so that clients know to log in again more strongly
+            AuthError::InsufficientAuthentication => (
+                StatusCode::UNAUTHORIZED,
+                "insufficient_user_authentication",
+                "stronger authentication is required",
+            ),
+            AuthError::MethodNotAllowed => (
+                StatusCode::METHOD_NOT_ALLOWED,
+                "method_not_allowed",
+                "method not allowed",
+            ),
+            AuthError::MissingIdentifier => (
+                StatusCode::BAD_REQUEST,
+                "missing_identifier",
+                "a login identifier is required",
+            ),
+            AuthError::TenantRequired => (
+                StatusCode::BAD_REQUEST,
+                "tenant_required",
+                "a tenant is required",
+            ),
+            AuthError::AudienceNotAllowed { .. } => (
+                StatusCode::BAD_REQUEST,
+                "audience_not_allowed",
+                "tokens can't be issued for the requested audience",
+            ),
+            AuthError::InvalidUsername { .. } => (
+                StatusCode::BAD_REQUEST,
+                "invalid_username",
+                "the username is not allowed",
+            ),
+            AuthError::WeakPassword { .. } => (
+                StatusCode::BAD_REQUEST,
+                "weak_password",
+                "the password does not meet the requirements",
+            ),
+            AuthError::HandlerTimeout => (
+                StatusCode::GATEWAY_TIMEOUT,
+                "timeout",
+                "the request timed out",
+            ),
+            AuthError::DatabaseTimeout
+            | AuthError::KeySetUnavailable { .. }
+            | AuthError::Overloaded
+            | AuthError::StoreFull => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "service_unavailable",
+                "the service is temporarily unavailable",
+            ),
             _ => (
                 StatusCode::INTERNAL_SERVER_ERROR,
+                "internal_error",
                 "an unknown error has occurred",
             ),
         };
-        return Ok(warp::reply::with_status(message, status));
+
+        let field = match auth_error {
+            AuthError::AlreadyExists { field } => Some(field.clone()),
+            _ => None,
+        };
+
+        let body = match format {
+            ErrorFormat::Error => warp::reply::json(&ErrorResponse {
+                error: message,
+                field,
+            }),
+            ErrorFormat::CodeDetail => warp::reply::json(&CodeDetailErrorResponse {
+                code,
+                detail: message,
+                field,
+            }),
+            ErrorFormat::Problem => warp::reply::json(&ProblemResponse {
+                problem_type: "about:blank",
+                title: status.canonical_reason().unwrap_or_default(),
+                detail: message,
+                status: status.as_u16(),
+                field,
+            }),
+        };
+
+        let mut response = warp::reply::with_status(body, status).into_response();
+        if *format == ErrorFormat::Problem {
+            response.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/problem+json"),
+            );
+        }
+
+        return Ok(response);
     }
 
     Err(err)
@@ -62,78 +720,933 @@ pub async fn handle_auth_errors(err: Rejection) -> Result<impl Reply, Rejection>
 
 #[derive(Debug, Deserialize)]
 pub struct RegisterQuery {
+    #[serde(default)]
     pub username: String,
     pub password: String,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    // the response to the registration challenge, if one is configured
+    #[serde(default)]
+    pub challenge_response: Option<String>,
+    // any other fields, one of which may be the login identifier
+    #[serde(flatten)]
+    pub other_fields: Map<String, Value>,
 }
 
 #[derive(Debug, Serialize)]
-pub struct RegisterResponse {}
+pub struct RegisterResponse {
+    // set if the user must verify their email before they can login
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub verification_pending: bool,
+    #[cfg(feature = "password-strength")]
+    pub password_score: u8,
+    #[cfg(feature = "password-strength")]
+    pub suggestions: Vec<String>,
+}
 
 async fn user_register(
     input: RegisterQuery,
+    remote: Option<SocketAddr>,
     auth: Arc<Mutex<AuthInternal>>,
 ) -> Result<impl Reply, Rejection> {
-    let auth = auth.lock().await;
+    // hashing is slow, so it is done without holding the lock, which is only retaken to issue tokens
+    let core = auth.lock().await.core();
+
+    let ip = remote.map(|remote| remote.ip());
+    core.check_registration_allowed(ip, input.challenge_response.as_deref())
+        .await?;
+
+    let tenant = core.tenant(input.tenant)?;
+    let username = core.login_identifier(input.username, &input.other_fields)?;
+    core.check_not_reserved(&username)?;
+
+    core.passwords().check_policy(&input.password)?;
+
+    #[cfg(feature = "password-strength")]
+    let strength = {
+        let strength = PasswordStrength::estimate(&input.password, &[&username.0]);
+        if core
+            .config()
+            .min_password_score
+            .is_some_and(|min_score| strength.score < min_score)
+        {
+            Err(AuthError::WeakPassword {
+                reasons: vec!["too_guessable".into()],
+            })?;
+        }
+        strength
+    };
 
     let new_user_id = UserID(Uuid::new_v4().to_string());
-    let username = Username(input.username);
-    let hashed_password = HashedPassword(auth.hash(&input.password));
+    let hashed_password = core.hash_password(&input.password).await?;
 
-    let user_id = auth
-        .create_user_if_not_exists(&new_user_id, &username, &hashed_password)
+    let user_id = core
+        .create_user_if_not_exists(tenant.as_ref(), &new_user_id, &username, &hashed_password)
         .await?;
 
     if !user_id.0.eq(&new_user_id.0) {
-        Err(AuthError::UsernameAlreadyTaken)?;
+        Err(AuthError::AlreadyExists {
+            field: core.config().login_identifier.field.clone(),
+        })?;
+    }
+
+    if let Some(sender) = &core.config().verification_sender {
+        let token =
+            auth.lock()
+                .await
+                .generate_verification_token(&user_id, &username, tenant.as_ref())?;
+        sender(&username, &token);
     }
 
-    Ok(Response::builder().body(json!(RegisterResponse {}).to_string()))
+    // the account isn't usable until it is verified, so the registration has only been accepted
+    let verification_pending = core.config().require_verification;
+    let status = if verification_pending {
+        StatusCode::ACCEPTED
+    } else {
+        StatusCode::OK
+    };
+
+    Ok(Response::builder().status(status).body(
+        json!(RegisterResponse {
+            verification_pending,
+            #[cfg(feature = "password-strength")]
+            password_score: strength.score,
+            #[cfg(feature = "password-strength")]
+            suggestions: strength.suggestions,
+        })
+        .to_string(),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AvailabilityQuery {
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    // any other fields, one of which may be the login identifier
+    #[serde(flatten)]
+    pub other_fields: Map<String, Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AvailabilityResponse {
+    pub available: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MeResponse {
+    pub user_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    pub roles: Vec<String>,
+}
+
+// Describe the authenticated user. Roles come from the database if it has any for the user, and otherwise from the
+// token's roles claim
+async fn user_me(
+    user_id: UserID,
+    claims: Claims,
+    auth: Arc<Mutex<AuthInternal>>,
+) -> Result<impl Reply, Rejection> {
+    let core = auth.lock().await.core();
+    let mut roles = core.roles(&user_id).await?;
+    if roles.is_empty() {
+        roles = claims.roles();
+    }
+
+    Ok(warp::reply::json(&MeResponse {
+        user_id: user_id.0,
+        username: claims.preferred_username,
+        roles: roles.into_iter().map(|role| role.0).collect(),
+    }))
+}
+
+// Report whether a username is free to register
+async fn user_available(
+    input: AvailabilityQuery,
+    auth: Arc<Mutex<AuthInternal>>,
+) -> Result<impl Reply, Rejection> {
+    let core = auth.lock().await.core();
+
+    let tenant = core.tenant(input.tenant)?;
+
+    let available = match core
+        .retreive_user(
+            tenant.as_ref(),
+            &core.login_identifier(input.username, &input.other_fields)?,
+        )
+        .await
+    {
+        Ok(_) => false,
+        Err(AuthError::DatabaseTimeout) => Err(AuthError::DatabaseTimeout)?,
+        // the database reports a missing user as an error
+        Err(_) => true,
+    };
+
+    Ok(warp::reply::json(&AvailabilityResponse { available }))
 }
 
+#[derive(Debug, Serialize)]
+pub struct PublicConfigResponse {
+    pub issuer: String,
+    /// In seconds.
+    pub token_lifetime: u64,
+    pub algorithms: Vec<Algorithm>,
+    pub methods: LoginMethods,
+    pub password_policy: PasswordPolicy,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoginMethods {
+    pub password: bool,
+    pub basic_auth: bool,
+    pub magic_link: bool,
+    pub password_reset: bool,
+    pub two_factor: bool,
+    pub oidc: bool,
+}
+
+/// The requirements new passwords must meet, so that clients can check passwords before submitting them. Requirements
+/// that aren't configured are `null`.
+#[derive(Debug, Serialize)]
+pub struct PasswordPolicy {
+    /// In characters.
+    pub min_length: Option<usize>,
+    /// In bits, as `AuthConfig::min_password_entropy`.
+    pub min_entropy: Option<f64>,
+    /// The minimum estimated strength, from 0 to 4, as `AuthConfig::min_password_score`.
+    pub min_score: Option<u8>,
+    pub reject_blank: bool,
+    /// How many previous passwords may not be reused, in addition to the current one.
+    pub history_length: usize,
+}
+
+impl PublicConfigResponse {
+    // Only ever copy settings that are safe to publish, never secrets or the salt
+    fn new(config: &AuthConfig) -> Self {
+        Self {
+            issuer: config.auth_token_issuer.clone(),
+            token_lifetime: config.auth_token_lifetime.as_secs(),
+            algorithms: config.auth_token_algorithms.clone(),
+            methods: LoginMethods {
+                password: true,
+                basic_auth: config.accept_basic_auth_login,
+                magic_link: config.magic_link_sender.is_some(),
+                password_reset: config.password_reset_sender.is_some(),
+                // there is no second factor yet
+                two_factor: false,
+                #[cfg(feature = "oidc")]
+                oidc: config.external_jwks.is_some(),
+                #[cfg(not(feature = "oidc"))]
+                oidc: false,
+            },
+            password_policy: PasswordPolicy {
+                min_length: config.min_password_length,
+                min_entropy: config.min_password_entropy,
+                #[cfg(feature = "password-strength")]
+                min_score: config.min_password_score,
+                #[cfg(not(feature = "password-strength"))]
+                min_score: None,
+                reject_blank: config.reject_blank_passwords,
+                history_length: config.password_history_length,
+            },
+        }
+    }
+}
+
+/// The header clients may send a device id in at login, to bind the issued token to that device. Bound tokens may only
+/// be refreshed by requests carrying the same device id, so that a token stolen from one device can't be kept alive
+/// from another.
+pub const DEVICE_ID_HEADER: &str = "x-device-id";
+
 #[derive(Debug, Deserialize)]
 pub struct LoginQuery {
+    #[serde(default)]
     pub username: String,
     pub password: String,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// The audience to issue the token for, which must be one of `AuthConfig::login_audiences`.
+    #[serde(default)]
+    pub audience: Option<String>,
+    // any other fields, one of which may be the login identifier
+    #[serde(flatten)]
+    pub other_fields: Map<String, Value>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct LoginResponse {
-    pub token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub csrf_token: Option<String>,
 }
 
 async fn user_login(
     input: LoginQuery,
+    anonymous_session: Option<String>,
+    device_id: Option<String>,
+    cert_thumbprint: Option<String>,
+    remote: Option<SocketAddr>,
     auth: Arc<Mutex<AuthInternal>>,
 ) -> Result<impl Reply, Rejection> {
-    let auth = auth.lock().await;
+    // the credentials are checked without holding the lock, which is only taken to issue the token
+    let core = auth.lock().await.core();
+
+    let tenant = core.tenant(input.tenant)?;
+    let audience = core.login_audience(input.audience)?;
+    let ip = remote.map(|remote| remote.ip());
+
+    core.check_not_blocked(None, ip).await?;
 
-    let username = Username(input.username);
+    let started = Instant::now();
 
-    let (user_id, hashed_password) = auth.retreive_user(&username).await?;
+    let username = core.login_identifier(input.username, &input.other_fields)?;
+
+    let result = core
+        .check_credentials(tenant.as_ref(), &username, &input.password)
+        .await;
+
+    core.pad_login_duration(started).await;
+
+    let user_id = result?;
+
+    core.check_not_blocked(Some(&user_id), None).await?;
+    core.check_verified(&user_id).await?;
+
+    let token = core
+        .issue_session_token(
+            &auth,
+            &user_id,
+            Some(&username),
+            tenant.as_ref(),
+            &[AMR_PASSWORD.into()],
+            SessionRequest {
+                device_id: device_id.as_deref(),
+                cert_thumbprint: cert_thumbprint.as_deref(),
+                audience: audience.as_deref(),
+                ..Default::default()
+            },
+        )
+        .await?;
 
-    if !auth.verify_hash(&input.password, &hashed_password) {
-        Err(AuthError::LoginFailed)?;
+    if let (Some(linker), Some(session_id)) =
+        (&core.config().anonymous_session_linker, anonymous_session)
+    {
+        linker(&session_id, &user_id);
     }
 
-    let token = auth.generate_token(&user_id)?;
+    Ok(token_response(&core, token))
+}
 
-    Ok(Response::builder().body(json!(LoginResponse { token }).to_string()))
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordQuery {
+    #[serde(default)]
+    pub username: String,
+    pub password: String,
+    pub new_password: String,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    // any other fields, one of which may be the login identifier
+    #[serde(flatten)]
+    pub other_fields: Map<String, Value>,
 }
 
-// Unwrap the bearer token and validate it
-async fn user_auth_check(
+// Replace the user's password, given their current one, invalidating their existing tokens, and log them in afresh
+async fn user_change_password(
+    input: ChangePasswordQuery,
+    auth: Arc<Mutex<AuthInternal>>,
+) -> Result<impl Reply, Rejection> {
+    // hashing is slow, so it is done without holding the lock, which is only retaken to replace the user's tokens
+    let core = auth.lock().await.core();
+
+    let tenant = core.tenant(input.tenant)?;
+
+    let username = core.login_identifier(input.username, &input.other_fields)?;
+
+    let user_id = core
+        .check_credentials(tenant.as_ref(), &username, &input.password)
+        .await?;
+    core.check_verified(&user_id).await?;
+    // read as soon as the user is known, so that a change made by any other request from here on is detected
+    let version = core.password_version(&user_id).await?;
+
+    core.passwords().check_policy(&input.new_password)?;
+    core.check_password_reuse(&user_id, Some(&input.password), &input.new_password)
+        .await?;
+
+    let hashed_password = core.hash_password(&input.new_password).await?;
+    core.update_password_if_version(&user_id, &hashed_password, version.as_deref())
+        .await?;
+
+    auth.lock().await.invalidate_tokens(&user_id);
+
+    let token = core
+        .issue_session_token(
+            &auth,
+            &user_id,
+            Some(&username),
+            tenant.as_ref(),
+            &[AMR_PASSWORD.into()],
+            SessionRequest::default(),
+        )
+        .await?;
+
+    Ok(token_response(&core, token))
+}
+
+// Exchange a current (or recently expired) token for a fresh one
+async fn user_refresh(
+    token: String,
+    device_id: Option<String>,
+    cert_thumbprint: Option<String>,
+    auth: Arc<Mutex<AuthInternal>>,
+) -> Result<impl Reply, Rejection> {
+    // the token store and database are reached without holding the lock, which is only taken to check the token and
+    // record the new one
+    let core = auth.lock().await.core();
+    let token = core.resolve_reference(&token).await?;
+
+    let claims = {
+        let mut auth = auth.lock().await;
+        let claims = auth.verify_refreshable_token(&token)?;
+        auth.check_device_binding(&claims, device_id.as_deref())?;
+        auth.check_cert_binding(&claims, cert_thumbprint.as_deref())?;
+        // the token is replaced by the refreshed one, so that it can't be refreshed again, however sessions are limited
+        auth.revoke_token(&claims);
+        claims
+    };
+
+    let user_id = UserID(claims.sub);
+    core.check_not_blocked(Some(&user_id), None).await?;
+
+    let username = claims.preferred_username.map(Username);
+    let tenant = claims.tenant.map(Tenant);
+
+    // a refreshed token carries the same assurance, and is for the same audience, as the login it descends from
+    let amr = claims.amr.unwrap_or_default();
+    let audience = claims.extra.get("aud").and_then(Value::as_str);
+
+    let token = core
+        .issue_session_token(
+            &auth,
+            &user_id,
+            username.as_ref(),
+            tenant.as_ref(),
+            &amr,
+            SessionRequest {
+                device_id: device_id.as_deref(),
+                cert_thumbprint: cert_thumbprint.as_deref(),
+                audience,
+                deadline: claims.deadline,
+            },
+        )
+        .await?;
+
+    Ok(token_response(&core, token))
+}
+
+// Revoke the token the request was authenticated with, and clear the auth cookie if there is one
+async fn user_logout(
     token: String,
     auth: Arc<Mutex<AuthInternal>>,
-) -> Result<UserID, Rejection> {
-    let token = token
+) -> Result<impl Reply, Rejection> {
+    let core = auth.lock().await.core();
+    let presented = core.read_presented_token(&token).await?;
+
+    let mut auth = auth.lock().await;
+    let claims = auth.verify_session_token(presented)?;
+    auth.revoke_token(&claims);
+
+    let mut response = Response::builder().status(StatusCode::NO_CONTENT);
+    let config = auth.config();
+    if let Some(name) = config.token_delivery.cookie() {
+        response = response.header(SET_COOKIE, config.cookie_attributes.set_cookie(name, "", 0));
+
+        if let Some(csrf_cookie) = &config.csrf_cookie {
+            response = response.header(
+                SET_COOKIE,
+                config
+                    .cookie_attributes
+                    .set_readable_cookie(csrf_cookie, "", 0),
+            );
+        }
+    }
+
+    Ok(response.body(String::new()))
+}
+
+// Revoke every token issued so far, at the request of an admin
+async fn revoke_all_tokens(
+    user_id: UserID,
+    auth: Arc<Mutex<AuthInternal>>,
+) -> Result<impl Reply, Rejection> {
+    auth.lock()
+        .await
+        .revoke_tokens_issued_before(SystemTime::now());
+
+    tracing::warn!("every token was revoked by {}", user_id.0);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MagicLinkQuery {
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    // any other fields, one of which may be the login identifier
+    #[serde(flatten)]
+    pub other_fields: Map<String, Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MagicLinkResponse {}
+
+async fn user_magic_link(
+    input: MagicLinkQuery,
+    auth: Arc<Mutex<AuthInternal>>,
+) -> Result<impl Reply, Rejection> {
+    // the user is looked up without holding the lock, which is only taken to sign the token
+    let core = auth.lock().await.core();
+
+    let sender = core
+        .config()
+        .magic_link_sender
+        .clone()
+        .ok_or_else(warp::reject::not_found)?;
+
+    let tenant = core.tenant(input.tenant)?;
+    let username = core.login_identifier(input.username, &input.other_fields)?;
+
+    // respond the same way whether or not the user exists, so this route can't be used to discover usernames
+    if let Ok((user_id, _)) = core.retreive_user(tenant.as_ref(), &username).await {
+        let token = auth
+            .lock()
+            .await
+            .generate_magic_token(&user_id, &username, tenant.as_ref())?;
+        sender(&username, &token);
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .body(json!(MagicLinkResponse {}).to_string()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MagicLoginQuery {
+    pub token: String,
+}
+
+async fn user_magic_login(
+    input: MagicLoginQuery,
+    auth: Arc<Mutex<AuthInternal>>,
+) -> Result<impl Reply, Rejection> {
+    let (subject, core) = {
+        let mut auth = auth.lock().await;
+        (auth.consume_magic_token(&input.token)?, auth.core())
+    };
+
+    core.check_not_blocked(Some(&subject.user_id), None).await?;
+
+    let token = core
+        .issue_session_token(
+            &auth,
+            &subject.user_id,
+            subject.username.as_ref(),
+            subject.tenant.as_ref(),
+            &[AMR_EMAIL.into()],
+            SessionRequest::default(),
+        )
+        .await?;
+
+    Ok(token_response(&core, token))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordQuery {
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    // any other fields, one of which may be the login identifier
+    #[serde(flatten)]
+    pub other_fields: Map<String, Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ForgotPasswordResponse {}
+
+async fn user_forgot_password(
+    input: ForgotPasswordQuery,
+    auth: Arc<Mutex<AuthInternal>>,
+) -> Result<impl Reply, Rejection> {
+    // the user is looked up and the response padded without holding the lock, which is only taken to sign the token
+    let core = auth.lock().await.core();
+
+    let sender = core
+        .config()
+        .password_reset_sender
+        .clone()
+        .ok_or_else(warp::reject::not_found)?;
+
+    let tenant = core.tenant(input.tenant)?;
+    let username = core.login_identifier(input.username, &input.other_fields)?;
+
+    let started = Instant::now();
+
+    // respond the same way, after the same work, whether or not the user exists, so this route can't be used to
+    // discover usernames. Only the sending differs, which the sender should do in the background
+    let user = core.retreive_user(tenant.as_ref(), &username).await;
+    let user_id = match &user {
+        Ok((user_id, _)) => user_id.clone(),
+        Err(_) => UserID(Uuid::new_v4().to_string()),
+    };
+    let token = auth
+        .lock()
+        .await
+        .generate_reset_token(&user_id, &username, tenant.as_ref())?;
+    if user.is_ok() {
+        sender(&username, &token);
+    }
+
+    core.pad_login_duration(started).await;
+
+    Ok(Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .body(json!(ForgotPasswordResponse {}).to_string()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordQuery {
+    pub token: String,
+    pub new_password: String,
+}
+
+// Replace the user's password, given a password reset token, invalidating their existing tokens, and log them in afresh
+async fn user_reset_password(
+    input: ResetPasswordQuery,
+    auth: Arc<Mutex<AuthInternal>>,
+) -> Result<impl Reply, Rejection> {
+    let (subject, core) = {
+        let auth = auth.lock().await;
+        (auth.check_reset_token(&input.token)?, auth.core())
+    };
+    let user_id = &subject.user_id;
+
+    // hashing is slow, so it is done without holding the lock, which is only retaken to replace the user's tokens
+    core.passwords().check_policy(&input.new_password)?;
+    core.check_password_reuse(user_id, None, &input.new_password)
+        .await?;
+
+    let hashed_password = core.hash_password(&input.new_password).await?;
+
+    // the token is used up before the password is stored, so that no two requests can both store one with it, but
+    // given back if storing the password fails, so that the user can try again with the same link
+    auth.lock().await.mark_token_used(&subject)?;
+    if let Err(error) = core.update_password(user_id, &hashed_password).await {
+        auth.lock().await.unmark_token_used(&subject);
+        return Err(error.into());
+    }
+
+    auth.lock().await.invalidate_tokens(user_id);
+
+    let token = core
+        .issue_session_token(
+            &auth,
+            user_id,
+            subject.username.as_ref(),
+            subject.tenant.as_ref(),
+            &[AMR_EMAIL.into()],
+            SessionRequest::default(),
+        )
+        .await?;
+
+    Ok(token_response(&core, token))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyQuery {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerifyResponse {}
+
+async fn user_verify(
+    input: VerifyQuery,
+    auth: Arc<Mutex<AuthInternal>>,
+) -> Result<impl Reply, Rejection> {
+    let (subject, core) = {
+        let auth = auth.lock().await;
+        (auth.check_verification_token(&input.token)?, auth.core())
+    };
+
+    // the token is only used up once the user is marked verified, so that a failure leaves the link usable. Marking
+    // the user verified twice does no harm, so a request racing this one may do so too before finding it used
+    core.mark_verified(&subject.user_id).await?;
+    auth.lock().await.mark_token_used(&subject)?;
+
+    Ok(Response::builder().body(json!(VerifyResponse {}).to_string()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResendVerificationQuery {
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    // any other fields, one of which may be the login identifier
+    #[serde(flatten)]
+    pub other_fields: Map<String, Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResendVerificationResponse {}
+
+async fn user_resend_verification(
+    input: ResendVerificationQuery,
+    auth: Arc<Mutex<AuthInternal>>,
+) -> Result<impl Reply, Rejection> {
+    // the user is looked up without holding the lock, which is only taken to sign the token
+    let core = auth.lock().await.core();
+
+    let sender = core
+        .config()
+        .verification_sender
+        .clone()
+        .ok_or_else(warp::reject::not_found)?;
+
+    let tenant = core.tenant(input.tenant)?;
+    let username = core.login_identifier(input.username, &input.other_fields)?;
+
+    core.check_resend_limit(tenant.as_ref(), &username).await?;
+
+    // respond the same way whether the user doesn't exist, is already verified, or is sent a new token, so this route
+    // can't be used to discover usernames or who has verified
+    if let Ok((user_id, _)) = core.retreive_user(tenant.as_ref(), &username).await {
+        if !core.is_verified(&user_id).await? {
+            let token = auth.lock().await.generate_verification_token(
+                &user_id,
+                &username,
+                tenant.as_ref(),
+            )?;
+            sender(&username, &token);
+        }
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .body(json!(ResendVerificationResponse {}).to_string()))
+}
+
+// Respond as configured to requests that are already authenticated, passing on any others to the route itself
+fn already_authenticated(
+    auth: &Auth,
+    response: AlreadyAuthenticatedResponse,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_optional_auth(auth).and_then(move |user_id: Option<UserID>| {
+        let response = response.clone();
+        async move {
+            if user_id.is_none() {
+                // not found, so that the rejection of the route itself takes precedence
+                return Err(warp::reject());
+            }
+
+            let reply = match response {
+                AlreadyAuthenticatedResponse::Ok => Response::builder()
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(json!({ "already_authenticated": true }).to_string()),
+                AlreadyAuthenticatedResponse::Redirect(location) => Response::builder()
+                    .status(StatusCode::SEE_OTHER)
+                    .header(LOCATION, location)
+                    .body(String::new()),
+            };
+
+            Ok(reply)
+        }
+    })
+}
+
+// Return a newly issued token to the client as configured
+fn token_response(
+    core: &AuthCore,
+    issued: IssuedToken,
+) -> Result<Response<String>, warp::http::Error> {
+    let IssuedToken {
+        token,
+        expires_at,
+        csrf_token,
+    } = issued;
+    let config = core.config();
+    let delivery = &config.token_delivery;
+
+    // as OAuth 2.0 requires of token responses, so that no cache keeps the token
+    let mut response = Response::builder()
+        .header(CACHE_CONTROL, "no-store")
+        .header(PRAGMA, "no-cache");
+    if let Some(header) = delivery.header() {
+        response = response.header(header, &token);
+    }
+    if let Some(name) = delivery.cookie() {
+        // the cookie lasts as long as the token, which may have a lifetime of its own or have been capped
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let max_age = expires_at.saturating_sub(now);
+        response = response.header(
+            SET_COOKIE,
+            config.cookie_attributes.set_cookie(name, &token, max_age),
+        );
+
+        if let (Some(csrf_cookie), Some(csrf_token)) = (&config.csrf_cookie, &csrf_token) {
+            response = response.header(
+                SET_COOKIE,
+                config
+                    .cookie_attributes
+                    .set_readable_cookie(csrf_cookie, csrf_token, max_age),
+            );
+        }
+    }
+
+    let token = delivery.in_body().then_some(token);
+
+    response.body(json!(LoginResponse { token, csrf_token }).to_string())
+}
+
+fn with_verified_claims(
+    auth: &Auth,
+) -> impl Filter<Extract = (UserID, Claims), Error = Rejection> + Clone {
+    request_token(auth)
+        .and(client_cert_thumbprint(&auth.config))
+        .and(warp::addr::remote())
+        .and(with_auth_state(auth.internal.clone()))
+        .and_then(user_auth_check)
+        .untuple_one()
+}
+
+// Extract the token a request is authenticated with: the bearer token from the authorization header, or in
+// cookie mode, the auth cookie if there is no authorization header
+fn request_token(auth: &Auth) -> BoxedFilter<(String,)> {
+    match auth.config.token_delivery.cookie() {
+        None => optional_header("authorization")
+            .and_then(|authorization: Option<String>| async move {
+                bearer_token(authorization.ok_or(AuthError::MissingToken)?)
+            })
+            .boxed(),
+        Some(name) => optional_header("authorization")
+            .and(optional_cookie(name.to_owned()))
+            .and_then(
+                |authorization: Option<String>, cookie: Option<String>| async move {
+                    match (authorization, cookie) {
+                        (Some(authorization), _) => bearer_token(authorization),
+                        (None, Some(cookie)) => Ok(cookie),
+                        (None, None) => Err(warp::reject::custom(AuthError::MissingToken)),
+                    }
+                },
+            )
+            .boxed(),
+    }
+}
+
+// Extract the thumbprint of the client's certificate, if the TLS terminator is configured to pass it on
+fn client_cert_thumbprint(config: &AuthConfig) -> BoxedFilter<(Option<String>,)> {
+    optional_configured_header(config.client_cert_thumbprint_header.clone())
+}
+
+fn bearer_token(authorization: String) -> Result<String, Rejection> {
+    let token = authorization
         .strip_prefix_ignore_ascii_case("bearer ")
         .ok_or(AuthError::TokenError { source: None })?;
 
-    let auth = auth.lock().await;
+    Ok(token.to_owned())
+}
+
+// Validate the token
+async fn user_auth_check(
+    token: String,
+    cert_thumbprint: Option<String>,
+    remote: Option<SocketAddr>,
+    auth: Arc<Mutex<AuthInternal>>,
+) -> Result<(UserID, Claims), Rejection> {
+    let core = auth.lock().await.core();
+    let ip = remote.map(|remote| remote.ip());
+
+    let verified = async {
+        // the provider's keys or the stored token may need fetching, which is done without holding the lock
+        let presented = core.read_presented_token(&token).await?;
+
+        let claims = {
+            let auth = auth.lock().await;
+            let claims = auth.verify_session_token(presented)?;
+            auth.check_cert_binding(&claims, cert_thumbprint.as_deref())?;
+            claims
+        };
+
+        let user_id = claims
+            .user_id(&core.config().user_id_claim)
+            .ok_or(AuthError::TokenError { source: None })?;
+
+        core.check_not_blocked(Some(&user_id), ip).await?;
+
+        Ok::<_, AuthError>((user_id, claims))
+    }
+    .await;
+
+    // queued for a worker of its own, so that a slow callback doesn't hold up the response
+    if let Err(error) = &verified {
+        core.report_auth_failure(error, ip);
+    }
+
+    let (user_id, claims) = verified?;
+    let seen_at = auth.lock().await.note_last_seen(&user_id);
+    if let (Some(seen_at), Some(recorder)) = (seen_at, &core.config().last_seen_recorder) {
+        recorder(&user_id, seen_at);
+    }
 
-    let claims = auth.verify_token(token)?;
+    // only the user id is recorded, never the token
+    tracing::Span::current().record(USER_ID_SPAN_FIELD, user_id.0.as_str());
 
-    Ok(UserID(claims.sub))
+    Ok((user_id, claims))
+}
+
+// Rejection for a wrong method on an auth route, reported as AuthError::MethodNotAllowed. It is kept apart from
+// AuthError so that handle_auth_errors can pick it out from among the rejections of other routes
+#[derive(Debug)]
+struct WrongMethod;
+
+impl Reject for WrongMethod {}
+
+// Match the expected method once the path is known. Wrong methods reject with a custom rejection rather than
+// warp's own MethodNotAllowed, which is outranked by rejections from any other route in the chain
+fn method_is(expected: Method) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::method()
+        .and_then(move |method: Method| {
+            let allowed = method == expected;
+            async move {
+                if allowed {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(WrongMethod))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+// Reject paths with a trailing slash as not found, unless they are accepted (as warp's path filters do by default)
+fn trailing_slash(accepted: bool) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path::full()
+        .and_then(move |path: FullPath| async move {
+            if !accepted && path.as_str().len() > 1 && path.as_str().ends_with('/') {
+                return Err(warp::reject::not_found());
+            }
+            Ok(())
+        })
+        .untuple_one()
 }
 
 // functor that adds a reference to the internal auth state into the filter chain
@@ -142,3 +1655,73 @@ fn with_auth_state(
 ) -> impl Filter<Extract = (Arc<Mutex<AuthInternal>>,), Error = Infallible> + Clone {
     warp::any().map(move || auth.clone())
 }
+
+// Deserialize the request body as a form if enabled and the client sent one, otherwise as JSON
+fn request_body<T: DeserializeOwned + Send>(
+    accept_form_bodies: bool,
+) -> impl Filter<Extract = (T,), Error = Rejection> + Clone {
+    let form = optional_header("content-type")
+        .and_then(move |content_type: Option<String>| async move {
+            let is_form = content_type.is_some_and(|content_type| {
+                content_type
+                    .strip_prefix_ignore_ascii_case("application/x-www-form-urlencoded")
+                    .is_some()
+            });
+
+            if is_form && accept_form_bodies {
+                Ok(())
+            } else {
+                Err(warp::reject())
+            }
+        })
+        .untuple_one()
+        .and(warp::body::form());
+
+    form.or(warp::body::json()).unify()
+}
+
+// Take the login credentials from a Basic authorization header if enabled and the client sent one, otherwise from
+// the request body
+fn login_input(config: &AuthConfig) -> BoxedFilter<(LoginQuery,)> {
+    let body = request_body(config.accept_form_bodies);
+
+    if !config.accept_basic_auth_login {
+        return body.boxed();
+    }
+
+    let identifier_field = config.login_identifier.field.clone();
+
+    optional_header("authorization")
+        .and_then(move |authorization: Option<String>| {
+            let identifier_field = identifier_field.clone();
+            async move {
+                let (identifier, password) = authorization
+                    .and_then(basic_credentials)
+                    .ok_or_else(warp::reject)?;
+
+                // the identifier goes in whichever field it is configured to be read from
+                let mut other_fields = Map::new();
+                other_fields.insert(identifier_field, identifier.clone().into());
+
+                Ok::<_, Rejection>(LoginQuery {
+                    username: identifier,
+                    password,
+                    tenant: None,
+                    audience: None,
+                    other_fields,
+                })
+            }
+        })
+        .or(body)
+        .unify()
+        .boxed()
+}
+
+// Type-erase a route so that a varying set of routes can be combined
+fn boxed_route<R: Reply + 'static>(
+    route: impl Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+) -> BoxedFilter<(Box<dyn Reply>,)> {
+    route
+        .map(|reply: R| Box::new(reply) as Box<dyn Reply>)
+        .boxed()
+}
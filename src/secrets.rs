@@ -0,0 +1,28 @@
+/// Supplies the secrets used to sign and verify auth tokens, so that they can be rotated at runtime, for instance
+/// from a secret manager such as Vault or AWS Secrets Manager. The provider is consulted for every token issued or
+/// verified, so implementations should cache secrets in memory and refresh them in the background.
+pub trait SecretProvider: Send + Sync + 'static {
+    /// The secret newly issued tokens are signed with.
+    fn signing_secret(&self) -> String;
+
+    /// The secrets tokens are accepted with, most likely first. During a rotation, this should include the previous
+    /// secret alongside the new one until tokens signed with the previous secret have expired.
+    fn verification_secrets(&self) -> Vec<String> {
+        vec![self.signing_secret()]
+    }
+
+    /// An id for the signing secret, stamped into the `kid` header of newly issued tokens, so that verifiers can tell
+    /// which key a token was signed with. If `None`, `AuthConfig::auth_token_key_id` is used instead.
+    fn signing_key_id(&self) -> Option<String> {
+        None
+    }
+}
+
+/// A secret that never changes. Used for the `auth_token_secret` when no other provider is configured.
+pub struct StaticSecret(pub String);
+
+impl SecretProvider for StaticSecret {
+    fn signing_secret(&self) -> String {
+        self.0.clone()
+    }
+}
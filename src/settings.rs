@@ -0,0 +1,207 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::auth::{AuthConfig, UserDatabase};
+
+/// The serializable settings of an [`AuthConfig`], for loading it from a config file or the environment with serde.
+/// Durations are given as human-friendly strings such as `"15m"` or `"1h30m"` (see [`parse_duration`]), and secrets
+/// either inline or as the name of an environment variable to read them from. Settings that are left out keep the
+/// defaults of [`AuthConfig::new`]. Build the config with [`AuthSettings::into_config`], supplying the database.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthSettings {
+    pub password_salt: SecretSetting,
+    pub auth_token_issuer: String,
+    pub auth_token_secret: SecretSetting,
+    #[serde(deserialize_with = "duration")]
+    pub auth_token_lifetime: Duration,
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub max_token_lifetime: Option<Duration>,
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub refresh_grace_period: Option<Duration>,
+    #[serde(default)]
+    pub auth_token_audience: Option<String>,
+    #[serde(default)]
+    pub argon2_secret: Option<SecretSetting>,
+    #[serde(default)]
+    pub identifier_hash_key: Option<SecretSetting>,
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub db_operation_timeout: Option<Duration>,
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub min_login_duration: Option<Duration>,
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub magic_link_lifetime: Option<Duration>,
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub password_reset_lifetime: Option<Duration>,
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub verification_lifetime: Option<Duration>,
+    #[serde(default)]
+    pub registration_enabled: Option<bool>,
+    #[serde(default)]
+    pub accept_form_bodies: Option<bool>,
+    #[serde(default)]
+    pub accept_trailing_slash: Option<bool>,
+    #[serde(default)]
+    pub max_sessions: Option<usize>,
+    #[serde(default)]
+    pub password_history_length: Option<usize>,
+    #[serde(default)]
+    pub min_password_entropy: Option<f64>,
+    #[serde(default)]
+    pub min_password_length: Option<usize>,
+    #[serde(default)]
+    pub reject_blank_passwords: Option<bool>,
+}
+
+impl AuthSettings {
+    /// Build the config from these settings, reading any secrets from the environment, and using the given database.
+    pub fn into_config(
+        self,
+        database_connection: Arc<dyn UserDatabase>,
+    ) -> Result<AuthConfig, SettingsError> {
+        let mut config = AuthConfig::new(
+            self.password_salt.resolve()?,
+            self.auth_token_issuer,
+            self.auth_token_secret.resolve()?,
+            self.auth_token_lifetime,
+            database_connection,
+        );
+
+        config.max_token_lifetime = self.max_token_lifetime;
+        config.auth_token_audience = self.auth_token_audience;
+        config.argon2_secret = self
+            .argon2_secret
+            .map(|secret| secret.resolve())
+            .transpose()?;
+        config.identifier_hash_key = self
+            .identifier_hash_key
+            .map(|secret| secret.resolve())
+            .transpose()?;
+        config.db_operation_timeout = self.db_operation_timeout;
+        config.min_login_duration = self.min_login_duration;
+        config.max_sessions = self.max_sessions;
+        config.min_password_entropy = self.min_password_entropy;
+        config.min_password_length = self.min_password_length;
+
+        if let Some(refresh_grace_period) = self.refresh_grace_period {
+            config.refresh_grace_period = refresh_grace_period;
+        }
+        if let Some(magic_link_lifetime) = self.magic_link_lifetime {
+            config.magic_link_lifetime = magic_link_lifetime;
+        }
+        if let Some(password_reset_lifetime) = self.password_reset_lifetime {
+            config.password_reset_lifetime = password_reset_lifetime;
+        }
+        if let Some(verification_lifetime) = self.verification_lifetime {
+            config.verification_lifetime = verification_lifetime;
+        }
+        if let Some(registration_enabled) = self.registration_enabled {
+            config.registration_enabled = registration_enabled;
+        }
+        if let Some(accept_form_bodies) = self.accept_form_bodies {
+            config.accept_form_bodies = accept_form_bodies;
+        }
+        if let Some(accept_trailing_slash) = self.accept_trailing_slash {
+            config.accept_trailing_slash = accept_trailing_slash;
+        }
+        if let Some(password_history_length) = self.password_history_length {
+            config.password_history_length = password_history_length;
+        }
+        if let Some(reject_blank_passwords) = self.reject_blank_passwords {
+            config.reject_blank_passwords = reject_blank_passwords;
+        }
+
+        Ok(config)
+    }
+}
+
+/// A secret setting, given either inline as a string, or as `{ env = "NAME" }` to read it from the named environment
+/// variable, so that secrets can stay out of config files. Its value is never printed by `Debug`.
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+pub enum SecretSetting {
+    Value(String),
+    Env { env: String },
+}
+
+impl SecretSetting {
+    /// The value of the secret, read from the environment if need be.
+    pub fn resolve(&self) -> Result<String, SettingsError> {
+        match self {
+            SecretSetting::Value(value) => Ok(value.clone()),
+            SecretSetting::Env { env } => {
+                std::env::var(env).map_err(|_| SettingsError::MissingSecret { name: env.clone() })
+            }
+        }
+    }
+}
+
+impl fmt::Debug for SecretSetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretSetting::Value(_) => f.write_str("SecretSetting(<redacted>)"),
+            SecretSetting::Env { env } => write!(f, "SecretSetting(env {env})"),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SettingsError {
+    #[error("the environment variable {name} holding a secret is not set")]
+    MissingSecret { name: String },
+}
+
+/// Parse a human-friendly duration, made up of whole numbers with units of `ms`, `s`, `m`, `h` or `d`, such as `"90s"`
+/// or `"1h30m"`.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let invalid =
+        || format!("invalid duration {input:?}, expected a form such as \"15m\" or \"1h30m\"");
+
+    let mut rest = input.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut total = Duration::ZERO;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let (number, unit_and_rest) = rest.split_at(digits);
+        let number = number.parse::<u64>().map_err(|_| invalid())?;
+
+        let unit_length = unit_and_rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(unit_and_rest.len());
+        let (unit, remainder) = unit_and_rest.split_at(unit_length);
+
+        let unit = match unit {
+            "ms" => Duration::from_millis(1),
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(60 * 60),
+            "d" => Duration::from_secs(24 * 60 * 60),
+            _ => return Err(invalid()),
+        };
+
+        total = unit
+            .checked_mul(number.try_into().map_err(|_| invalid())?)
+            .and_then(|duration| total.checked_add(duration))
+            .ok_or_else(invalid)?;
+        rest = remainder;
+    }
+
+    Ok(total)
+}
+
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let input = String::deserialize(deserializer)?;
+    parse_duration(&input).map_err(serde::de::Error::custom)
+}
+
+fn optional_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    duration(deserializer).map(Some)
+}
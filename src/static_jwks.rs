@@ -0,0 +1,58 @@
+use jsonwebtoken::{
+    decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, TokenData, Validation,
+};
+use serde::de::DeserializeOwned;
+
+use crate::error::AuthError;
+
+/// A fixed JSON Web Key Set, embedded at startup, used to verify tokens without fetching keys from their issuer. This
+/// is the offline counterpart to `RemoteJwks`, for verifiers (such as edge nodes) that can't reach a JWKS endpoint.
+/// The key is selected by the `kid` in each token's header.
+pub struct StaticJwks {
+    keys: JwkSet,
+    algorithms: Vec<Algorithm>,
+}
+
+impl StaticJwks {
+    /// Verify tokens against the given key set.
+    pub fn new(keys: JwkSet) -> Self {
+        Self {
+            keys,
+            algorithms: vec![Algorithm::RS256],
+        }
+    }
+
+    /// Verify tokens against the key set in the given JSON, in the format served from a `jwks_uri`.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        Ok(Self::new(serde_json::from_str(json)?))
+    }
+
+    /// The signing algorithms to accept. Defaults to RS256 only.
+    pub fn with_algorithms(mut self, algorithms: Vec<Algorithm>) -> Self {
+        self.algorithms = algorithms;
+        self
+    }
+
+    pub(crate) fn decode<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        mut validation: Validation,
+    ) -> Result<TokenData<T>, AuthError> {
+        let kid = decode_header(token)?
+            .kid
+            .ok_or(AuthError::TokenError { source: None })?;
+
+        let jwk = self
+            .keys
+            .find(&kid)
+            .ok_or(AuthError::UnknownKeyId { kid })?;
+
+        validation.algorithms = self.algorithms.clone();
+
+        Ok(decode::<T>(
+            token,
+            &DecodingKey::from_jwk(jwk)?,
+            &validation,
+        )?)
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    error::Error,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+
+use crate::{error::AuthError, expiring_map::ExpiringMap};
+
+/// Stores counts of recent attempts (such as failed logins) by key, for throttling. The default
+/// [`MemoryThrottleStore`] only counts attempts made against this process; deployments running several instances
+/// should share a store between them, for instance one backed by Redis, so that a limit can't be evaded by
+/// spreading attempts across instances.
+#[async_trait]
+pub trait ThrottleStore: Send + Sync + 'static {
+    /// Record an attempt against the key, and return the number of attempts recorded within the current window.
+    /// The window starts at the first attempt, and the count starts over once it has passed.
+    async fn record_attempt(
+        &self,
+        key: &str,
+        window: Duration,
+    ) -> Result<u32, Box<dyn Error + Send + Sync>>;
+
+    /// The number of attempts recorded against the key within the current window.
+    async fn attempts(&self, key: &str) -> Result<u32, Box<dyn Error + Send + Sync>>;
+
+    /// Forget any attempts recorded against the key.
+    async fn reset(&self, key: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Hold attempts against at most this many keys at once, as set by `AuthConfig::max_store_entries`, refusing
+    /// attempts against any more rather than forgetting earlier ones. Stores kept outside this process are bounded by
+    /// their own means, and needn't implement this.
+    fn limit_entries(&self, _max_entries: usize) {}
+
+    /// The number of keys attempts are held against, for `Auth::store_sizes`, if the store is kept in this process.
+    fn entries(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Counts attempts in the memory of this process.
+#[derive(Default)]
+pub struct MemoryThrottleStore {
+    // attempt counts, until the end of their window
+    attempts: Mutex<ExpiringMap<u32, Instant>>,
+}
+
+#[async_trait]
+impl ThrottleStore for MemoryThrottleStore {
+    async fn record_attempt(
+        &self,
+        key: &str,
+        window: Duration,
+    ) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        let now = Instant::now();
+        let mut attempts = self.attempts.lock().unwrap();
+        attempts.forget_expired(now);
+
+        if let Some(count) = attempts.get_mut(key) {
+            *count += 1;
+            return Ok(*count);
+        }
+
+        // attempts that can't be counted are refused, rather than let through uncounted
+        if !attempts.has_room_for(key) {
+            return Err(Box::new(AuthError::StoreFull));
+        }
+        attempts.insert(key.into(), 1, now + window);
+
+        Ok(1)
+    }
+
+    async fn attempts(&self, key: &str) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        let attempts = self.attempts.lock().unwrap();
+
+        Ok(match attempts.get_with_expiry(key) {
+            Some((count, window_end)) if window_end > Instant::now() => *count,
+            _ => 0,
+        })
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.attempts.lock().unwrap().remove(key);
+
+        Ok(())
+    }
+
+    fn limit_entries(&self, max_entries: usize) {
+        self.attempts
+            .lock()
+            .unwrap()
+            .set_capacity(Some(max_entries));
+    }
+
+    fn entries(&self) -> Option<usize> {
+        Some(self.attempts.lock().unwrap().len())
+    }
+}
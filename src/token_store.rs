@@ -0,0 +1,86 @@
+use std::{error::Error, sync::Mutex, time::SystemTime};
+
+use async_trait::async_trait;
+
+use crate::{error::AuthError, expiring_map::ExpiringMap};
+
+/// Stores issued session tokens server-side by an opaque reference, so that clients are only ever given the
+/// reference (see `AuthConfig::token_store`). Removing a token from the store revokes it at once. The default
+/// [`MemoryTokenStore`] only holds tokens issued by this process; deployments running several instances should share
+/// a store between them, for instance one backed by Redis.
+#[async_trait]
+pub trait TokenStore: Send + Sync + 'static {
+    /// Store the token under the reference. It need not be kept beyond `expires_at`, when it can no longer be used.
+    async fn insert(
+        &self,
+        reference: &str,
+        token: &str,
+        expires_at: SystemTime,
+    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// The token stored under the reference, if any.
+    async fn get(&self, reference: &str) -> Result<Option<String>, Box<dyn Error + Send + Sync>>;
+
+    /// Forget the token stored under the reference, revoking it.
+    async fn remove(&self, reference: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Hold at most this many tokens at once, as set by `AuthConfig::max_store_entries`, refusing to store any more
+    /// rather than forgetting earlier ones. Stores kept outside this process are bounded by their own means, and
+    /// needn't implement this.
+    fn limit_entries(&self, _max_entries: usize) {}
+
+    /// The number of tokens held, for `Auth::store_sizes`, if the store is kept in this process.
+    fn entries(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Stores tokens in the memory of this process.
+#[derive(Default)]
+pub struct MemoryTokenStore {
+    // tokens by reference, until they expire
+    tokens: Mutex<ExpiringMap<String, SystemTime>>,
+}
+
+#[async_trait]
+impl TokenStore for MemoryTokenStore {
+    async fn insert(
+        &self,
+        reference: &str,
+        token: &str,
+        expires_at: SystemTime,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.forget_expired(SystemTime::now());
+
+        if !tokens.has_room_for(reference) {
+            return Err(Box::new(AuthError::StoreFull));
+        }
+        tokens.insert(reference.into(), token.into(), expires_at);
+
+        Ok(())
+    }
+
+    async fn get(&self, reference: &str) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let tokens = self.tokens.lock().unwrap();
+
+        Ok(match tokens.get_with_expiry(reference) {
+            Some((token, expires_at)) if expires_at > SystemTime::now() => Some(token.clone()),
+            _ => None,
+        })
+    }
+
+    async fn remove(&self, reference: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.tokens.lock().unwrap().remove(reference);
+
+        Ok(())
+    }
+
+    fn limit_entries(&self, max_entries: usize) {
+        self.tokens.lock().unwrap().set_capacity(Some(max_entries));
+    }
+
+    fn entries(&self) -> Option<usize> {
+        Some(self.tokens.lock().unwrap().len())
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[repr(transparent)]
@@ -8,13 +9,140 @@ pub struct UserID(pub String);
 #[repr(transparent)]
 pub struct Username(pub String);
 
+/// A password hash in argon2's encoded string format, which carries the salt and hashing parameters alongside the
+/// hash. Databases must return it exactly as it was stored; configure `AuthConfig::password_encoding` to store it in
+/// any other form.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[repr(transparent)]
 pub struct HashedPassword(pub String);
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[repr(transparent)]
+pub struct Tenant(pub String);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[repr(transparent)]
+pub struct Role(pub String);
+
 #[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct Claims {
     pub(crate) exp: u64,
     pub(crate) iss: String,
     pub(crate) sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) iat: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) scope: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) act: Option<Actor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) typ: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) jti: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) tenant: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) created_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) preferred_username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) amr: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) acr: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) csrf: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) cnf: Option<Confirmation>,
+    // when a token issued to expire at a deadline was meant to end, which no token refreshed from it may outlive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) deadline: Option<u64>,
+    #[serde(flatten)]
+    pub(crate) extra: Map<String, Value>,
+}
+
+impl Claims {
+    // Names of the claims this crate sets itself, which custom claims may not override
+    const REGISTERED: &'static [&'static str] = &[
+        "exp",
+        "iss",
+        "sub",
+        "iat",
+        "aud",
+        "scope",
+        "act",
+        "typ",
+        "jti",
+        "tenant",
+        "created_at",
+        "preferred_username",
+        "client_id",
+        "amr",
+        "acr",
+        "csrf",
+        "cnf",
+        "deadline",
+        "zip",
+    ];
+
+    pub(crate) fn is_registered(name: &str) -> bool {
+        Self::REGISTERED.contains(&name)
+    }
+
+    // Derive the user id from the named claim, which may be a string or a number
+    pub(crate) fn user_id(&self, claim: &str) -> Option<UserID> {
+        if claim == "sub" {
+            return Some(UserID(self.sub.clone()));
+        }
+
+        match self.extra.get(claim)? {
+            Value::String(id) => Some(UserID(id.clone())),
+            Value::Number(id) => Some(UserID(id.to_string())),
+            _ => None,
+        }
+    }
+
+    // The authentication assurance level, if the token has a numeric one
+    pub(crate) fn acr_level(&self) -> Option<u32> {
+        self.acr.as_deref()?.parse().ok()
+    }
+
+    // The roles in the token's roles claim, if it has one
+    pub(crate) fn roles(&self) -> Vec<Role> {
+        let Some(Value::Array(roles)) = self.extra.get("roles") else {
+            return vec![];
+        };
+
+        roles
+            .iter()
+            .filter_map(Value::as_str)
+            .map(|role| Role(role.into()))
+            .collect()
+    }
+
+    pub(crate) fn scopes(&self) -> impl Iterator<Item = &str> {
+        self.scope.as_deref().unwrap_or_default().split_whitespace()
+    }
+}
+
+/// The authenticated user, as identified by their auth token.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub user_id: UserID,
+    /// The username at the time the token was issued, if `AuthConfig::username_claim` is enabled.
+    pub username: Option<Username>,
+}
+
+// The key a token is bound to, as per RFC 8705: the SHA-256 thumbprint of the client's TLS certificate
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct Confirmation {
+    #[serde(rename = "x5t#S256")]
+    pub(crate) x5t_s256: String,
+}
+
+// The party acting on behalf of the subject of a delegated token
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct Actor {
+    pub(crate) sub: String,
 }
@@ -0,0 +1,53 @@
+use unicode_normalization::UnicodeNormalization;
+
+// Normalize an identifier to NFKC, so that composed and decomposed (or compatibility) forms of the same name compare
+// equal. If case-folding, the identifier is lowercased in between, and normalized again as lowercasing can undo it
+pub(crate) fn fold_identifier(identifier: &str, case_insensitive: bool) -> String {
+    let normalized = identifier.nfkc().collect::<String>();
+
+    if case_insensitive {
+        normalized.to_lowercase().nfkc().collect()
+    } else {
+        normalized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fold_identifier;
+
+    #[test]
+    fn composed_and_decomposed_forms_match() {
+        let composed = "Jos\u{e9}";
+        let decomposed = "Jose\u{301}";
+        assert_ne!(composed, decomposed);
+
+        assert_eq!(
+            fold_identifier(composed, false),
+            fold_identifier(decomposed, false)
+        );
+        assert_eq!(
+            fold_identifier(composed, true),
+            fold_identifier(decomposed, true)
+        );
+    }
+
+    #[test]
+    fn compatibility_forms_match() {
+        // fullwidth letters and the "fi" ligature
+        assert_eq!(fold_identifier("\u{ff33}am", false), "Sam");
+        assert_eq!(fold_identifier("\u{fb01}sh", false), "fish");
+    }
+
+    #[test]
+    fn case_is_kept_unless_folding() {
+        assert_eq!(
+            fold_identifier("J\u{c9}R\u{d4}ME", false),
+            "J\u{c9}R\u{d4}ME"
+        );
+        assert_eq!(
+            fold_identifier("JE\u{301}RO\u{302}ME", true),
+            "j\u{e9}r\u{f4}me"
+        );
+    }
+}
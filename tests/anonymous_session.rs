@@ -0,0 +1,80 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use auth_for_warp::{
+    build_api_route_filter, handle_auth_errors, with_anonymous_session, AnonymousSession, Auth,
+    AuthConfig, UserID,
+};
+use common::{register, serve, test_config};
+use reqwest::{header::SET_COOKIE, StatusCode};
+use serde_json::json;
+use warp::{path, Filter};
+
+#[tokio::test]
+async fn anonymous_session_cookie_minted_once_and_linked_at_login() {
+    let linked = Arc::new(Mutex::new(vec![]));
+
+    let links = linked.clone();
+    let auth = Auth::new(AuthConfig {
+        anonymous_session_linker: Some(Arc::new(move |session_id: &str, user_id: &UserID| {
+            links
+                .lock()
+                .unwrap()
+                .push((session_id.to_owned(), user_id.0.clone()));
+        })),
+        ..test_config()
+    });
+
+    let cart = path!("cart")
+        .and(with_anonymous_session())
+        .map(|session: AnonymousSession| session.reply(session.id.clone()));
+
+    let routes = cart
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("http://{addr}/cart"))
+        .send()
+        .await
+        .unwrap();
+    let set_cookie = response.headers()[SET_COOKIE].to_str().unwrap().to_owned();
+    let session_id = response.text().await.unwrap();
+    assert!(set_cookie.starts_with(&format!("session_id={session_id};")));
+
+    let cookie = format!("session_id={session_id}");
+
+    let response = client
+        .get(format!("http://{addr}/cart"))
+        .header("cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert!(
+        response.headers().get(SET_COOKIE).is_none(),
+        "an existing session should be reused"
+    );
+    assert_eq!(response.text().await.unwrap(), session_id);
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    assert_eq!(
+        client
+            .post(format!("http://{addr}/users/login"))
+            .header("cookie", &cookie)
+            .json(&json!({"username": "Sam I Am", "password": "foobar"}))
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::OK
+    );
+
+    let linked = linked.lock().unwrap();
+    assert_eq!(linked.len(), 1);
+    assert_eq!(linked[0].0, session_id);
+}
@@ -0,0 +1,947 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use auth_for_warp::{
+    build_api_route_filter, handle_auth_errors, with_account_age, with_acr, with_auth,
+    with_auth_context, with_auth_guarded, with_auth_loading, with_auth_matching_param,
+    with_auth_requiring, with_auth_roles, with_auth_timeout, Auth, AuthConfig, AuthContext,
+    AuthFailureKind, BlockList, ClaimRequirement, Role, UserID, Username, ACR_MULTI_FACTOR,
+    ACR_SINGLE_FACTOR,
+};
+use common::{
+    decode_claims, login, register, serve, serve_auth_routes, sign_claims, test_config,
+    test_config_with_db, unix_time, TestDB, TEST_ISSUER,
+};
+use reqwest::StatusCode;
+use serde_json::json;
+use warp::{path, Filter};
+
+#[tokio::test]
+async fn route_with_combined_claim_requirements() {
+    let db = TestDB::default()
+        .with_claims("admin", json!({"role": "admin", "email_verified": true}))
+        .with_claims(
+            "unverified admin",
+            json!({"role": "admin", "email_verified": false}),
+        )
+        .with_claims(
+            "verified user",
+            json!({"role": "user", "email_verified": true}),
+        );
+
+    let auth = Auth::new(test_config_with_db(db));
+
+    let admin_page = path!("admin")
+        .and(with_auth_requiring(
+            &auth,
+            &[
+                ClaimRequirement::equals("role", "admin"),
+                ClaimRequirement::equals("email_verified", true),
+            ],
+        ))
+        .map(|_| "welcome, admin");
+
+    let routes = admin_page
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    for (username, expected_status) in [
+        ("admin", StatusCode::OK),
+        ("unverified admin", StatusCode::FORBIDDEN),
+        ("verified user", StatusCode::FORBIDDEN),
+    ] {
+        register(&client, addr, username, "foobar").await;
+        let token = login(&client, addr, username, "foobar").await;
+
+        assert_eq!(
+            client
+                .get(format!("http://{addr}/admin"))
+                .bearer_auth(token)
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            expected_status,
+            "unexpected access to the admin page for {username}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn route_restricted_to_the_user_in_the_path() {
+    let auth = Auth::new(test_config_with_db(TestDB::default()));
+
+    let user_data = warp::path("users")
+        .and(with_auth_matching_param(&auth))
+        .and(path!("data"))
+        .map(|_| "your data");
+
+    let routes = user_data
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    register(&client, addr, "Green Eggs", "foobar").await;
+
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+    let own_id = decode_claims(&token)["sub"].as_str().unwrap().to_owned();
+
+    let other_token = login(&client, addr, "Green Eggs", "foobar").await;
+    let other_id = decode_claims(&other_token)["sub"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    for (user_id, expected_status) in [(own_id, StatusCode::OK), (other_id, StatusCode::FORBIDDEN)]
+    {
+        assert_eq!(
+            client
+                .get(format!("http://{addr}/users/{user_id}/data"))
+                .bearer_auth(&token)
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            expected_status,
+            "unexpected access to the data of user {user_id}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn route_loads_roles_from_database() {
+    let db = TestDB::default().with_roles("editor", &["editor", "reviewer"]);
+
+    let auth = Auth::new(test_config_with_db(db));
+
+    let roles_page =
+        path!("roles")
+            .and(with_auth_roles(&auth))
+            .map(|_user_id: UserID, roles: Vec<Role>| {
+                warp::reply::json(&roles.into_iter().map(|role| role.0).collect::<Vec<_>>())
+            });
+
+    let routes = roles_page
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    for (username, expected_roles) in [
+        ("editor", json!(["editor", "reviewer"])),
+        ("reader", json!([])),
+    ] {
+        register(&client, addr, username, "foobar").await;
+        let token = login(&client, addr, username, "foobar").await;
+
+        let response = client
+            .get(format!("http://{addr}/roles"))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.json::<serde_json::Value>().await.unwrap(),
+            expected_roles,
+            "unexpected roles for {username}"
+        );
+    }
+
+    assert_eq!(
+        reqwest::get(format!("http://{addr}/roles"))
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn route_guarded_by_live_database_state() {
+    let db = Arc::new(
+        TestDB::default().with_claims("subscriber", json!({ "subscription_active": true })),
+    );
+
+    let auth = Auth::new(AuthConfig {
+        database_connection: db.clone(),
+        ..test_config()
+    });
+
+    let premium_page = path!("premium")
+        .and(with_auth_guarded(&auth, |_user_id, claims| async move {
+            if claims.get("subscription_active") == Some(&json!(true)) {
+                Ok(())
+            } else {
+                Err("subscription inactive".into())
+            }
+        }))
+        .map(|_| "premium content");
+
+    let routes = premium_page
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "subscriber", "foobar").await;
+    let token = login(&client, addr, "subscriber", "foobar").await;
+
+    let get_premium = || {
+        client
+            .get(format!("http://{addr}/premium"))
+            .bearer_auth(&token)
+            .send()
+    };
+
+    assert_eq!(get_premium().await.unwrap().status(), StatusCode::OK);
+
+    // the subscription lapses after the token was issued
+    db.lock().await.claims.insert(
+        "subscriber".into(),
+        json!({ "subscription_active": false })
+            .as_object()
+            .unwrap()
+            .clone(),
+    );
+
+    assert_eq!(get_premium().await.unwrap().status(), StatusCode::FORBIDDEN);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Organisation {
+    org_id: String,
+}
+
+#[tokio::test]
+async fn loaded_context_reaches_handler() {
+    let auth = Auth::new(test_config());
+
+    // stands in for the app's own tables
+    let memberships = Arc::new(Mutex::new(HashMap::<String, Organisation>::new()));
+
+    let members = memberships.clone();
+    let org_page = path!("org")
+        .and(with_auth_loading(&auth, move |user_id: UserID| {
+            let organisation = members.lock().unwrap().get(&user_id.0).cloned();
+            async move { organisation.ok_or("not a member of any organisation") }
+        }))
+        .map(|user_id: UserID, organisation: Organisation| {
+            warp::reply::json(&json!({ "user_id": user_id.0, "org_id": organisation.org_id }))
+        });
+
+    let routes = org_page
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+    let user_id = decode_claims(&token)["sub"].as_str().unwrap().to_owned();
+
+    let get_org = || {
+        client
+            .get(format!("http://{addr}/org"))
+            .bearer_auth(&token)
+            .send()
+    };
+
+    assert_eq!(
+        get_org().await.unwrap().status(),
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "a failed load should fail the request"
+    );
+
+    memberships.lock().unwrap().insert(
+        user_id.clone(),
+        Organisation {
+            org_id: "green-eggs-inc".into(),
+        },
+    );
+
+    let response = get_org().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.json::<serde_json::Value>().await.unwrap(),
+        json!({ "user_id": user_id, "org_id": "green-eggs-inc" })
+    );
+
+    let response = client
+        .get(format!("http://{addr}/org"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn route_restricted_to_established_accounts() {
+    let db = TestDB::default().with_created_at(
+        "old timer",
+        SystemTime::now() - Duration::from_secs(30 * 24 * 60 * 60),
+    );
+
+    let auth = Auth::new(test_config_with_db(db));
+
+    let invite = path!("invite")
+        .and(with_account_age(
+            &auth,
+            Duration::from_secs(7 * 24 * 60 * 60),
+        ))
+        .map(|_| "invitation sent");
+
+    let routes = invite
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    for (username, expected_status) in [
+        ("old timer", StatusCode::OK),
+        ("newcomer", StatusCode::FORBIDDEN),
+    ] {
+        register(&client, addr, username, "foobar").await;
+        let token = login(&client, addr, username, "foobar").await;
+
+        assert_eq!(
+            client
+                .post(format!("http://{addr}/invite"))
+                .bearer_auth(token)
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            expected_status,
+            "unexpected access to invitations for {username}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn password_only_token_requires_step_up_for_multi_factor_route() {
+    let auth = Auth::new(test_config());
+
+    let profile = path!("profile")
+        .and(with_acr(&auth, ACR_SINGLE_FACTOR))
+        .map(|_| "your profile");
+    let payment = path!("payment")
+        .and(with_acr(&auth, ACR_MULTI_FACTOR))
+        .map(|_| "payment sent");
+
+    let routes = profile
+        .or(payment)
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+
+    let claims = decode_claims(&token);
+    assert_eq!(claims["amr"], json!(["pwd"]));
+    assert_eq!(claims["acr"], json!("1"));
+
+    let response = client
+        .get(format!("http://{addr}/profile"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .post(format!("http://{addr}/payment"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        StatusCode::UNAUTHORIZED,
+        "password-only token allowed on a route requiring multiple factors"
+    );
+    assert_eq!(
+        response.json::<serde_json::Value>().await.unwrap(),
+        json!({ "error": "stronger authentication is required" })
+    );
+}
+
+#[tokio::test]
+async fn missing_authorization_header_is_unauthorized() {
+    let auth = Auth::new(test_config_with_db(TestDB::default()));
+
+    let secure_page = path!("secure").and(with_auth(&auth)).map(|_| "hello, user");
+
+    let routes = secure_page
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let response = reqwest::get(format!("http://{addr}/secure")).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.json::<serde_json::Value>().await.unwrap(),
+        json!({"error": "authentication required"})
+    );
+}
+
+#[tokio::test]
+async fn auth_context_marks_replies_private() {
+    let auth = Auth::new(test_config());
+
+    let profile = path!("profile")
+        .and(with_auth_context(&auth))
+        .map(|context: AuthContext| context.finish(context.user_id.0.clone()));
+
+    let routes = profile
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "green eggs").await;
+    let token = login(&client, addr, "Sam I Am", "green eggs").await;
+
+    let response = client
+        .get(format!("http://{addr}/profile"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["cache-control"], "private");
+}
+
+// Blocks the user ids added to it
+#[derive(Default)]
+struct BlockedUsers(std::sync::Mutex<Vec<String>>);
+
+#[async_trait]
+impl BlockList for BlockedUsers {
+    async fn is_user_blocked(
+        &self,
+        user_id: &UserID,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        Ok(self.0.lock().unwrap().contains(&user_id.0))
+    }
+}
+
+#[tokio::test]
+async fn blocked_user_denied_despite_valid_token() {
+    let blocked_users = Arc::new(BlockedUsers::default());
+
+    let auth = Auth::new(AuthConfig {
+        block_list: Some(blocked_users.clone()),
+        ..test_config()
+    });
+
+    let secure_page = path!("secure").and(with_auth(&auth)).map(|_| "hello, user");
+
+    let routes = secure_page
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "green eggs").await;
+    register(&client, addr, "Green Eggs", "ham").await;
+    let blocked_token = login(&client, addr, "Sam I Am", "green eggs").await;
+    let other_token = login(&client, addr, "Green Eggs", "ham").await;
+
+    let blocked_id = decode_claims(&blocked_token)["sub"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    blocked_users.0.lock().unwrap().push(blocked_id);
+
+    let status = |token: String| {
+        let client = client.clone();
+        async move {
+            client
+                .get(format!("http://{addr}/secure"))
+                .bearer_auth(token)
+                .send()
+                .await
+                .unwrap()
+                .status()
+        }
+    };
+
+    assert_eq!(status(blocked_token).await, StatusCode::FORBIDDEN);
+    assert_eq!(status(other_token).await, StatusCode::OK);
+
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .json(&json!({ "username": "Sam I Am", "password": "green eggs" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        StatusCode::FORBIDDEN,
+        "a blocked user should not be able to login"
+    );
+}
+
+#[tokio::test]
+async fn blocked_user_cannot_refresh() {
+    let blocked_users = Arc::new(BlockedUsers::default());
+
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        block_list: Some(blocked_users.clone()),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "green eggs").await;
+    let token = login(&client, addr, "Sam I Am", "green eggs").await;
+
+    let blocked_id = decode_claims(&token)["sub"].as_str().unwrap().to_owned();
+    blocked_users.0.lock().unwrap().push(blocked_id);
+
+    let response = client
+        .post(format!("http://{addr}/users/refresh"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        StatusCode::FORBIDDEN,
+        "a blocked user should not be able to refresh their token"
+    );
+}
+
+#[tokio::test]
+async fn blocked_user_cannot_login_with_magic_link() {
+    let blocked_users = Arc::new(BlockedUsers::default());
+    let outbox = Arc::new(Mutex::new(Vec::<String>::new()));
+
+    let sent = outbox.clone();
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        block_list: Some(blocked_users.clone()),
+        magic_link_sender: Some(Arc::new(move |_: &Username, token: &str| {
+            sent.lock().unwrap().push(token.to_owned());
+        })),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "green eggs").await;
+    let token = login(&client, addr, "Sam I Am", "green eggs").await;
+
+    let blocked_id = decode_claims(&token)["sub"].as_str().unwrap().to_owned();
+    blocked_users.0.lock().unwrap().push(blocked_id);
+
+    let response = client
+        .post(format!("http://{addr}/users/magic-link"))
+        .json(&json!({ "username": "Sam I Am" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let magic_token = outbox.lock().unwrap().pop().unwrap();
+
+    let response = client
+        .get(format!("http://{addr}/users/magic-login"))
+        .query(&[("token", &magic_token)])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        StatusCode::FORBIDDEN,
+        "a blocked user should not be able to login with a magic link"
+    );
+}
+
+// Swap the first character of the token's signature for another
+fn tamper(token: &str) -> String {
+    let (unsigned, signature) = token.rsplit_once('.').unwrap();
+    let replacement = if signature.starts_with('A') { "B" } else { "A" };
+    format!("{unsigned}.{replacement}{}", &signature[1..])
+}
+
+#[tokio::test]
+async fn tampered_token_reported_to_failure_callback() {
+    let (failures, mut reported) = tokio::sync::mpsc::unbounded_channel();
+
+    let auth = Auth::new(AuthConfig {
+        on_auth_failure: Some(Arc::new(move |kind, ip| {
+            failures.send((kind, ip)).unwrap();
+        })),
+        ..test_config()
+    });
+
+    let secure_page = path!("secure").and(with_auth(&auth)).map(|_| "hello, user");
+
+    let routes = secure_page
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = client
+        .get(format!("http://{addr}/secure"))
+        .bearer_auth(tamper(&token))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // the test server doesn't know the client address, so only the kind is checked
+    let (kind, _) = tokio::time::timeout(Duration::from_secs(5), reported.recv())
+        .await
+        .expect("the failure should be reported")
+        .unwrap();
+    assert_eq!(kind, AuthFailureKind::InvalidSignature);
+    assert!(
+        reported.try_recv().is_err(),
+        "only one failure should be reported"
+    );
+}
+
+#[tokio::test]
+async fn slow_failure_callback_holds_up_no_requests() {
+    let (release, released) = std::sync::mpsc::channel::<()>();
+    let released = Mutex::new(released);
+    let (failures, mut reported) = tokio::sync::mpsc::unbounded_channel();
+
+    let auth = Auth::new(AuthConfig {
+        // blocks until the test is done with its requests, or panics if it never is
+        on_auth_failure: Some(Arc::new(move |kind, _| {
+            released
+                .lock()
+                .unwrap()
+                .recv_timeout(Duration::from_secs(10))
+                .expect("the callback was never released");
+            failures.send(kind).unwrap();
+        })),
+        ..test_config()
+    });
+
+    let secure_page = path!("secure").and(with_auth(&auth)).map(|_| "hello, user");
+
+    let routes = secure_page
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+
+    let get_secure = |token: String| {
+        let client = client.clone();
+        async move {
+            let response = tokio::time::timeout(
+                Duration::from_secs(5),
+                client
+                    .get(format!("http://{addr}/secure"))
+                    .bearer_auth(token)
+                    .send(),
+            )
+            .await
+            .expect("the request should not wait for the callback")
+            .unwrap();
+            response.status()
+        }
+    };
+
+    assert_eq!(get_secure(tamper(&token)).await, StatusCode::FORBIDDEN);
+    assert_eq!(get_secure(token.clone()).await, StatusCode::OK);
+    assert_eq!(get_secure(tamper(&token)).await, StatusCode::FORBIDDEN);
+
+    release.send(()).unwrap();
+    release.send(()).unwrap();
+
+    for _ in 0..2 {
+        let kind = tokio::time::timeout(Duration::from_secs(5), reported.recv())
+            .await
+            .expect("the failure should be reported once the callback is released")
+            .unwrap();
+        assert_eq!(kind, AuthFailureKind::InvalidSignature);
+    }
+}
+
+#[tokio::test]
+async fn panicking_failure_callback_still_reports_later_failures() {
+    let (failures, mut reported) = tokio::sync::mpsc::unbounded_channel();
+    let calls = AtomicUsize::new(0);
+
+    let auth = Auth::new(AuthConfig {
+        // panics on the first failure only
+        on_auth_failure: Some(Arc::new(move |kind, _| {
+            if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("the first report panics");
+            }
+            failures.send(kind).unwrap();
+        })),
+        ..test_config()
+    });
+
+    let secure_page = path!("secure").and(with_auth(&auth)).map(|_| "hello, user");
+
+    let routes = secure_page
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+
+    for _ in 0..2 {
+        let response = client
+            .get(format!("http://{addr}/secure"))
+            .bearer_auth(tamper(&token))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    let kind = tokio::time::timeout(Duration::from_secs(5), reported.recv())
+        .await
+        .expect("the second failure should be reported despite the first panicking")
+        .unwrap();
+    assert_eq!(kind, AuthFailureKind::InvalidSignature);
+}
+
+#[tokio::test]
+async fn token_failures_classified_by_kind() {
+    let auth = Auth::new(AuthConfig {
+        auth_token_audience: Some("my-api".into()),
+        ..test_config()
+    });
+
+    let valid = json!({
+        "iss": TEST_ISSUER,
+        "aud": "my-api",
+        "sub": "some-user-id",
+        "exp": unix_time(60 * 60),
+    });
+    let with = |claim: &str, value| {
+        let mut claims = valid.clone();
+        claims[claim] = value;
+        claims
+    };
+    let without = |claim: &str| {
+        let mut claims = valid.clone();
+        claims.as_object_mut().unwrap().remove(claim);
+        claims
+    };
+
+    assert!(auth.authenticate(&sign_claims(&valid)).await.is_ok());
+
+    let cases = [
+        (
+            with("exp", json!(unix_time(-60 * 60))),
+            AuthFailureKind::Expired,
+        ),
+        (
+            with("aud", json!("other-api")),
+            AuthFailureKind::InvalidAudience,
+        ),
+        (
+            with("iss", json!("someone else")),
+            AuthFailureKind::InvalidIssuer,
+        ),
+        (without("exp"), AuthFailureKind::InvalidClaims),
+        (without("aud"), AuthFailureKind::InvalidClaims),
+        (
+            with("exp", json!("tomorrow")),
+            AuthFailureKind::InvalidClaims,
+        ),
+    ];
+
+    for (claims, expected) in cases {
+        let error = auth.authenticate(&sign_claims(&claims)).await.unwrap_err();
+        assert_eq!(AuthFailureKind::of(&error), Some(expected), "{claims}");
+    }
+
+    let error = auth.authenticate("not.a.token").await.unwrap_err();
+    assert_eq!(AuthFailureKind::of(&error), Some(AuthFailureKind::Invalid));
+}
+
+#[tokio::test]
+async fn slow_handler_times_out() {
+    let auth = Auth::new(test_config());
+
+    let fast = path!("fast").and(with_auth_timeout(
+        &auth,
+        Duration::from_millis(500),
+        |user_id: UserID| async move { user_id.0 },
+    ));
+    let slow = path!("slow").and(with_auth_timeout(
+        &auth,
+        Duration::from_millis(500),
+        |user_id: UserID| async move {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            user_id.0
+        },
+    ));
+
+    let routes = fast
+        .or(slow)
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = client
+        .get(format!("http://{addr}/fast"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .get(format!("http://{addr}/slow"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+}
+
+#[tokio::test]
+async fn cert_bound_token_accepted_only_with_that_cert() {
+    let auth = Auth::new(AuthConfig {
+        client_cert_thumbprint_header: Some("x-client-cert-thumbprint".into()),
+        ..test_config()
+    });
+
+    let secure_page = path!("secure").and(with_auth(&auth)).map(|_| "hello, user");
+
+    let routes = secure_page
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .header(
+            "x-client-cert-thumbprint",
+            "bwcK0esc3ACC3DB2Y5_lESsXE8o9ltc05O89jdN-dg2",
+        )
+        .json(&json!({ "username": "Sam I Am", "password": "foobar" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let token = response.json::<serde_json::Value>().await.unwrap()["token"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    assert_eq!(
+        decode_claims(&token)["cnf"],
+        json!({ "x5t#S256": "bwcK0esc3ACC3DB2Y5_lESsXE8o9ltc05O89jdN-dg2" })
+    );
+
+    let request_with = |thumbprint: Option<&'static str>| {
+        let mut request = client
+            .get(format!("http://{addr}/secure"))
+            .bearer_auth(&token);
+        if let Some(thumbprint) = thumbprint {
+            request = request.header("x-client-cert-thumbprint", thumbprint);
+        }
+        request.send()
+    };
+
+    assert_eq!(
+        request_with(Some("bwcK0esc3ACC3DB2Y5_lESsXE8o9ltc05O89jdN-dg2"))
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        request_with(Some("some-other-certificate-thumbprint"))
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        request_with(None).await.unwrap().status(),
+        StatusCode::FORBIDDEN
+    );
+
+    // tokens issued without a client certificate aren't bound
+    let unbound = login(&client, addr, "Sam I Am", "foobar").await;
+    assert!(decode_claims(&unbound).get("cnf").is_none());
+    assert_eq!(
+        client
+            .get(format!("http://{addr}/secure"))
+            .bearer_auth(&unbound)
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::OK
+    );
+}
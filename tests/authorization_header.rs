@@ -0,0 +1,129 @@
+mod common;
+
+use std::sync::Arc;
+
+use auth_for_warp::{with_auth, Auth, AuthConfig, AuthError, StaticJwks, TokenDelivery};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use common::test_config;
+use proptest::prelude::*;
+use serde_json::{json, Value};
+use warp::{http::HeaderValue, test::RequestBuilder};
+
+// Run an arbitrary request through with_auth, which must reject it cleanly
+fn check_request(auth: &Auth, request: RequestBuilder) -> Result<(), TestCaseError> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    let result = runtime.block_on(request.filter(&with_auth(auth)));
+
+    match result {
+        Ok(user_id) => prop_assert!(false, "unexpectedly authenticated as {user_id:?}"),
+        Err(rejection) => prop_assert!(
+            matches!(
+                rejection.find(),
+                Some(
+                    AuthError::TokenError { .. }
+                        | AuthError::MissingToken
+                        | AuthError::UnknownKeyId { .. }
+                )
+            ),
+            "unexpected rejection {rejection:?}"
+        ),
+    }
+
+    Ok(())
+}
+
+fn check_header(auth: &Auth, value: &[u8]) -> Result<(), TestCaseError> {
+    check_request(auth, warp::test::request().header("authorization", value))
+}
+
+// Any bytes that are legal in a header value, including non-ASCII "obs-text"
+fn header_value_bytes() -> impl Strategy<Value = Vec<u8>> {
+    proptest::collection::vec(
+        prop_oneof![Just(b'\t'), 0x20u8..0x7f, 0x80u8..=0xff],
+        0..256,
+    )
+}
+
+// Three base64url segments, so as to get past the check of the token's shape to the decoder. The header is either
+// arbitrary bytes or a JSON header with arbitrary fields
+fn jwt_shaped_token() -> impl Strategy<Value = String> {
+    let header = prop_oneof![
+        any::<Vec<u8>>(),
+        (
+            prop_oneof![
+                Just("HS256".to_owned()),
+                Just("RS256".to_owned()),
+                Just("none".to_owned()),
+                any::<String>()
+            ],
+            proptest::option::of(any::<String>()),
+        )
+            .prop_map(|(alg, kid)| {
+                let mut header = json!({ "alg": alg, "typ": "JWT" });
+                if let Some(kid) = kid {
+                    header["kid"] = Value::String(kid);
+                }
+                header.to_string().into_bytes()
+            }),
+    ];
+
+    (header, any::<Vec<u8>>(), any::<Vec<u8>>()).prop_map(|(header, claims, signature)| {
+        [header, claims, signature]
+            .map(|segment| URL_SAFE_NO_PAD.encode(segment))
+            .join(".")
+    })
+}
+
+fn static_jwks_auth() -> Auth {
+    let path = format!("{}/tests/keys/jwks_1.json", env!("CARGO_MANIFEST_DIR"));
+    let key_set = std::fs::read_to_string(path).unwrap();
+
+    Auth::new(AuthConfig {
+        static_jwks: Some(Arc::new(StaticJwks::from_json(&key_set).unwrap())),
+        ..test_config()
+    })
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_authorization_header_never_panics(value in header_value_bytes()) {
+        check_header(&Auth::new(test_config()), &value)?;
+    }
+
+    #[test]
+    fn arbitrary_bearer_token_never_panics(token in any::<String>()) {
+        let value = format!("Bearer {token}");
+        prop_assume!(HeaderValue::from_str(&value).is_ok());
+
+        check_header(&Auth::new(test_config()), value.as_bytes())?;
+    }
+
+    #[test]
+    fn arbitrary_bearer_token_bytes_never_panic(token in header_value_bytes()) {
+        let value = [b"Bearer ".as_slice(), &token].concat();
+
+        check_header(&Auth::new(test_config()), &value)?;
+    }
+
+    #[test]
+    fn jwt_shaped_bearer_token_never_panics(token in jwt_shaped_token()) {
+        let value = format!("Bearer {token}");
+
+        check_header(&Auth::new(test_config()), value.as_bytes())?;
+        check_header(&static_jwks_auth(), value.as_bytes())?;
+    }
+
+    #[test]
+    fn arbitrary_token_cookie_never_panics(token in header_value_bytes()) {
+        let auth = Auth::new(AuthConfig {
+            token_delivery: TokenDelivery::Cookie("auth_token".into()),
+            ..test_config()
+        });
+        let value = [b"auth_token=".as_slice(), &token].concat();
+
+        check_request(&auth, warp::test::request().header("cookie", value.as_slice()))?;
+    }
+}
@@ -0,0 +1,423 @@
+#![allow(dead_code)]
+
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use auth_for_warp::{
+    auth_error_handler, build_api_route_filter, Auth, AuthConfig, HashedPassword, Role, Tenant,
+    UserDatabase, UserID, Username,
+};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tokio::{
+    net::TcpListener,
+    sync::{Mutex, MutexGuard},
+};
+use tokio_stream::wrappers::TcpListenerStream;
+use warp::{Filter, Reply};
+
+// What the test database stores, behind the database's own lock
+#[derive(Default)]
+pub struct TestData {
+    pub storage: HashMap<String, (UserID, HashedPassword)>,
+    // users keyed by tenant and username, when usernames are tenant-scoped
+    pub tenant_storage: HashMap<(String, String), (UserID, HashedPassword)>,
+    // replaced password hashes, most recent first, keyed by user id
+    pub password_history: HashMap<String, Vec<HashedPassword>>,
+    // account creation times, keyed by username
+    pub created_at: HashMap<String, SystemTime>,
+    // token lifetime overrides, keyed by username
+    pub token_lifetimes: HashMap<String, Duration>,
+    // custom claims to issue, keyed by username
+    pub claims: HashMap<String, Map<String, Value>>,
+    // roles, keyed by username
+    pub roles: HashMap<String, Vec<Role>>,
+    // ids of users who have verified their email
+    pub verified: HashSet<String>,
+}
+
+impl TestData {
+    fn username_of(&self, user_id: &UserID) -> Option<&str> {
+        self.storage
+            .iter()
+            .find(|(_, (id, _))| id.0 == user_id.0)
+            .map(|(username, _)| username.as_str())
+    }
+}
+
+#[derive(Default)]
+pub struct TestDB {
+    data: Mutex<TestData>,
+    // while set, password updates and verifications fail, as though the database were down
+    pub failing_writes: Arc<AtomicBool>,
+}
+
+impl TestDB {
+    pub fn with_claims(mut self, username: &str, claims: Value) -> Self {
+        self.data
+            .get_mut()
+            .claims
+            .insert(username.into(), claims.as_object().unwrap().clone());
+        self
+    }
+
+    pub fn with_roles(mut self, username: &str, roles: &[&str]) -> Self {
+        self.data.get_mut().roles.insert(
+            username.into(),
+            roles.iter().map(|role| Role(role.to_string())).collect(),
+        );
+        self
+    }
+
+    pub fn with_created_at(mut self, username: &str, created_at: SystemTime) -> Self {
+        self.data
+            .get_mut()
+            .created_at
+            .insert(username.into(), created_at);
+        self
+    }
+
+    pub fn with_token_lifetime(mut self, username: &str, lifetime: Duration) -> Self {
+        self.data
+            .get_mut()
+            .token_lifetimes
+            .insert(username.into(), lifetime);
+        self
+    }
+
+    // Look at (or change) what is stored, as a test
+    pub async fn lock(&self) -> MutexGuard<'_, TestData> {
+        self.data.lock().await
+    }
+}
+
+#[async_trait]
+impl UserDatabase for TestDB {
+    async fn create_user_if_not_exists(
+        &self,
+        user_id: &UserID,
+        username: &Username,
+        hashed_password: &HashedPassword,
+    ) -> Result<UserID, Box<dyn Error + Send + Sync>> {
+        let mut data = self.data.lock().await;
+
+        if data.storage.contains_key(&username.0) {
+            Ok(data.storage[&username.0.clone()].0.clone())
+        } else {
+            data.storage.insert(
+                username.0.clone(),
+                (user_id.clone(), hashed_password.clone()),
+            );
+            data.created_at
+                .entry(username.0.clone())
+                .or_insert_with(SystemTime::now);
+            Ok(user_id.clone())
+        }
+    }
+
+    async fn retreive_user(
+        &self,
+        username: &Username,
+    ) -> Result<(UserID, HashedPassword), Box<dyn Error + Send + Sync>> {
+        let result = self
+            .data
+            .lock()
+            .await
+            .storage
+            .get(&username.0)
+            .ok_or_else(|| anyhow!("user not found"))
+            .cloned()?;
+
+        Ok(result)
+    }
+
+    async fn list_users(
+        &self,
+        after: Option<&UserID>,
+        limit: usize,
+    ) -> Result<Vec<(UserID, Username)>, Box<dyn Error + Send + Sync>> {
+        let mut users = self
+            .data
+            .lock()
+            .await
+            .storage
+            .iter()
+            .filter(|(_, (user_id, _))| after.is_none_or(|after| user_id.0 > after.0))
+            .map(|(username, (user_id, _))| (user_id.clone(), Username(username.clone())))
+            .collect::<Vec<_>>();
+
+        users.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+        users.truncate(limit);
+
+        Ok(users)
+    }
+
+    async fn account_created_at(
+        &self,
+        user_id: &UserID,
+    ) -> Result<Option<SystemTime>, Box<dyn Error + Send + Sync>> {
+        let data = self.data.lock().await;
+
+        Ok(data
+            .username_of(user_id)
+            .and_then(|username| data.created_at.get(username))
+            .copied())
+    }
+
+    async fn roles(&self, user_id: &UserID) -> Result<Vec<Role>, Box<dyn Error + Send + Sync>> {
+        let data = self.data.lock().await;
+
+        Ok(data
+            .username_of(user_id)
+            .and_then(|username| data.roles.get(username))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn token_lifetime(
+        &self,
+        user_id: &UserID,
+    ) -> Result<Option<Duration>, Box<dyn Error + Send + Sync>> {
+        let data = self.data.lock().await;
+
+        Ok(data
+            .username_of(user_id)
+            .and_then(|username| data.token_lifetimes.get(username))
+            .copied())
+    }
+
+    async fn update_password(
+        &self,
+        user_id: &UserID,
+        hashed_password: &HashedPassword,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.failing_writes.load(Ordering::SeqCst) {
+            return Err("the database is down".into());
+        }
+
+        let mut data = self.data.lock().await;
+        let data = &mut *data;
+
+        let (_, current) = data
+            .storage
+            .values_mut()
+            .chain(data.tenant_storage.values_mut())
+            .find(|(id, _)| id.0 == user_id.0)
+            .ok_or_else(|| anyhow!("user not found"))?;
+
+        let replaced = std::mem::replace(current, hashed_password.clone());
+        data.password_history
+            .entry(user_id.0.clone())
+            .or_default()
+            .insert(0, replaced);
+
+        Ok(())
+    }
+
+    async fn is_verified(&self, user_id: &UserID) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        Ok(self.data.lock().await.verified.contains(&user_id.0))
+    }
+
+    async fn mark_verified(&self, user_id: &UserID) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.failing_writes.load(Ordering::SeqCst) {
+            return Err("the database is down".into());
+        }
+
+        self.data.lock().await.verified.insert(user_id.0.clone());
+
+        Ok(())
+    }
+
+    async fn password_history(
+        &self,
+        user_id: &UserID,
+    ) -> Result<Vec<HashedPassword>, Box<dyn Error + Send + Sync>> {
+        Ok(self
+            .data
+            .lock()
+            .await
+            .password_history
+            .get(&user_id.0)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn create_tenant_user_if_not_exists(
+        &self,
+        tenant: &Tenant,
+        user_id: &UserID,
+        username: &Username,
+        hashed_password: &HashedPassword,
+    ) -> Result<UserID, Box<dyn Error + Send + Sync>> {
+        let mut data = self.data.lock().await;
+        let (existing_id, _) = data
+            .tenant_storage
+            .entry((tenant.0.clone(), username.0.clone()))
+            .or_insert_with(|| (user_id.clone(), hashed_password.clone()));
+
+        Ok(existing_id.clone())
+    }
+
+    async fn retreive_tenant_user(
+        &self,
+        tenant: &Tenant,
+        username: &Username,
+    ) -> Result<(UserID, HashedPassword), Box<dyn Error + Send + Sync>> {
+        let result = self
+            .data
+            .lock()
+            .await
+            .tenant_storage
+            .get(&(tenant.0.clone(), username.0.clone()))
+            .ok_or_else(|| anyhow!("user not found"))
+            .cloned()?;
+
+        Ok(result)
+    }
+
+    async fn custom_claims(
+        &self,
+        user_id: &UserID,
+    ) -> Result<Map<String, Value>, Box<dyn Error + Send + Sync>> {
+        let data = self.data.lock().await;
+
+        Ok(data
+            .username_of(user_id)
+            .and_then(|username| data.claims.get(username))
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+pub fn test_config() -> AuthConfig {
+    test_config_with_db(TestDB::default())
+}
+
+pub const TEST_ISSUER: &str = "insert app or organisation name here";
+pub const TEST_SECRET: &str = "this is a really bad secret";
+
+pub fn test_config_with_db(db: TestDB) -> AuthConfig {
+    AuthConfig::new(
+        "this is a terrible salt",
+        TEST_ISSUER,
+        TEST_SECRET,
+        Duration::from_secs(60 * 60),
+        Arc::new(db),
+    )
+}
+
+pub async fn serve<F>(routes: F) -> Result<SocketAddr, anyhow::Error>
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+
+    let local_addr = listener.local_addr()?;
+
+    tokio::spawn(warp::serve(routes).run_incoming(TcpListenerStream::new(listener)));
+
+    Ok(local_addr)
+}
+
+// Serve just the auth routes for the given config
+pub async fn serve_auth_routes(config: AuthConfig) -> (Auth, SocketAddr) {
+    let auth = Auth::new(config);
+
+    let routes = build_api_route_filter(&auth).recover(auth_error_handler(&auth));
+
+    (auth, serve(routes).await.unwrap())
+}
+
+pub async fn register(client: &reqwest::Client, addr: SocketAddr, username: &str, password: &str) {
+    let response = client
+        .post(format!("http://{addr}/users/register"))
+        .json(&json!({ "username": username, "password": password }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK, "failed to register user");
+}
+
+pub async fn change_password(
+    client: &reqwest::Client,
+    addr: SocketAddr,
+    username: &str,
+    password: &str,
+    new_password: &str,
+) -> StatusCode {
+    client
+        .post(format!("http://{addr}/users/password"))
+        .json(&json!({ "username": username, "password": password, "new_password": new_password }))
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+pub async fn login(
+    client: &reqwest::Client,
+    addr: SocketAddr,
+    username: &str,
+    password: &str,
+) -> String {
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .json(&json!({ "username": username, "password": password }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK, "failed to login as user");
+
+    response.json::<LoginResponse>().await.unwrap().token
+}
+
+// Read the claims out of a token without validating it
+pub fn decode_claims(token: &str) -> serde_json::Value {
+    let mut validation = Validation::default();
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.required_spec_claims.clear();
+
+    decode::<serde_json::Value>(token, &DecodingKey::from_secret(&[]), &validation)
+        .unwrap()
+        .claims
+}
+
+// Sign arbitrary claims with the test secret, as if issued by the test config
+pub fn sign_claims(claims: &serde_json::Value) -> String {
+    encode(
+        &Header::default(),
+        claims,
+        &EncodingKey::from_secret(TEST_SECRET.as_bytes()),
+    )
+    .unwrap()
+}
+
+// Seconds since the unix epoch, offset by the given number of seconds
+pub fn unix_time(offset: i64) -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    now.checked_add_signed(offset).unwrap()
+}
+
+#[derive(Deserialize)]
+pub struct LoginResponse {
+    pub token: String,
+}
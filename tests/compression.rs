@@ -0,0 +1,78 @@
+#![cfg(feature = "compression")]
+
+mod common;
+
+use auth_for_warp::{
+    build_api_route_filter, handle_auth_errors, with_auth_requiring, Auth, AuthConfig,
+    ClaimRequirement,
+};
+use common::{decode_claims, login, register, serve, test_config_with_db, TestDB};
+use reqwest::StatusCode;
+use serde_json::{Map, Value};
+use warp::{path, Filter};
+
+#[tokio::test]
+async fn token_with_many_claims_round_trips_compressed() {
+    let claims = (0..100)
+        .map(|i| (format!("claim_{i}"), Value::from(format!("value {i}"))))
+        .collect::<Map<_, _>>();
+
+    let db = TestDB::default().with_claims("big", Value::Object(claims));
+
+    let auth = Auth::new(AuthConfig {
+        token_compression_threshold: Some(1024),
+        ..test_config_with_db(db)
+    });
+
+    let secure_page = path!("secure")
+        .and(with_auth_requiring(
+            &auth,
+            &[ClaimRequirement::equals("claim_42", "value 42")],
+        ))
+        .map(|_| "hello, user");
+
+    let addr = serve(
+        secure_page
+            .or(build_api_route_filter(&auth))
+            .recover(handle_auth_errors),
+    )
+    .await
+    .unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "big", "foobar").await;
+    let token = login(&client, addr, "big", "foobar").await;
+
+    let outer_claims = decode_claims(&token);
+    assert!(
+        outer_claims.get("zip").is_some(),
+        "token was not compressed"
+    );
+    assert!(outer_claims.get("claim_42").is_none());
+
+    let response = client
+        .get(format!("http://{addr}/secure"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        StatusCode::OK,
+        "claims were not restored from the compressed token"
+    );
+
+    // small tokens are left uncompressed, and still accepted
+    register(&client, addr, "small", "foobar").await;
+    let token = login(&client, addr, "small", "foobar").await;
+    assert!(decode_claims(&token).get("zip").is_none());
+
+    let response = client
+        .get(format!("http://{addr}/secure"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use std::{convert::Infallible, sync::Arc};
+
+use auth_for_warp::{build_api_route_filter, Auth, AuthConfig, AuthError, Username};
+use common::test_config;
+use serde_json::json;
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+#[test]
+fn short_secret_is_reported_as_insecure() {
+    let config = AuthConfig {
+        auth_token_secret: "abc".into(),
+        ..test_config()
+    };
+
+    assert!(config
+        .insecure_settings()
+        .iter()
+        .any(|problem| problem.contains("auth_token_secret")));
+
+    assert!(
+        matches!(Auth::try_new(config), Err(AuthError::InsecureConfig { .. })),
+        "a 3-character secret should be refused in strict mode"
+    );
+}
+
+#[test]
+fn required_verification_without_a_sender_is_refused() {
+    let config = AuthConfig {
+        password_salt: "a sufficiently long salt value".into(),
+        auth_token_secret: "a sufficiently long secret, at least 32 bytes".into(),
+        require_verification: true,
+        ..test_config()
+    };
+
+    assert_eq!(
+        config.insecure_settings(),
+        ["require_verification needs a verification_sender, or no user can verify their email to login"]
+    );
+    assert!(matches!(
+        Auth::try_new(config.clone()),
+        Err(AuthError::InsecureConfig { .. })
+    ));
+
+    let config = AuthConfig {
+        verification_sender: Some(Arc::new(|_: &Username, _: &str| {})),
+        ..config
+    };
+    assert!(config.insecure_settings().is_empty());
+    assert!(Auth::try_new(config).is_ok());
+}
+
+#[test]
+fn strong_config_is_accepted() {
+    let config = AuthConfig {
+        password_salt: "a sufficiently long salt value".into(),
+        auth_token_secret: "a sufficiently long secret, at least 32 bytes".into(),
+        ..test_config()
+    };
+
+    assert!(config.insecure_settings().is_empty());
+    assert!(Auth::try_new(config).is_ok());
+}
+
+#[tokio::test]
+async fn warmup_checks_hashing_parameters() {
+    let auth = Auth::new(test_config());
+    assert!(auth.warmup().await.is_ok());
+
+    // argon2 requires a salt of at least 8 bytes
+    let auth = Auth::new(AuthConfig {
+        password_salt: "salt".into(),
+        ..test_config()
+    });
+    assert!(matches!(
+        auth.warmup().await,
+        Err(AuthError::HashingError { .. })
+    ));
+}
+
+// Serve the auth routes, replying to each request with whether it was rejected for a hashing error
+fn hashing_error_routes(
+    config: AuthConfig,
+) -> impl Filter<Extract = impl Reply, Error = Infallible> + Clone {
+    build_api_route_filter(&Auth::new(config)).recover(|rejection: Rejection| async move {
+        let hashing_error = matches!(rejection.find(), Some(AuthError::HashingError { .. }));
+        Ok::<_, Infallible>(if hashing_error {
+            "hashing error"
+        } else {
+            "other error"
+        })
+    })
+}
+
+#[tokio::test]
+async fn unusable_hashing_parameters_fail_requests_cleanly() {
+    let config = test_config();
+    let register = |username: &str| {
+        warp::test::request()
+            .method("POST")
+            .path("/users/register")
+            .json(&json!({ "username": username, "password": "foobar" }))
+    };
+
+    let routes = hashing_error_routes(config.clone());
+    let response = register("Sam I Am").reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // the same user database, but without calling warmup to catch the short salt
+    let routes = hashing_error_routes(AuthConfig {
+        password_salt: "salt".into(),
+        ..config
+    });
+
+    let change_password = warp::test::request()
+        .method("POST")
+        .path("/users/password")
+        .json(&json!({ "username": "Sam I Am", "password": "foobar", "new_password": "barfoo" }));
+
+    for request in [register("Green Eggs"), change_password] {
+        assert_eq!(request.reply(&routes).await.body(), "hashing error");
+    }
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use auth_for_warp::{
+    build_api_route_filter, handle_auth_errors, with_csrf_protection, Auth, AuthConfig,
+    TokenDelivery,
+};
+use common::{register, serve, test_config};
+use reqwest::{header::SET_COOKIE, StatusCode};
+use serde_json::{json, Value};
+use warp::{path, Filter};
+
+#[tokio::test]
+async fn login_issues_readable_csrf_token_in_cookie_mode() {
+    let auth = Auth::new(AuthConfig {
+        token_delivery: TokenDelivery::Cookie("auth_token".into()),
+        csrf_cookie: Some("csrf_token".into()),
+        ..test_config()
+    });
+
+    let transfer = path!("transfer")
+        .and(with_csrf_protection(&auth))
+        .map(|_| "transfer complete");
+
+    let routes = transfer
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .json(&json!({"username": "Sam I Am", "password": "foobar"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let cookies = response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .map(|cookie| cookie.to_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+
+    let auth_cookie = cookies
+        .iter()
+        .find(|cookie| cookie.starts_with("auth_token="))
+        .unwrap();
+    assert!(auth_cookie.contains("HttpOnly"));
+
+    let csrf_cookie = cookies
+        .iter()
+        .find(|cookie| cookie.starts_with("csrf_token="))
+        .expect("no CSRF cookie set");
+    assert!(
+        !csrf_cookie.contains("HttpOnly"),
+        "CSRF cookie should be readable by scripts"
+    );
+
+    let body = response.json::<Value>().await.unwrap();
+    let csrf_token = body["csrf_token"]
+        .as_str()
+        .expect("no CSRF token in the response body")
+        .to_owned();
+    assert!(csrf_cookie.starts_with(&format!("csrf_token={csrf_token};")));
+
+    let auth_cookie = auth_cookie.split(';').next().unwrap();
+
+    let response = client
+        .post(format!("http://{addr}/transfer"))
+        .header("cookie", auth_cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        StatusCode::FORBIDDEN,
+        "request without the CSRF token should be refused"
+    );
+
+    let response = client
+        .post(format!("http://{addr}/transfer"))
+        .header("cookie", auth_cookie)
+        .header("x-csrf-token", &csrf_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
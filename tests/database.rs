@@ -0,0 +1,179 @@
+mod common;
+
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use auth_for_warp::{AuthConfig, HashedPassword, Role, UserDatabase, UserID, Username};
+use common::{serve_auth_routes, sign_claims, test_config, unix_time, TEST_ISSUER};
+use futures_util::future::join_all;
+use reqwest::StatusCode;
+use serde_json::json;
+
+// A database whose queries hang for far longer than any reasonable request
+struct StuckDB;
+
+#[async_trait]
+impl UserDatabase for StuckDB {
+    async fn create_user_if_not_exists(
+        &self,
+        user_id: &UserID,
+        _username: &Username,
+        _hashed_password: &HashedPassword,
+    ) -> Result<UserID, Box<dyn Error + Send + Sync>> {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Ok(user_id.clone())
+    }
+
+    async fn retreive_user(
+        &self,
+        _username: &Username,
+    ) -> Result<(UserID, HashedPassword), Box<dyn Error + Send + Sync>> {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Err("user not found".into())
+    }
+}
+
+#[tokio::test]
+async fn stuck_database_times_out_with_service_unavailable() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        database_connection: Arc::new(StuckDB),
+        db_operation_timeout: Some(Duration::from_millis(100)),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    assert_eq!(
+        client
+            .post(format!("http://{addr}/users/login"))
+            .json(&json!({"username": "Sam I Am", "password": "foobar"}))
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::SERVICE_UNAVAILABLE,
+        "a stuck database should time out rather than hang the request"
+    );
+}
+
+// A database that takes a while to look users and their roles up, and can do so for any number of requests at once,
+// keeping track of the most lookups that were in progress together
+#[derive(Default)]
+struct PooledDB {
+    reading: AtomicUsize,
+    most_reading: AtomicUsize,
+}
+
+impl PooledDB {
+    async fn read(&self) {
+        let reading = self.reading.fetch_add(1, Ordering::SeqCst) + 1;
+        self.most_reading.fetch_max(reading, Ordering::SeqCst);
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        self.reading.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl UserDatabase for PooledDB {
+    async fn create_user_if_not_exists(
+        &self,
+        user_id: &UserID,
+        _username: &Username,
+        _hashed_password: &HashedPassword,
+    ) -> Result<UserID, Box<dyn Error + Send + Sync>> {
+        Ok(user_id.clone())
+    }
+
+    async fn retreive_user(
+        &self,
+        _username: &Username,
+    ) -> Result<(UserID, HashedPassword), Box<dyn Error + Send + Sync>> {
+        self.read().await;
+        Err("user not found".into())
+    }
+
+    async fn roles(&self, _userid: &UserID) -> Result<Vec<Role>, Box<dyn Error + Send + Sync>> {
+        self.read().await;
+        Ok(vec![Role("reader".into())])
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn database_is_read_concurrently_at_login() {
+    let db = Arc::new(PooledDB::default());
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        database_connection: db.clone(),
+        max_concurrent_hashes: 4,
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    let statuses = join_all((0..4).map(|i| {
+        client
+            .post(format!("http://{addr}/users/login"))
+            .json(&json!({"username": format!("user {i}"), "password": "foobar"}))
+            .send()
+    }))
+    .await;
+
+    for status in statuses {
+        assert_eq!(status.unwrap().status(), StatusCode::FORBIDDEN);
+    }
+    assert_eq!(
+        db.most_reading.load(Ordering::SeqCst),
+        4,
+        "every login should have looked its user up at once"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn database_is_read_concurrently_by_authenticated_requests() {
+    let db = Arc::new(PooledDB::default());
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        database_connection: db.clone(),
+        me_route_enabled: true,
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    let responses = join_all((0..4).map(|i| {
+        let token = sign_claims(&json!({
+            "sub": format!("user {i}"),
+            "iss": TEST_ISSUER,
+            "iat": unix_time(0),
+            "exp": unix_time(60 * 60),
+        }));
+        client
+            .get(format!("http://{addr}/users/me"))
+            .bearer_auth(token)
+            .send()
+    }))
+    .await;
+
+    for response in responses {
+        let response = response.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let me: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(me["roles"], json!(["reader"]));
+    }
+    assert_eq!(
+        db.most_reading.load(Ordering::SeqCst),
+        4,
+        "every request should have looked its user's roles up at once"
+    );
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use auth_for_warp::{AdaptiveHashCost, AuthConfig};
+use common::{login, register, serve_auth_routes, test_config};
+
+#[test]
+fn cost_backs_off_under_high_latency_and_recovers() {
+    let cost = AdaptiveHashCost::new(1, 4, Duration::from_millis(50));
+    assert_eq!(cost.time_cost(), 4);
+
+    for _ in 0..10 {
+        cost.record_latency(Duration::from_millis(200));
+    }
+    assert_eq!(cost.time_cost(), 1, "cost should fall to the minimum");
+
+    for _ in 0..30 {
+        cost.record_latency(Duration::from_millis(5));
+    }
+    assert_eq!(cost.time_cost(), 4, "cost should recover to the maximum");
+}
+
+#[tokio::test]
+async fn passwords_hashed_at_reduced_cost_still_verify() {
+    // no hash can meet this target, so every registration lowers the cost
+    let cost = Arc::new(AdaptiveHashCost::new(1, 3, Duration::from_nanos(1)));
+
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        adaptive_hash_cost: Some(cost.clone()),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    for username in ["Sam I Am", "Green Eggs", "Ham"] {
+        register(&client, addr, username, "foobar").await;
+    }
+    assert_eq!(cost.time_cost(), 1);
+
+    for username in ["Sam I Am", "Green Eggs", "Ham"] {
+        login(&client, addr, username, "foobar").await;
+    }
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use auth_for_warp::{AuthConfig, HashedPassword, LoginIdentifier, UserID, Username};
+use common::{login, serve_auth_routes, test_config};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn imported_users_can_login() {
+    let (auth, addr) = serve_auth_routes(test_config()).await;
+
+    // hashed elsewhere, with a salt of its own
+    let hashed_password = HashedPassword(
+        legacy_argon2::hash_encoded(
+            b"imported password",
+            b"another system's salt",
+            &Default::default(),
+        )
+        .unwrap(),
+    );
+
+    let users = (0..1000).map(|i| {
+        (
+            UserID(Uuid::new_v4().to_string()),
+            Username(format!("user {i}")),
+            hashed_password.clone(),
+        )
+    });
+
+    assert_eq!(
+        auth.import_users(tokio_stream::iter(users)).await.unwrap(),
+        1000
+    );
+
+    let client = reqwest::Client::new();
+
+    for _ in 0..5 {
+        let i = Uuid::new_v4().as_u128() % 1000;
+        login(&client, addr, &format!("user {i}"), "imported password").await;
+    }
+}
+
+#[tokio::test]
+async fn imported_users_can_login_with_hashed_identifiers() {
+    let (auth, addr) = serve_auth_routes(AuthConfig {
+        identifier_hash_key: Some("identifier key".into()),
+        ..test_config()
+    })
+    .await;
+
+    let hashed_password = HashedPassword(
+        legacy_argon2::hash_encoded(
+            b"imported password",
+            b"another system's salt",
+            &Default::default(),
+        )
+        .unwrap(),
+    );
+
+    let users = [(
+        UserID(Uuid::new_v4().to_string()),
+        Username("sam@example.com".into()),
+        hashed_password,
+    )];
+
+    assert_eq!(
+        auth.import_users(tokio_stream::iter(users)).await.unwrap(),
+        1
+    );
+
+    login(
+        &reqwest::Client::new(),
+        addr,
+        "sam@example.com",
+        "imported password",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn imported_users_can_login_regardless_of_case() {
+    let (auth, addr) = serve_auth_routes(AuthConfig {
+        login_identifier: LoginIdentifier {
+            field: "username".into(),
+            case_insensitive: true,
+        },
+        ..test_config()
+    })
+    .await;
+
+    let hashed_password = HashedPassword(
+        legacy_argon2::hash_encoded(
+            b"imported password",
+            b"another system's salt",
+            &Default::default(),
+        )
+        .unwrap(),
+    );
+
+    // as another system may have kept it, cased as the user first typed it
+    let users = [(
+        UserID(Uuid::new_v4().to_string()),
+        Username(" Sam@Example.com".into()),
+        hashed_password,
+    )];
+
+    assert_eq!(
+        auth.import_users(tokio_stream::iter(users)).await.unwrap(),
+        1
+    );
+
+    login(
+        &reqwest::Client::new(),
+        addr,
+        "sam@EXAMPLE.com",
+        "imported password",
+    )
+    .await;
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use auth_for_warp::{
+    build_api_route_filter, handle_auth_errors, with_auth, Auth, AuthConfig, UserID,
+};
+use common::{decode_claims, login, register, serve, test_config};
+use reqwest::StatusCode;
+use warp::{path, Filter};
+
+#[tokio::test]
+async fn last_seen_reported_at_most_once_per_interval() {
+    let seen = Arc::new(Mutex::new(Vec::<(UserID, SystemTime)>::new()));
+
+    let recorded = seen.clone();
+    let auth = Auth::new(AuthConfig {
+        last_seen_recorder: Some(Arc::new(move |user_id: &UserID, at: SystemTime| {
+            recorded.lock().unwrap().push((user_id.clone(), at));
+        })),
+        last_seen_interval: Duration::from_secs(1),
+        ..test_config()
+    });
+
+    let hello = path!("hello").and(with_auth(&auth)).map(|_| "hello");
+    let routes = hello
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    register(&client, addr, "Guy Am I", "foobar").await;
+    let sam = login(&client, addr, "Sam I Am", "foobar").await;
+    let guy = login(&client, addr, "Guy Am I", "foobar").await;
+
+    let hello = |token: &str| {
+        client
+            .get(format!("http://{addr}/hello"))
+            .bearer_auth(token)
+            .send()
+    };
+
+    for _ in 0..10 {
+        assert_eq!(hello(&sam).await.unwrap().status(), StatusCode::OK);
+    }
+    assert_eq!(
+        seen.lock().unwrap().len(),
+        1,
+        "rapid requests should be reported once"
+    );
+    assert_eq!(
+        seen.lock().unwrap()[0].0 .0,
+        decode_claims(&sam)["sub"].as_str().unwrap()
+    );
+
+    hello(&guy).await.unwrap();
+    assert_eq!(
+        seen.lock().unwrap().len(),
+        2,
+        "each user should be reported separately"
+    );
+
+    // unauthenticated requests aren't anyone's activity
+    client
+        .get(format!("http://{addr}/hello"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(seen.lock().unwrap().len(), 2);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    hello(&sam).await.unwrap();
+    hello(&sam).await.unwrap();
+    assert_eq!(
+        seen.lock().unwrap().len(),
+        3,
+        "the user should be reported again once the interval has passed"
+    );
+
+    // the other user's report, whose interval has passed too, is forgotten as this one is recorded
+    assert_eq!(auth.store_sizes().await.last_seen, 1);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    auth.purge_expired().await;
+    assert_eq!(auth.store_sizes().await.last_seen, 0);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn slow_last_seen_recorder_holds_up_no_other_user() {
+    let reported = AtomicBool::new(false);
+    let auth = Auth::new(AuthConfig {
+        // the first report is as slow as one written to a distant database
+        last_seen_recorder: Some(Arc::new(move |_: &UserID, _: SystemTime| {
+            if !reported.swap(true, Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_secs(2));
+            }
+        })),
+        ..test_config()
+    });
+
+    let hello = path!("hello").and(with_auth(&auth)).map(|_| "hello");
+    let routes = hello
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    register(&client, addr, "Guy Am I", "foobar").await;
+    let sam = login(&client, addr, "Sam I Am", "foobar").await;
+    let guy = login(&client, addr, "Guy Am I", "foobar").await;
+
+    let hello = |token: String| {
+        let client = client.clone();
+        async move {
+            client
+                .get(format!("http://{addr}/hello"))
+                .bearer_auth(token)
+                .send()
+                .await
+                .unwrap()
+                .status()
+        }
+    };
+
+    let sam_seen = tokio::spawn(hello(sam));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let status = tokio::time::timeout(Duration::from_secs(1), hello(guy))
+        .await
+        .expect("the request should not wait for another user's report");
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(sam_seen.await.unwrap(), StatusCode::OK);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use std::collections::HashSet;
+
+use auth_for_warp::{AuthConfig, UserID};
+use common::{decode_claims, login, register, serve_auth_routes, test_config};
+
+#[tokio::test]
+async fn paging_through_users_visits_each_once() {
+    let (auth, addr) = serve_auth_routes(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    let usernames = ["Sam I Am", "Green Eggs", "Ham", "Fox", "Box"];
+    for username in usernames {
+        register(&client, addr, username, "password").await;
+    }
+
+    let mut visited = vec![];
+    let mut after = None;
+    loop {
+        let page = auth.list_users(after.as_ref(), 1).await.unwrap();
+        let Some((user_id, username)) = page.into_iter().next() else {
+            break;
+        };
+
+        visited.push(username.0);
+        after = Some(user_id);
+    }
+
+    assert_eq!(visited.len(), usernames.len());
+    assert_eq!(
+        visited.iter().map(String::as_str).collect::<HashSet<_>>(),
+        HashSet::from(usernames)
+    );
+}
+
+#[tokio::test]
+async fn sessions_listed_oldest_first() {
+    let (auth, addr) = serve_auth_routes(AuthConfig {
+        max_sessions: Some(5),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "green eggs").await;
+
+    let mut session_ids = vec![];
+    let mut user_id = None;
+    for _ in 0..3 {
+        let token = login(&client, addr, "Sam I Am", "green eggs").await;
+        let claims = decode_claims(&token);
+        session_ids.push(claims["jti"].as_str().unwrap().to_owned());
+        user_id = Some(UserID(claims["sub"].as_str().unwrap().to_owned()));
+    }
+
+    let sessions = auth.sessions(&user_id.unwrap()).await;
+    assert_eq!(
+        sessions
+            .into_iter()
+            .map(|session| session.id)
+            .collect::<Vec<_>>(),
+        session_ids
+    );
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use auth_for_warp::{AuthConfig, LockoutPolicy, ThrottleStore};
+use common::{register, serve_auth_routes, test_config};
+use reqwest::StatusCode;
+use serde_json::json;
+
+// Stands in for a store shared between instances, such as Redis. Windows never end
+#[derive(Default)]
+struct MockStore {
+    attempts: Mutex<HashMap<String, u32>>,
+}
+
+#[async_trait]
+impl ThrottleStore for MockStore {
+    async fn record_attempt(
+        &self,
+        key: &str,
+        _window: Duration,
+    ) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        let mut attempts = self.attempts.lock().unwrap();
+        let count = attempts.entry(key.into()).or_default();
+        *count += 1;
+        Ok(*count)
+    }
+
+    async fn attempts(&self, key: &str) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        Ok(self
+            .attempts
+            .lock()
+            .unwrap()
+            .get(key)
+            .copied()
+            .unwrap_or_default())
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.attempts.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+async fn login_status(
+    client: &reqwest::Client,
+    addr: std::net::SocketAddr,
+    password: &str,
+) -> StatusCode {
+    client
+        .post(format!("http://{addr}/users/login"))
+        .json(&json!({ "username": "Sam I Am", "password": password }))
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn lockout_is_shared_between_instances() {
+    let config = AuthConfig {
+        lockout: Some(LockoutPolicy {
+            max_failed_attempts: 3,
+            lockout_duration: Duration::from_secs(60),
+        }),
+        throttle_store: Arc::new(MockStore::default()),
+        ..test_config()
+    };
+
+    // both instances share the same database and throttle store
+    let (_, first) = serve_auth_routes(config.clone()).await;
+    let (_, second) = serve_auth_routes(config).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, first, "Sam I Am", "green eggs").await;
+
+    assert_eq!(
+        login_status(&client, second, "green eggs").await,
+        StatusCode::OK
+    );
+
+    for _ in 0..3 {
+        assert_eq!(
+            login_status(&client, first, "ham").await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    assert_eq!(
+        login_status(&client, second, "green eggs").await,
+        StatusCode::TOO_MANY_REQUESTS,
+        "the lockout should apply on the other instance, even with the right password"
+    );
+}
+
+#[tokio::test]
+async fn successful_login_resets_failed_attempts() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        lockout: Some(LockoutPolicy {
+            max_failed_attempts: 2,
+            lockout_duration: Duration::from_secs(60),
+        }),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "green eggs").await;
+
+    for _ in 0..3 {
+        assert_eq!(
+            login_status(&client, addr, "ham").await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            login_status(&client, addr, "green eggs").await,
+            StatusCode::OK
+        );
+    }
+}
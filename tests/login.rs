@@ -0,0 +1,450 @@
+mod common;
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use auth_for_warp::{
+    AlreadyAuthenticatedResponse, AuthConfig, ErrorFormat, LoginIdentifier, TokenDelivery,
+};
+use common::{login, register, serve_auth_routes, test_config, LoginResponse, TestDB};
+use reqwest::StatusCode;
+use ring::hmac;
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn login_with_form_encoded_body() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        accept_form_bodies: true,
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .form(&[("username", "Sam I Am"), ("password", "foobar")])
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response.status(),
+        StatusCode::OK,
+        "failed to login with a form-encoded body"
+    );
+    assert!(!response
+        .json::<LoginResponse>()
+        .await
+        .unwrap()
+        .token
+        .is_empty());
+}
+
+#[tokio::test]
+async fn login_with_basic_auth() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        accept_basic_auth_login: true,
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "green eggs").await;
+
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .basic_auth("Sam I Am", Some("green eggs"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response
+        .json::<LoginResponse>()
+        .await
+        .unwrap()
+        .token
+        .is_empty());
+
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .basic_auth("Sam I Am", Some("ham"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn form_encoded_body_rejected_unless_enabled() {
+    let (_, addr) = serve_auth_routes(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    assert_eq!(
+        client
+            .post(format!("http://{addr}/users/login"))
+            .form(&[("username", "Sam I Am"), ("password", "foobar")])
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        "form-encoded body should only be accepted when enabled"
+    );
+}
+
+#[tokio::test]
+async fn token_delivered_in_header_and_body() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        token_delivery: TokenDelivery::BodyAndHeader("X-Auth-Token".into()),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .json(&json!({"username": "Sam I Am", "password": "foobar"}))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK, "failed to login as user");
+
+    let header_token = response
+        .headers()
+        .get("x-auth-token")
+        .expect("token should be set in the configured response header")
+        .to_str()
+        .unwrap()
+        .to_owned();
+
+    let body_token = response.json::<LoginResponse>().await.unwrap().token;
+
+    assert_eq!(header_token, body_token);
+}
+
+#[tokio::test]
+async fn token_delivered_in_header_only() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        token_delivery: TokenDelivery::Header("X-Auth-Token".into()),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .json(&json!({"username": "Sam I Am", "password": "foobar"}))
+        .send()
+        .await
+        .unwrap();
+
+    assert!(response.headers().contains_key("x-auth-token"));
+    assert_eq!(
+        response.json::<serde_json::Value>().await.unwrap(),
+        json!({}),
+        "token should not be in the body"
+    );
+}
+
+#[tokio::test]
+async fn unknown_username_takes_as_long_as_wrong_password() {
+    let (_, addr) = serve_auth_routes(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    let time_login = |username: &'static str| {
+        let client = client.clone();
+        async move {
+            let started = Instant::now();
+            let response = client
+                .post(format!("http://{addr}/users/login"))
+                .json(&json!({"username": username, "password": "wrong"}))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            started.elapsed()
+        }
+    };
+
+    // warm up both paths, so one-off setup isn't counted
+    time_login("Sam I Am").await;
+    time_login("Green Eggs").await;
+
+    let mut existing = Duration::ZERO;
+    let mut unknown = Duration::ZERO;
+    for _ in 0..5 {
+        existing += time_login("Sam I Am").await;
+        unknown += time_login("Green Eggs").await;
+    }
+
+    let difference = existing.abs_diff(unknown);
+    assert!(
+        difference < existing.max(unknown) / 2,
+        "login time reveals whether the username exists ({existing:?} vs {unknown:?})"
+    );
+}
+
+#[tokio::test]
+async fn login_failures_padded_with_jitter() {
+    let min_login_duration = Duration::from_millis(600);
+    let jitter = Duration::from_millis(200);
+
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        min_login_duration: Some(min_login_duration),
+        login_duration_jitter: Some(jitter),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    for username in ["Sam I Am", "Green Eggs", "Sam I Am"] {
+        let started = Instant::now();
+        let response = client
+            .post(format!("http://{addr}/users/login"))
+            .json(&json!({"username": username, "password": "wrong"}))
+            .send()
+            .await
+            .unwrap();
+        let elapsed = started.elapsed();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(
+            elapsed >= min_login_duration
+                && elapsed < min_login_duration + jitter + Duration::from_millis(150),
+            "login took {elapsed:?}, outside the padded window"
+        );
+    }
+}
+
+#[tokio::test]
+async fn password_hashed_with_argon2_secret_requires_it_to_login() {
+    let with_secret = AuthConfig {
+        argon2_secret: Some("a pepper kept out of the database".into()),
+        ..test_config()
+    };
+    let without_secret = AuthConfig {
+        argon2_secret: None,
+        ..with_secret.clone()
+    };
+
+    let (_, addr) = serve_auth_routes(with_secret).await;
+    let (_, addr_without_secret) = serve_auth_routes(without_secret).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    for (addr, expected_status) in [
+        (addr, StatusCode::OK),
+        (addr_without_secret, StatusCode::FORBIDDEN),
+    ] {
+        assert_eq!(
+            client
+                .post(format!("http://{addr}/users/login"))
+                .json(&json!({"username": "Sam I Am", "password": "foobar"}))
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            expected_status,
+            "the argon2 secret should be required to verify the password"
+        );
+    }
+}
+
+#[tokio::test]
+async fn login_failure_reported_as_problem_details() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        error_format: ErrorFormat::Problem,
+        ..test_config()
+    })
+    .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("http://{addr}/users/login"))
+        .json(&json!({ "username": "Sam I Am", "password": "green eggs and ham" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/problem+json"
+    );
+    assert_eq!(
+        response.json::<Value>().await.unwrap(),
+        json!({
+            "type": "about:blank",
+            "title": "Forbidden",
+            "detail": "access denied",
+            "status": 403,
+        })
+    );
+}
+
+#[tokio::test]
+async fn login_failure_reported_with_code_and_detail() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        error_format: ErrorFormat::CodeDetail,
+        ..test_config()
+    })
+    .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("http://{addr}/users/login"))
+        .json(&json!({ "username": "Sam I Am", "password": "green eggs and ham" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        response.json::<Value>().await.unwrap(),
+        json!({ "code": "access_denied", "detail": "access denied" })
+    );
+}
+
+#[tokio::test]
+async fn login_with_case_insensitive_email_identifier() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        login_identifier: LoginIdentifier {
+            field: "email".into(),
+            case_insensitive: true,
+        },
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("http://{addr}/users/register"))
+        .json(&json!({ "email": "Sam@Example.com", "password": "foobar" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK, "failed to register user");
+
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .json(&json!({ "email": " sam@example.COM", "password": "foobar" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        StatusCode::OK,
+        "failed to login with a differently cased email"
+    );
+
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .json(&json!({ "username": "sam@example.com", "password": "foobar" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        StatusCode::BAD_REQUEST,
+        "logged in without the configured identifier field"
+    );
+}
+
+#[tokio::test]
+async fn already_authenticated_login_redirected() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        already_authenticated_response: Some(AlreadyAuthenticatedResponse::Redirect(
+            "/home".into(),
+        )),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .bearer_auth(&token)
+        .json(&json!({ "username": "Sam I Am", "password": "foobar" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers()["location"], "/home");
+
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .bearer_auth("not a valid token")
+        .json(&json!({ "username": "Sam I Am", "password": "wrong" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response.status(),
+        StatusCode::FORBIDDEN,
+        "login without a valid token should be processed as usual"
+    );
+}
+
+#[tokio::test]
+async fn login_with_hashed_identifier() {
+    let db = Arc::new(TestDB::default());
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        database_connection: db.clone(),
+        identifier_hash_key: Some("identifier key".into()),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "sam@example.com", "foobar").await;
+    login(&client, addr, "sam@example.com", "foobar").await;
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, b"identifier key");
+    let digest = hmac::sign(&key, b"sam@example.com")
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+
+    let storage = &db.lock().await.storage;
+    assert!(
+        storage.contains_key(&digest),
+        "the database should be keyed by the identifier's digest"
+    );
+    assert!(!storage.contains_key("sam@example.com"));
+}
@@ -0,0 +1,274 @@
+mod common;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use auth_for_warp::{AuthConfig, ClaimRequirement, CookieAttributes, SameSite, TokenDelivery};
+use common::{
+    decode_claims, login, register, serve_auth_routes, sign_claims, test_config,
+    test_config_with_db, TestDB, TEST_ISSUER,
+};
+use reqwest::{header::SET_COOKIE, StatusCode};
+use serde_json::json;
+
+#[tokio::test]
+async fn logout_revokes_token() {
+    let (_, addr) = serve_auth_routes(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = client
+        .post(format!("http://{addr}/users/logout"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(response.text().await.unwrap().is_empty());
+
+    assert_eq!(
+        client
+            .post(format!("http://{addr}/users/refresh"))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::FORBIDDEN,
+        "token should no longer be accepted after logout"
+    );
+}
+
+#[tokio::test]
+async fn logout_clears_auth_cookie() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        token_delivery: TokenDelivery::Cookie("auth_token".into()),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .json(&json!({"username": "Sam I Am", "password": "foobar"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let set_cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+    assert!(set_cookie.contains("HttpOnly"));
+    let cookie = set_cookie.split(';').next().unwrap().to_owned();
+
+    let response = client
+        .post(format!("http://{addr}/users/logout"))
+        .header("cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let cleared = response.headers()[SET_COOKIE].to_str().unwrap();
+    assert!(cleared.starts_with("auth_token=;"), "unexpected {cleared}");
+    assert!(cleared.contains("Max-Age=0"), "unexpected {cleared}");
+
+    assert_eq!(
+        client
+            .post(format!("http://{addr}/users/logout"))
+            .header("cookie", &cookie)
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::FORBIDDEN,
+        "cookie should no longer be accepted after logout"
+    );
+}
+
+#[tokio::test]
+async fn auth_cookie_lasts_as_long_as_the_token() {
+    let db = TestDB::default()
+        .with_token_lifetime("brief", Duration::from_secs(2 * 60))
+        .with_token_lifetime("lengthy", Duration::from_secs(24 * 60 * 60));
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        token_delivery: TokenDelivery::Cookie("auth_token".into()),
+        max_token_lifetime: Some(Duration::from_secs(10 * 60)),
+        ..test_config_with_db(db)
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    for (username, lifetime) in [("brief", 2 * 60), ("lengthy", 10 * 60), ("usual", 10 * 60)] {
+        register(&client, addr, username, "foobar").await;
+
+        let response = client
+            .post(format!("http://{addr}/users/login"))
+            .json(&json!({"username": username, "password": "foobar"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let set_cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        let max_age = set_cookie
+            .split("; ")
+            .find_map(|attribute| attribute.strip_prefix("Max-Age="))
+            .unwrap()
+            .parse::<u64>()
+            .unwrap();
+        assert!(
+            (lifetime - 1..=lifetime).contains(&max_age),
+            "{username}'s cookie has the wrong max age in {set_cookie}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn logout_clears_auth_cookie_with_login_attributes() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        token_delivery: TokenDelivery::Cookie("auth_token".into()),
+        cookie_attributes: CookieAttributes {
+            path: "/app".into(),
+            domain: Some("example.com".into()),
+            secure: true,
+            same_site: SameSite::Lax,
+        },
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .json(&json!({"username": "Sam I Am", "password": "foobar"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let set_cookie = response.headers()[SET_COOKIE].to_str().unwrap().to_owned();
+    let cookie = set_cookie.split(';').next().unwrap().to_owned();
+
+    let response = client
+        .post(format!("http://{addr}/users/logout"))
+        .header("cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let cleared = response.headers()[SET_COOKIE].to_str().unwrap();
+
+    // everything but the value and max age must match for the browser to delete the cookie
+    let attributes = |cookie: &str| {
+        cookie
+            .split("; ")
+            .skip(1)
+            .filter(|attribute| !attribute.starts_with("Max-Age="))
+            .map(str::to_owned)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(attributes(cleared), attributes(&set_cookie));
+    assert_eq!(
+        attributes(cleared),
+        [
+            "Path=/app",
+            "Domain=example.com",
+            "HttpOnly",
+            "Secure",
+            "SameSite=Lax"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn revoke_all_invalidates_every_previously_issued_token() {
+    let db = TestDB::default().with_claims("admin", json!({ "role": "admin" }));
+    let (auth, addr) = serve_auth_routes(AuthConfig {
+        revoke_all_requirements: Some(vec![ClaimRequirement::equals("role", "admin")]),
+        ..test_config_with_db(db)
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "admin", "foobar").await;
+    register(&client, addr, "Sam I Am", "green eggs").await;
+    let admin_token = login(&client, addr, "admin", "foobar").await;
+    let user_token = login(&client, addr, "Sam I Am", "green eggs").await;
+
+    let revoke_all = |token: &str| {
+        client
+            .post(format!("http://{addr}/auth/revoke-all"))
+            .bearer_auth(token)
+            .send()
+    };
+
+    assert_eq!(
+        revoke_all(&user_token).await.unwrap().status(),
+        StatusCode::FORBIDDEN
+    );
+
+    // tokens are invalidated with a granularity of whole seconds
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    assert_eq!(
+        revoke_all(&admin_token).await.unwrap().status(),
+        StatusCode::NO_CONTENT
+    );
+
+    for token in [&admin_token, &user_token] {
+        assert!(auth.authenticate(&format!("Bearer {token}")).await.is_err());
+    }
+
+    let new_token = login(&client, addr, "Sam I Am", "green eggs").await;
+    assert!(auth
+        .authenticate(&format!("Bearer {new_token}"))
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn revocation_includes_tokens_from_the_cutoffs_second() {
+    let (auth, addr) = serve_auth_routes(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let sub = decode_claims(&login(&client, addr, "Sam I Am", "foobar").await)["sub"].clone();
+
+    let cutoff = SystemTime::now();
+    let issued_at = cutoff.duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let token = sign_claims(&json!({
+        "sub": sub,
+        "iss": TEST_ISSUER,
+        "iat": issued_at,
+        "exp": issued_at + 60 * 60,
+    }));
+    assert!(auth.authenticate(&format!("Bearer {token}")).await.is_ok());
+
+    auth.revoke_tokens_issued_before(cutoff).await;
+
+    assert!(
+        auth.authenticate(&format!("Bearer {token}")).await.is_err(),
+        "a token issued within the cutoff's second should be revoked"
+    );
+
+    let new_token = login(&client, addr, "Sam I Am", "foobar").await;
+    assert!(
+        auth.authenticate(&format!("Bearer {new_token}"))
+            .await
+            .is_ok(),
+        "a token issued after the cutoff should be accepted, even within the same second"
+    );
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use auth_for_warp::{
+    build_api_route_filter, handle_auth_errors, with_auth, Auth, AuthConfig, Username,
+};
+use common::{register, serve, test_config, LoginResponse};
+use reqwest::StatusCode;
+use serde_json::json;
+use warp::{path, Filter};
+
+type Outbox = Arc<Mutex<Vec<(String, String)>>>;
+
+async fn start_server() -> (std::net::SocketAddr, Outbox) {
+    let outbox = Outbox::default();
+
+    let sent = outbox.clone();
+    let auth = Auth::new(AuthConfig {
+        magic_link_sender: Some(Arc::new(move |username: &Username, token: &str| {
+            sent.lock()
+                .unwrap()
+                .push((username.0.clone(), token.to_owned()));
+        })),
+        ..test_config()
+    });
+
+    let secure_page = path!("secure").and(with_auth(&auth)).map(|_| "hello, user");
+
+    let routes = secure_page
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    (serve(routes).await.unwrap(), outbox)
+}
+
+async fn request_magic_link(client: &reqwest::Client, addr: std::net::SocketAddr, username: &str) {
+    assert_eq!(
+        client
+            .post(format!("http://{addr}/users/magic-link"))
+            .json(&json!({ "username": username }))
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::ACCEPTED
+    );
+}
+
+#[tokio::test]
+async fn magic_link_login() {
+    let (addr, outbox) = start_server().await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    request_magic_link(&client, addr, "Sam I Am").await;
+
+    let (username, magic_token) = outbox.lock().unwrap().pop().unwrap();
+    assert_eq!(username, "Sam I Am");
+
+    assert_eq!(
+        client
+            .get(format!("http://{addr}/secure"))
+            .bearer_auth(&magic_token)
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::FORBIDDEN,
+        "a magic link token should not be usable as a session token"
+    );
+
+    let response = client
+        .get(format!("http://{addr}/users/magic-login"))
+        .query(&[("token", &magic_token)])
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK, "magic login failed");
+
+    let session_token = response.json::<LoginResponse>().await.unwrap().token;
+
+    assert_eq!(
+        client
+            .get(format!("http://{addr}/secure"))
+            .bearer_auth(&session_token)
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::OK,
+        "failed to access secure page after magic login"
+    );
+}
+
+#[tokio::test]
+async fn magic_link_token_is_single_use() {
+    let (addr, outbox) = start_server().await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    request_magic_link(&client, addr, "Sam I Am").await;
+
+    let (_, magic_token) = outbox.lock().unwrap().pop().unwrap();
+
+    let magic_login = || {
+        client
+            .get(format!("http://{addr}/users/magic-login"))
+            .query(&[("token", &magic_token)])
+            .send()
+    };
+
+    assert_eq!(magic_login().await.unwrap().status(), StatusCode::OK);
+    assert_eq!(
+        magic_login().await.unwrap().status(),
+        StatusCode::FORBIDDEN,
+        "a magic link token should be rejected once used"
+    );
+}
+
+#[tokio::test]
+async fn magic_link_for_unknown_user_sends_nothing() {
+    let (addr, outbox) = start_server().await;
+
+    let client = reqwest::Client::new();
+
+    request_magic_link(&client, addr, "Nobody").await;
+
+    assert!(outbox.lock().unwrap().is_empty());
+}
@@ -0,0 +1,57 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use auth_for_warp::{
+    AuthConfig, NullUserDatabase, UserDatabase, UserID, Username, NO_DATABASE_CONFIGURED,
+};
+use common::{serve_auth_routes, TEST_ISSUER, TEST_SECRET};
+use reqwest::StatusCode;
+use serde_json::json;
+
+#[tokio::test]
+async fn every_method_reports_missing_database() {
+    let db = NullUserDatabase;
+    let user_id = UserID("1".into());
+
+    let errors = [
+        db.retreive_user(&Username("Sam I Am".into()))
+            .await
+            .unwrap_err(),
+        db.custom_claims(&user_id).await.unwrap_err(),
+        db.account_created_at(&user_id).await.unwrap_err(),
+        db.token_lifetime(&user_id).await.unwrap_err(),
+        db.password_history(&user_id).await.unwrap_err(),
+        db.list_users(None, 10).await.unwrap_err(),
+        db.import_users(Box::pin(futures_util::stream::empty()))
+            .await
+            .unwrap_err(),
+    ];
+
+    for error in errors {
+        assert_eq!(error.to_string(), NO_DATABASE_CONFIGURED);
+    }
+}
+
+#[tokio::test]
+async fn registration_fails_without_database() {
+    let (_, addr) = serve_auth_routes(AuthConfig::new(
+        "this is a terrible salt",
+        TEST_ISSUER,
+        TEST_SECRET,
+        Duration::from_secs(60 * 60),
+        Arc::new(NullUserDatabase),
+    ))
+    .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("http://{addr}/users/register"))
+        .json(&json!({ "username": "Sam I Am", "password": "foobar" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
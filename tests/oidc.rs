@@ -0,0 +1,258 @@
+#![cfg(feature = "oidc")]
+
+mod common;
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use auth_for_warp::{
+    build_api_route_filter, handle_auth_errors, with_auth, Auth, AuthConfig, RemoteJwks,
+};
+use common::{login, register, serve, test_config};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use warp::{path, Filter};
+
+const ISSUER: &str = "https://identity.example.com/";
+const AUDIENCE: &str = "https://api.example.com";
+
+// A stand-in for an identity provider's JWKS endpoint, which counts how often it is fetched, and can be made to stop
+// responding
+struct MockProvider {
+    keys: Arc<Mutex<Value>>,
+    fetches: Arc<AtomicUsize>,
+    unresponsive: Arc<AtomicBool>,
+    addr: SocketAddr,
+}
+
+impl MockProvider {
+    async fn start(keys: Value) -> Self {
+        let keys = Arc::new(Mutex::new(keys));
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let unresponsive = Arc::new(AtomicBool::new(false));
+
+        let route = {
+            let keys = keys.clone();
+            let fetches = fetches.clone();
+            let unresponsive = unresponsive.clone();
+            path!("jwks.json").then(move || {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                let keys = keys.lock().unwrap().clone();
+                let unresponsive = unresponsive.load(Ordering::SeqCst);
+                async move {
+                    if unresponsive {
+                        tokio::time::sleep(Duration::from_secs(60)).await;
+                    }
+                    warp::reply::json(&keys)
+                }
+            })
+        };
+
+        let addr = serve(route).await.unwrap();
+
+        Self {
+            keys,
+            fetches,
+            unresponsive,
+            addr,
+        }
+    }
+
+    fn jwks_url(&self) -> String {
+        format!("http://{}/jwks.json", self.addr)
+    }
+}
+
+fn key_set(files: &[&str]) -> Value {
+    let keys = files
+        .iter()
+        .flat_map(|file| {
+            let path = format!("{}/tests/keys/{file}", env!("CARGO_MANIFEST_DIR"));
+            let set: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+            set["keys"].as_array().unwrap().clone()
+        })
+        .collect::<Vec<_>>();
+
+    json!({ "keys": keys })
+}
+
+fn provider_token(key: &str, kid: &str, audience: &str) -> String {
+    let exp = SystemTime::now() + Duration::from_secs(60 * 60);
+
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(kid.into());
+
+    let pem = std::fs::read(format!("{}/tests/keys/{key}", env!("CARGO_MANIFEST_DIR"))).unwrap();
+
+    encode(
+        &header,
+        &json!({
+            "iss": ISSUER,
+            "aud": audience,
+            "sub": "auth0|12345",
+            "exp": exp.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            "typ": "Bearer",
+        }),
+        &EncodingKey::from_rsa_pem(&pem).unwrap(),
+    )
+    .unwrap()
+}
+
+async fn start_resource_server(jwks: RemoteJwks) -> SocketAddr {
+    let auth = Auth::new(AuthConfig {
+        auth_token_issuer: ISSUER.into(),
+        auth_token_audience: Some(AUDIENCE.into()),
+        external_jwks: Some(Arc::new(jwks)),
+        ..test_config()
+    });
+
+    let secure_page = path!("secure")
+        .and(with_auth(&auth))
+        .map(|user_id: auth_for_warp::UserID| user_id.0);
+
+    serve(
+        secure_page
+            .or(build_api_route_filter(&auth))
+            .recover(handle_auth_errors),
+    )
+    .await
+    .unwrap()
+}
+
+async fn get_secure(addr: SocketAddr, token: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("http://{addr}/secure"))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn verifies_provider_token_against_remote_jwks() {
+    let provider = MockProvider::start(key_set(&["jwks_1.json"])).await;
+    let addr = start_resource_server(RemoteJwks::new(provider.jwks_url())).await;
+
+    let response = get_secure(addr, &provider_token("rsa_key_1.pem", "key-1", AUDIENCE)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), "auth0|12345");
+
+    assert_eq!(
+        get_secure(addr, &provider_token("rsa_key_1.pem", "key-1", AUDIENCE))
+            .await
+            .status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        provider.fetches.load(Ordering::SeqCst),
+        1,
+        "the key set should be cached between requests"
+    );
+
+    assert_eq!(
+        get_secure(
+            addr,
+            &provider_token("rsa_key_1.pem", "key-1", "https://other.example.com")
+        )
+        .await
+        .status(),
+        StatusCode::FORBIDDEN,
+        "a token for another audience should be rejected"
+    );
+
+    assert_eq!(
+        get_secure(addr, &provider_token("rsa_key_2.pem", "key-1", AUDIENCE))
+            .await
+            .status(),
+        StatusCode::FORBIDDEN,
+        "a token signed with the wrong key should be rejected"
+    );
+}
+
+#[tokio::test]
+async fn refetches_key_set_when_provider_rotates_keys() {
+    let provider = MockProvider::start(key_set(&["jwks_1.json"])).await;
+    let addr = start_resource_server(
+        RemoteJwks::new(provider.jwks_url()).with_min_refetch_interval(Duration::ZERO),
+    )
+    .await;
+
+    assert_eq!(
+        get_secure(addr, &provider_token("rsa_key_1.pem", "key-1", AUDIENCE))
+            .await
+            .status(),
+        StatusCode::OK
+    );
+
+    *provider.keys.lock().unwrap() = key_set(&["jwks_1.json", "jwks_2.json"]);
+
+    assert_eq!(
+        get_secure(addr, &provider_token("rsa_key_2.pem", "key-2", AUDIENCE))
+            .await
+            .status(),
+        StatusCode::OK,
+        "a token signed with a newly published key should be accepted"
+    );
+    assert_eq!(provider.fetches.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn keeps_cached_keys_while_provider_is_unresponsive() {
+    let provider = MockProvider::start(key_set(&["jwks_1.json"])).await;
+    let addr = start_resource_server(
+        RemoteJwks::new(provider.jwks_url())
+            .with_max_age(Duration::ZERO)
+            .with_min_refetch_interval(Duration::ZERO)
+            .with_fetch_timeout(Duration::from_millis(200)),
+    )
+    .await;
+
+    let token = provider_token("rsa_key_1.pem", "key-1", AUDIENCE);
+    assert_eq!(get_secure(addr, &token).await.status(), StatusCode::OK);
+
+    provider.unresponsive.store(true, Ordering::SeqCst);
+
+    assert_eq!(
+        get_secure(addr, &token).await.status(),
+        StatusCode::OK,
+        "the cached keys should be used when they can't be refreshed"
+    );
+    assert_eq!(provider.fetches.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn fetching_keys_holds_up_no_other_requests() {
+    let provider = MockProvider::start(key_set(&["jwks_1.json"])).await;
+    provider.unresponsive.store(true, Ordering::SeqCst);
+
+    let addr = start_resource_server(
+        RemoteJwks::new(provider.jwks_url()).with_fetch_timeout(Duration::from_secs(3)),
+    )
+    .await;
+
+    let token = provider_token("rsa_key_1.pem", "key-1", AUDIENCE);
+    let waiting = tokio::spawn(async move { get_secure(addr, &token).await.status() });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let client = reqwest::Client::new();
+    let started = Instant::now();
+    register(&client, addr, "user", "password").await;
+    login(&client, addr, "user", "password").await;
+    assert!(
+        started.elapsed() < Duration::from_secs(2),
+        "logging in waited on the key set"
+    );
+
+    assert_eq!(
+        waiting.await.unwrap(),
+        StatusCode::SERVICE_UNAVAILABLE,
+        "the fetch should time out"
+    );
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use auth_for_warp::{AuthConfig, PasswordHasher};
+use common::{login, register, serve_auth_routes, test_config};
+use futures_util::future::join_all;
+use reqwest::StatusCode;
+use serde_json::json;
+
+// Takes long enough to hash that a burst of requests overlaps, keeping track of the most hashes that ran at once
+#[derive(Default)]
+struct SlowHasher {
+    running: AtomicUsize,
+    most_running: AtomicUsize,
+}
+
+impl PasswordHasher for SlowHasher {
+    fn hash(&self, password: &str) -> String {
+        let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+        self.most_running.fetch_max(running, Ordering::SeqCst);
+
+        thread::sleep(Duration::from_millis(300));
+
+        self.running.fetch_sub(1, Ordering::SeqCst);
+        password.chars().rev().collect()
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> bool {
+        self.hash(password) == hash
+    }
+}
+
+fn slow_config(hasher: &Arc<SlowHasher>) -> AuthConfig {
+    AuthConfig {
+        password_hashers: HashMap::from([(
+            "slow".to_owned(),
+            hasher.clone() as Arc<dyn PasswordHasher>,
+        )]),
+        password_hash_version: Some("slow".into()),
+        max_concurrent_hashes: 1,
+        ..test_config()
+    }
+}
+
+async fn register_burst(config: AuthConfig, count: usize) -> Vec<StatusCode> {
+    let (_, addr) = serve_auth_routes(config).await;
+
+    let client = reqwest::Client::new();
+
+    join_all((0..count).map(|i| {
+        client
+            .post(format!("http://{addr}/users/register"))
+            .json(&json!({ "username": format!("user {i}"), "password": "foobar" }))
+            .send()
+    }))
+    .await
+    .into_iter()
+    .map(|response| response.unwrap().status())
+    .collect()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn burst_beyond_hash_queue_is_shed() {
+    let statuses = register_burst(
+        AuthConfig {
+            max_queued_hashes: Some(1),
+            ..slow_config(&Arc::default())
+        },
+        5,
+    )
+    .await;
+
+    let succeeded = statuses.iter().filter(|status| status.is_success()).count();
+    let shed = statuses
+        .iter()
+        .filter(|status| **status == StatusCode::SERVICE_UNAVAILABLE)
+        .count();
+
+    // one hashing, and one waiting its turn
+    assert_eq!(succeeded, 2, "{statuses:?}");
+    assert_eq!(shed, 3, "{statuses:?}");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn burst_waits_without_a_queue_limit() {
+    let statuses = register_burst(slow_config(&Arc::default()), 3).await;
+
+    assert!(
+        statuses.iter().all(|status| status.is_success()),
+        "{statuses:?}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn hashes_run_up_to_the_limit_at_once() {
+    let hasher = Arc::<SlowHasher>::default();
+
+    let statuses = register_burst(
+        AuthConfig {
+            max_concurrent_hashes: 3,
+            ..slow_config(&hasher)
+        },
+        8,
+    )
+    .await;
+
+    assert!(
+        statuses.iter().all(|status| status.is_success()),
+        "{statuses:?}"
+    );
+    assert_eq!(hasher.most_running.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn padded_logins_do_not_hold_a_turn_to_hash() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        min_login_duration: Some(Duration::from_secs(2)),
+        ..slow_config(&Arc::default())
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+    register(&client, addr, "user", "foobar").await;
+
+    let started = Instant::now();
+    join_all((0..3).map(|_| login(&client, addr, "user", "foobar"))).await;
+
+    // the hashes take their turns, but the padding after them overlaps
+    assert!(started.elapsed() < Duration::from_secs(4));
+}
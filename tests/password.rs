@@ -0,0 +1,285 @@
+mod common;
+
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use auth_for_warp::{AuthConfig, HashedPassword, UserDatabase, UserID, Username};
+use common::{
+    change_password, decode_claims, login, register, serve_auth_routes, sign_claims, test_config,
+    unix_time, LoginResponse, TestDB, TEST_ISSUER,
+};
+use reqwest::StatusCode;
+use serde_json::json;
+
+#[tokio::test]
+async fn recent_password_cannot_be_reused() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        password_history_length: 3,
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "first").await;
+
+    assert_eq!(
+        change_password(&client, addr, "Sam I Am", "first", "second").await,
+        StatusCode::OK
+    );
+
+    assert_eq!(
+        change_password(&client, addr, "Sam I Am", "second", "first").await,
+        StatusCode::BAD_REQUEST,
+        "changing back to a recent password should be refused"
+    );
+    assert_eq!(
+        change_password(&client, addr, "Sam I Am", "second", "second").await,
+        StatusCode::BAD_REQUEST,
+        "keeping the current password should be refused"
+    );
+
+    assert_eq!(
+        change_password(&client, addr, "Sam I Am", "second", "third").await,
+        StatusCode::OK,
+        "a novel password should be accepted"
+    );
+
+    login(&client, addr, "Sam I Am", "third").await;
+}
+
+#[tokio::test]
+async fn blank_password_rejected() {
+    let (_, addr) = serve_auth_routes(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("http://{addr}/users/register"))
+        .json(&json!({ "username": "Sam I Am", "password": "    " }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        StatusCode::BAD_REQUEST,
+        "an all-spaces password should be refused"
+    );
+
+    register(&client, addr, "Sam I Am", "green eggs").await;
+
+    assert_eq!(
+        change_password(&client, addr, "Sam I Am", "green eggs", "\t \n").await,
+        StatusCode::BAD_REQUEST,
+        "changing to a whitespace-only password should be refused"
+    );
+    login(&client, addr, "Sam I Am", "green eggs").await;
+}
+
+#[tokio::test]
+async fn low_entropy_password_rejected() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        min_password_entropy: Some(30.0),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("http://{addr}/users/register"))
+        .json(&json!({ "username": "Sam I Am", "password": "aaaaaaaaaaaa" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        StatusCode::BAD_REQUEST,
+        "a long password with little variety should be refused"
+    );
+
+    register(&client, addr, "Sam I Am", "q7#Lm2!vX9pz").await;
+
+    assert_eq!(
+        change_password(&client, addr, "Sam I Am", "q7#Lm2!vX9pz", "abababababab").await,
+        StatusCode::BAD_REQUEST,
+        "changing to a low-entropy password should be refused"
+    );
+}
+
+#[tokio::test]
+async fn password_change_invalidates_existing_tokens() {
+    let (auth, addr) = serve_auth_routes(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "first").await;
+    let old_token = login(&client, addr, "Sam I Am", "first").await;
+
+    // tokens are invalidated with a granularity of whole seconds
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    assert_eq!(
+        change_password(&client, addr, "Sam I Am", "first", "second").await,
+        StatusCode::OK
+    );
+
+    assert!(
+        auth.authenticate(&format!("Bearer {old_token}"))
+            .await
+            .is_err(),
+        "a token issued before the password change should be rejected"
+    );
+
+    let new_token = login(&client, addr, "Sam I Am", "second").await;
+    assert!(auth
+        .authenticate(&format!("Bearer {new_token}"))
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn password_change_invalidates_tokens_from_the_same_second() {
+    let (auth, addr) = serve_auth_routes(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "first").await;
+    let sub = decode_claims(&login(&client, addr, "Sam I Am", "first").await)["sub"].clone();
+
+    // issued as late as possible before the password change, which is most likely within the same second
+    let old_token = sign_claims(&json!({
+        "sub": sub,
+        "iss": TEST_ISSUER,
+        "iat": unix_time(0),
+        "exp": unix_time(60 * 60),
+    }));
+
+    let response = client
+        .post(format!("http://{addr}/users/password"))
+        .json(&json!({ "username": "Sam I Am", "password": "first", "new_password": "second" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let new_token = response.json::<LoginResponse>().await.unwrap().token;
+
+    assert!(
+        auth.authenticate(&format!("Bearer {old_token}"))
+            .await
+            .is_err(),
+        "a token issued before the password change should be rejected"
+    );
+    assert!(
+        auth.authenticate(&format!("Bearer {new_token}"))
+            .await
+            .is_ok(),
+        "the token issued by the password change should be accepted"
+    );
+}
+
+// Versions the password with a counter, and can simulate another request changing the password just after a
+// request has read its version
+#[derive(Default)]
+struct VersionedDB {
+    inner: TestDB,
+    version: AtomicU64,
+    concurrent_change: AtomicBool,
+}
+
+#[async_trait]
+impl UserDatabase for VersionedDB {
+    async fn create_user_if_not_exists(
+        &self,
+        user_id: &UserID,
+        username: &Username,
+        hashed_password: &HashedPassword,
+    ) -> Result<UserID, Box<dyn Error + Send + Sync>> {
+        self.inner
+            .create_user_if_not_exists(user_id, username, hashed_password)
+            .await
+    }
+
+    async fn retreive_user(
+        &self,
+        username: &Username,
+    ) -> Result<(UserID, HashedPassword), Box<dyn Error + Send + Sync>> {
+        self.inner.retreive_user(username).await
+    }
+
+    async fn update_password(
+        &self,
+        user_id: &UserID,
+        hashed_password: &HashedPassword,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.version.fetch_add(1, Ordering::SeqCst);
+        self.inner.update_password(user_id, hashed_password).await
+    }
+
+    async fn password_version(
+        &self,
+        _user_id: &UserID,
+    ) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let version = self.version.load(Ordering::SeqCst);
+        if self.concurrent_change.swap(false, Ordering::SeqCst) {
+            self.version.fetch_add(1, Ordering::SeqCst);
+        }
+
+        Ok(Some(version.to_string()))
+    }
+
+    async fn update_password_if_version(
+        &self,
+        user_id: &UserID,
+        hashed_password: &HashedPassword,
+        expected_version: &str,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        if self.version.load(Ordering::SeqCst).to_string() != expected_version {
+            return Ok(false);
+        }
+
+        self.update_password(user_id, hashed_password).await?;
+        Ok(true)
+    }
+}
+
+#[tokio::test]
+async fn concurrent_password_change_is_a_conflict() {
+    let db = Arc::new(VersionedDB::default());
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        database_connection: db.clone(),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "first").await;
+
+    assert_eq!(
+        change_password(&client, addr, "Sam I Am", "first", "second").await,
+        StatusCode::OK
+    );
+
+    db.concurrent_change.store(true, Ordering::SeqCst);
+
+    assert_eq!(
+        change_password(&client, addr, "Sam I Am", "second", "third").await,
+        StatusCode::CONFLICT,
+        "a change that raced with another should be refused"
+    );
+    login(&client, addr, "Sam I Am", "second").await;
+
+    assert_eq!(
+        change_password(&client, addr, "Sam I Am", "second", "third").await,
+        StatusCode::OK,
+        "retrying the change should succeed"
+    );
+}
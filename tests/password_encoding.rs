@@ -0,0 +1,64 @@
+mod common;
+
+use std::{error::Error, sync::Arc};
+
+use auth_for_warp::{AuthConfig, HashedPassword, PasswordEncoding};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use common::{change_password, login, register, serve_auth_routes, test_config, TestDB};
+use reqwest::StatusCode;
+
+// Stores hashes base64-encoded, as a database with a binary password column might
+struct Base64Encoding;
+
+impl PasswordEncoding for Base64Encoding {
+    fn encode(&self, hashed_password: HashedPassword) -> HashedPassword {
+        HashedPassword(STANDARD.encode(hashed_password.0))
+    }
+
+    fn decode(
+        &self,
+        stored_password: HashedPassword,
+    ) -> Result<HashedPassword, Box<dyn Error + Send + Sync>> {
+        Ok(HashedPassword(String::from_utf8(
+            STANDARD.decode(stored_password.0)?,
+        )?))
+    }
+}
+
+#[tokio::test]
+async fn encoded_passwords_still_verify() {
+    let db = Arc::new(TestDB::default());
+
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        database_connection: db.clone(),
+        password_encoding: Some(Arc::new(Base64Encoding)),
+        password_history_length: 3,
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "green eggs").await;
+
+    let (_, stored_password) = db.lock().await.storage["Sam I Am"].clone();
+    assert!(
+        !stored_password.0.starts_with("$argon2"),
+        "the hash should be stored encoded"
+    );
+    assert!(STANDARD.decode(&stored_password.0).is_ok());
+
+    login(&client, addr, "Sam I Am", "green eggs").await;
+
+    assert_eq!(
+        change_password(&client, addr, "Sam I Am", "green eggs", "ham").await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        change_password(&client, addr, "Sam I Am", "ham", "green eggs").await,
+        StatusCode::BAD_REQUEST,
+        "the password history should be decoded too"
+    );
+
+    login(&client, addr, "Sam I Am", "ham").await;
+}
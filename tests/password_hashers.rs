@@ -0,0 +1,238 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use auth_for_warp::{AuthConfig, HashedPassword, PasswordHasher, UserID, Username};
+use common::{login, register, serve_auth_routes, test_config, TestDB};
+use reqwest::StatusCode;
+use serde_json::json;
+
+// Stands in for an older scheme being migrated away from, such as bcrypt
+struct LegacyHasher;
+
+impl PasswordHasher for LegacyHasher {
+    fn hash(&self, password: &str) -> String {
+        password.chars().rev().collect()
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> bool {
+        self.hash(password) == hash
+    }
+}
+
+fn versioned_config(db: Arc<TestDB>) -> AuthConfig {
+    AuthConfig {
+        database_connection: db,
+        password_hashers: HashMap::from([(
+            "v1".to_owned(),
+            Arc::new(LegacyHasher) as Arc<dyn PasswordHasher>,
+        )]),
+        password_hash_version: Some("v2".into()),
+        ..test_config()
+    }
+}
+
+#[tokio::test]
+async fn previous_version_verifies_and_is_upgraded_at_login() {
+    let db = Arc::new(TestDB::default());
+    let (auth, addr) = serve_auth_routes(versioned_config(db.clone())).await;
+
+    auth.import_users(tokio_stream::iter([(
+        UserID("sam".into()),
+        Username("Sam I Am".into()),
+        HashedPassword("v1$sgge neerg".into()),
+    )]))
+    .await
+    .unwrap();
+
+    let client = reqwest::Client::new();
+
+    login(&client, addr, "Sam I Am", "green eggs").await;
+
+    let (_, stored_password) = db.lock().await.storage["Sam I Am"].clone();
+    assert!(
+        stored_password.0.starts_with("v2$argon2"),
+        "the hash should have been upgraded, but is {}",
+        stored_password.0
+    );
+
+    login(&client, addr, "Sam I Am", "green eggs").await;
+}
+
+#[tokio::test]
+async fn unversioned_hash_verifies_and_is_upgraded_at_login() {
+    let db = Arc::new(TestDB::default());
+
+    let (_, unversioned) = serve_auth_routes(AuthConfig {
+        database_connection: db.clone(),
+        ..test_config()
+    })
+    .await;
+    let (_, versioned) = serve_auth_routes(versioned_config(db.clone())).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, unversioned, "Sam I Am", "green eggs").await;
+    let (_, stored_password) = db.lock().await.storage["Sam I Am"].clone();
+    assert!(stored_password.0.starts_with("$argon2"));
+
+    login(&client, versioned, "Sam I Am", "green eggs").await;
+    let (_, stored_password) = db.lock().await.storage["Sam I Am"].clone();
+    assert!(stored_password.0.starts_with("v2$argon2"));
+}
+
+#[tokio::test]
+async fn argon2i_hash_verifies_and_is_upgraded_to_argon2id_at_login() {
+    let db = Arc::new(TestDB::default());
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        database_connection: db.clone(),
+        ..test_config()
+    })
+    .await;
+
+    // as hashed by default before argon2id
+    let old_hash = legacy_argon2::hash_encoded(
+        b"green eggs",
+        b"this is a terrible salt",
+        &Default::default(),
+    )
+    .unwrap();
+    db.lock().await.storage.insert(
+        "Sam I Am".into(),
+        (UserID("sam".into()), HashedPassword(old_hash)),
+    );
+
+    let client = reqwest::Client::new();
+
+    login(&client, addr, "Sam I Am", "green eggs").await;
+    let (_, stored_password) = db.lock().await.storage["Sam I Am"].clone();
+    assert!(
+        stored_password.0.starts_with("$argon2id$"),
+        "the hash should have been upgraded, but is {}",
+        stored_password.0
+    );
+
+    login(&client, addr, "Sam I Am", "green eggs").await;
+}
+
+#[tokio::test]
+async fn unrecognised_hash_format_is_handled_by_legacy_verifier() {
+    let legacy_hash = HashedPassword("plain:green eggs".into());
+
+    let mut servers = vec![];
+    for legacy_password_verifier in [
+        None,
+        Some(Arc::new(|password: &str, hash: &HashedPassword| {
+            hash.0.strip_prefix("plain:") == Some(password)
+        }) as _),
+    ] {
+        let db = Arc::new(TestDB::default());
+        let (auth, addr) = serve_auth_routes(AuthConfig {
+            database_connection: db.clone(),
+            legacy_password_verifier,
+            password_hash_version: Some("v2".into()),
+            ..test_config()
+        })
+        .await;
+
+        auth.import_users(tokio_stream::iter([(
+            UserID("sam".into()),
+            Username("Sam I Am".into()),
+            legacy_hash.clone(),
+        )]))
+        .await
+        .unwrap();
+
+        servers.push((db, addr));
+    }
+
+    let client = reqwest::Client::new();
+    let try_login = |addr, password: &str| {
+        client
+            .post(format!("http://{addr}/users/login"))
+            .json(&json!({ "username": "Sam I Am", "password": password }))
+            .send()
+    };
+
+    // without a verifier, the hash fails to verify rather than panicking
+    let (_, addr) = &servers[0];
+    assert_eq!(
+        try_login(addr, "green eggs").await.unwrap().status(),
+        StatusCode::FORBIDDEN
+    );
+
+    let (db, addr) = &servers[1];
+    assert_eq!(
+        try_login(addr, "ham").await.unwrap().status(),
+        StatusCode::FORBIDDEN
+    );
+    login(&client, *addr, "Sam I Am", "green eggs").await;
+
+    let (_, stored_password) = db.lock().await.storage["Sam I Am"].clone();
+    assert!(stored_password.0.starts_with("v2$argon2"));
+}
+
+// Counts the hashes it makes and verifies
+#[derive(Default)]
+struct CountingHasher {
+    hashes: AtomicUsize,
+    verifies: AtomicUsize,
+}
+
+impl PasswordHasher for CountingHasher {
+    fn hash(&self, password: &str) -> String {
+        self.hashes.fetch_add(1, Ordering::SeqCst);
+        password.chars().rev().collect()
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> bool {
+        self.verifies.fetch_add(1, Ordering::SeqCst);
+        password.chars().rev().collect::<String>() == hash
+    }
+}
+
+#[tokio::test]
+async fn unknown_username_verifies_against_dummy_hash_made_at_startup() {
+    let hasher = Arc::new(CountingHasher::default());
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        password_hashers: HashMap::from([(
+            "v1".to_owned(),
+            hasher.clone() as Arc<dyn PasswordHasher>,
+        )]),
+        password_hash_version: Some("v1".into()),
+        ..test_config()
+    })
+    .await;
+
+    assert_eq!(
+        hasher.hashes.load(Ordering::SeqCst),
+        1,
+        "the dummy hash should be made at startup, with the current hasher"
+    );
+
+    let client = reqwest::Client::new();
+
+    for attempt in 1..=2 {
+        let response = client
+            .post(format!("http://{addr}/users/login"))
+            .json(&json!({ "username": "nobody", "password": "green eggs" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        assert_eq!(hasher.verifies.load(Ordering::SeqCst), attempt);
+    }
+
+    assert_eq!(
+        hasher.hashes.load(Ordering::SeqCst),
+        1,
+        "the dummy hash should be reused"
+    );
+}
@@ -0,0 +1,131 @@
+mod common;
+
+use std::sync::Arc;
+
+use auth_for_warp::{AuthError, HashedPassword, PasswordHasher, PasswordManager};
+use common::test_config;
+
+struct ReversingHasher;
+
+impl PasswordHasher for ReversingHasher {
+    fn hash(&self, password: &str) -> String {
+        password.chars().rev().collect()
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> bool {
+        self.hash(password) == hash
+    }
+}
+
+#[test]
+fn hashes_and_verifies_passwords() {
+    let passwords = PasswordManager::new("this is a terrible salt").with_argon2_secret("pepper");
+
+    let hash = passwords.hash("green eggs");
+    assert!(hash.0.starts_with("$argon2id$"));
+
+    assert!(passwords.verify("green eggs", &hash));
+    assert!(!passwords.verify("ham", &hash));
+
+    let without_secret = PasswordManager::new("this is a terrible salt");
+    assert!(
+        !without_secret.verify("green eggs", &hash),
+        "the argon2 secret should be mixed into the hash"
+    );
+}
+
+#[test]
+fn built_from_auth_config_verifies_the_same_hashes() {
+    let config = test_config();
+    let from_config = PasswordManager::from(&config);
+    let standalone = PasswordManager::new(config.password_salt.clone());
+
+    assert!(standalone.verify("green eggs", &from_config.hash("green eggs")));
+    assert!(from_config.verify("green eggs", &standalone.hash("green eggs")));
+}
+
+#[test]
+fn versioned_hashes_are_flagged_for_rehash() {
+    let previous = PasswordManager::new("this is a terrible salt")
+        .with_hasher("v1", Arc::new(ReversingHasher))
+        .with_hash_version("v1");
+    let current = PasswordManager::new("this is a terrible salt")
+        .with_hasher("v1", Arc::new(ReversingHasher))
+        .with_hash_version("v2");
+
+    let old_hash = previous.hash("green eggs");
+    assert_eq!(old_hash.0, "v1$sgge neerg");
+    assert!(current.verify("green eggs", &old_hash));
+    assert!(current.needs_rehash(&old_hash));
+
+    let new_hash = current.hash("green eggs");
+    assert!(new_hash.0.starts_with("v2$argon2"));
+    assert!(current.verify("green eggs", &new_hash));
+    assert!(!current.needs_rehash(&new_hash));
+}
+
+#[test]
+fn argon2i_hashes_verify_and_are_flagged_for_rehash() {
+    let passwords = PasswordManager::new("this is a terrible salt").with_argon2_secret("pepper");
+
+    // as hashed by default before argon2id
+    let old_hash = HashedPassword(
+        legacy_argon2::hash_encoded(
+            b"green eggs",
+            b"this is a terrible salt",
+            &legacy_argon2::Config {
+                secret: b"pepper",
+                ..Default::default()
+            },
+        )
+        .unwrap(),
+    );
+    assert!(old_hash.0.starts_with("$argon2i$"));
+
+    assert!(passwords.verify("green eggs", &old_hash));
+    assert!(!passwords.verify("ham", &old_hash));
+    assert!(passwords.needs_rehash(&old_hash));
+    assert!(!passwords.needs_rehash(&passwords.hash("green eggs")));
+}
+
+#[test]
+fn enforces_password_policy() {
+    let passwords = PasswordManager::new("this is a terrible salt").with_min_entropy(28.0);
+
+    assert!(matches!(
+        passwords.check_policy("aaaaaaaaaaaa"),
+        Err(AuthError::WeakPassword { .. })
+    ));
+    assert!(passwords.check_policy("q7#Lm2!vX9pz").is_ok());
+}
+
+#[test]
+fn enforces_min_length_in_characters() {
+    let passwords = PasswordManager::new("this is a terrible salt").with_min_length(4);
+
+    assert!(matches!(
+        passwords.check_policy("abc"),
+        Err(AuthError::WeakPassword { .. })
+    ));
+    // multibyte characters count once each
+    assert!(passwords.check_policy("éééé").is_ok());
+}
+
+#[test]
+fn blank_passwords_rejected_unless_allowed() {
+    let passwords = PasswordManager::new("this is a terrible salt");
+
+    assert!(matches!(
+        passwords.check_policy("   "),
+        Err(AuthError::WeakPassword { .. })
+    ));
+    assert!(passwords.allow_blank_passwords().check_policy("").is_ok());
+}
+
+#[test]
+fn warmup_reports_unusable_salt() {
+    assert!(PasswordManager::new("salty").warmup().is_err());
+    assert!(PasswordManager::new("this is a terrible salt")
+        .warmup()
+        .is_ok());
+}
@@ -0,0 +1,189 @@
+mod common;
+
+use std::{
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use auth_for_warp::{AuthConfig, Username};
+use common::{login, register, serve_auth_routes, test_config, test_config_with_db, TestDB};
+use reqwest::StatusCode;
+use serde_json::json;
+
+type Outbox = Arc<Mutex<Vec<(String, String)>>>;
+
+async fn start_server() -> (SocketAddr, Outbox) {
+    start_server_with_db(TestDB::default()).await
+}
+
+async fn start_server_with_db(db: TestDB) -> (SocketAddr, Outbox) {
+    let outbox = Outbox::default();
+
+    let sent = outbox.clone();
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        password_reset_sender: Some(Arc::new(move |username: &Username, token: &str| {
+            sent.lock()
+                .unwrap()
+                .push((username.0.clone(), token.to_owned()));
+        })),
+        ..test_config_with_db(db)
+    })
+    .await;
+
+    (addr, outbox)
+}
+
+async fn reset_password(
+    client: &reqwest::Client,
+    addr: SocketAddr,
+    token: &str,
+    new_password: &str,
+) -> StatusCode {
+    client
+        .post(format!("http://{addr}/users/reset"))
+        .json(&json!({ "token": token, "new_password": new_password }))
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+async fn forgot_password(
+    client: &reqwest::Client,
+    addr: SocketAddr,
+    username: &str,
+) -> (StatusCode, String) {
+    let response = client
+        .post(format!("http://{addr}/users/forgot"))
+        .json(&json!({ "username": username }))
+        .send()
+        .await
+        .unwrap();
+
+    (response.status(), response.text().await.unwrap())
+}
+
+#[tokio::test]
+async fn forgot_password_response_does_not_reveal_usernames() {
+    let (addr, outbox) = start_server().await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    let known = forgot_password(&client, addr, "Sam I Am").await;
+    let unknown = forgot_password(&client, addr, "Green Eggs").await;
+
+    assert_eq!(known.0, StatusCode::ACCEPTED);
+    assert_eq!(known, unknown);
+
+    let outbox = outbox.lock().unwrap();
+    assert_eq!(
+        outbox.len(),
+        1,
+        "only the known user should be sent a token"
+    );
+    assert_eq!(outbox[0].0, "Sam I Am");
+}
+
+#[tokio::test]
+async fn reset_password_with_emailed_token() {
+    let (addr, outbox) = start_server().await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    forgot_password(&client, addr, "Sam I Am").await;
+
+    let (_, reset_token) = outbox.lock().unwrap().pop().unwrap();
+
+    let reset = || {
+        client
+            .post(format!("http://{addr}/users/reset"))
+            .json(&json!({ "token": reset_token, "new_password": "bazqux" }))
+            .send()
+    };
+
+    assert_eq!(reset().await.unwrap().status(), StatusCode::OK);
+
+    login(&client, addr, "Sam I Am", "bazqux").await;
+
+    assert_eq!(
+        reset().await.unwrap().status(),
+        StatusCode::FORBIDDEN,
+        "a reset token may only be used once"
+    );
+}
+
+#[tokio::test]
+async fn failed_reset_leaves_token_usable() {
+    let db = TestDB::default();
+    let failing_writes = db.failing_writes.clone();
+    let (addr, outbox) = start_server_with_db(db).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    forgot_password(&client, addr, "Sam I Am").await;
+
+    let (_, reset_token) = outbox.lock().unwrap().pop().unwrap();
+
+    assert_eq!(
+        reset_password(&client, addr, &reset_token, "  ").await,
+        StatusCode::BAD_REQUEST,
+        "a blank password should be refused"
+    );
+
+    failing_writes.store(true, Ordering::SeqCst);
+    assert_eq!(
+        reset_password(&client, addr, &reset_token, "bazqux").await,
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+    failing_writes.store(false, Ordering::SeqCst);
+
+    assert_eq!(
+        reset_password(&client, addr, &reset_token, "bazqux").await,
+        StatusCode::OK,
+        "the token should still be usable after the failed attempts"
+    );
+    login(&client, addr, "Sam I Am", "bazqux").await;
+
+    assert_eq!(
+        reset_password(&client, addr, &reset_token, "quxbaz").await,
+        StatusCode::FORBIDDEN,
+        "a reset token may only be used once"
+    );
+}
+
+#[tokio::test]
+async fn padded_forgot_password_holds_up_no_other_requests() {
+    let (auth, addr) = serve_auth_routes(AuthConfig {
+        password_reset_sender: Some(Arc::new(|_: &Username, _: &str| {})),
+        min_login_duration: Some(Duration::from_secs(3)),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+
+    let forgot = tokio::spawn({
+        let client = client.clone();
+        async move { forgot_password(&client, addr, "Sam I Am").await }
+    });
+    // let the forgot password request reach its padding
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let started = Instant::now();
+    assert!(auth.authenticate(&token).await.is_ok());
+    assert!(
+        started.elapsed() < Duration::from_secs(1),
+        "authentication waited {:?} for the forgot password request",
+        started.elapsed()
+    );
+
+    assert_eq!(forgot.await.unwrap().0, StatusCode::ACCEPTED);
+}
@@ -0,0 +1,60 @@
+#![cfg(feature = "password-strength")]
+
+mod common;
+
+use auth_for_warp::AuthConfig;
+use common::{serve_auth_routes, test_config};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn weak_password_gets_low_score_and_suggestions() {
+    let (_, addr) = serve_auth_routes(test_config()).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("http://{addr}/users/register"))
+        .json(&json!({ "username": "Sam I Am", "password": "password" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response.status(),
+        StatusCode::OK,
+        "weak passwords are accepted without a minimum score"
+    );
+
+    let body: Value = response.json().await.unwrap();
+    assert!(body["password_score"].as_u64().unwrap() <= 1);
+    assert!(!body["suggestions"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn password_below_minimum_score_is_rejected() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        min_password_score: Some(3),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("http://{addr}/users/register"))
+        .json(&json!({ "username": "Sam I Am", "password": "samiam123" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = client
+        .post(format!("http://{addr}/users/register"))
+        .json(&json!({ "username": "Sam I Am", "password": "correct horse battery staple" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body: Value = response.json().await.unwrap();
+    assert!(body["password_score"].as_u64().unwrap() >= 3);
+}
@@ -0,0 +1,96 @@
+#![cfg(feature = "phc")]
+
+mod common;
+
+use std::{collections::HashMap, sync::Arc};
+
+use argon2::Argon2;
+use auth_for_warp::{
+    AuthConfig, HashedPassword, PasswordHasher, PasswordManager, PhcHasher, UserID,
+};
+use common::{login, register, serve_auth_routes, test_config, TestDB};
+use pbkdf2::{Params, Pbkdf2};
+
+// Far fewer rounds than the default, to keep the tests fast
+fn fast_pbkdf2() -> PhcHasher<Pbkdf2> {
+    PhcHasher::with_params(
+        Pbkdf2,
+        Params {
+            rounds: 1000,
+            ..Params::default()
+        },
+    )
+}
+
+fn phc_config(db: Arc<TestDB>, hasher: Arc<dyn PasswordHasher>) -> AuthConfig {
+    AuthConfig {
+        database_connection: db,
+        password_hashers: HashMap::from([("v2".to_owned(), hasher)]),
+        password_hash_version: Some("v2".into()),
+        ..test_config()
+    }
+}
+
+#[tokio::test]
+async fn register_and_login_with_phc_hashers() {
+    let hashers: [(&str, Arc<dyn PasswordHasher>); 2] = [
+        ("$argon2id$", Arc::new(PhcHasher::new(Argon2::default()))),
+        ("$pbkdf2-sha256$", Arc::new(fast_pbkdf2())),
+    ];
+
+    for (prefix, hasher) in hashers {
+        let db = Arc::new(TestDB::default());
+        let (_, addr) = serve_auth_routes(phc_config(db.clone(), hasher)).await;
+
+        let client = reqwest::Client::new();
+
+        register(&client, addr, "Sam I Am", "foobar").await;
+        login(&client, addr, "Sam I Am", "foobar").await;
+
+        let (_, stored_password) = db.lock().await.storage["Sam I Am"].clone();
+        assert!(
+            stored_password.0.starts_with(&format!("v2${prefix}")),
+            "{} was not hashed with {prefix}",
+            stored_password.0
+        );
+    }
+}
+
+#[test]
+fn phc_hashers_reject_wrong_and_malformed_hashes() {
+    let hashers: [Box<dyn PasswordHasher>; 2] = [
+        Box::new(PhcHasher::new(Argon2::default())),
+        Box::new(fast_pbkdf2()),
+    ];
+
+    for hasher in hashers {
+        let hash = hasher.hash("foobar");
+
+        assert!(hasher.verify("foobar", &hash));
+        assert!(!hasher.verify("green eggs", &hash));
+        assert!(!hasher.verify("foobar", "not a PHC string"));
+    }
+}
+
+#[tokio::test]
+async fn built_in_argon2_hashes_verify_with_phc_argon2() {
+    let built_in = PasswordManager::new("this is a terrible salt").hash("foobar");
+    let phc = PhcHasher::new(Argon2::default());
+
+    assert!(phc.verify("foobar", &built_in.0));
+    assert!(!phc.verify("green eggs", &built_in.0));
+
+    // and existing users can still login once the PHC hasher is the current version, being rehashed with it
+    let db = Arc::new(TestDB::default());
+    db.lock().await.storage.insert(
+        "Sam I Am".into(),
+        (UserID("sam".into()), HashedPassword(built_in.0.clone())),
+    );
+    let (_, addr) = serve_auth_routes(phc_config(db.clone(), Arc::new(phc))).await;
+
+    let client = reqwest::Client::new();
+    login(&client, addr, "Sam I Am", "foobar").await;
+
+    let (_, stored_password) = db.lock().await.storage["Sam I Am"].clone();
+    assert!(stored_password.0.starts_with("v2$$argon2id$"));
+}
@@ -0,0 +1,191 @@
+mod common;
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use auth_for_warp::{AuthConfig, UserID, DEVICE_ID_HEADER};
+use common::{
+    decode_claims, login, register, serve_auth_routes, sign_claims, test_config, unix_time,
+    LoginResponse, TEST_ISSUER,
+};
+use reqwest::StatusCode;
+use serde_json::json;
+
+async fn refresh(
+    client: &reqwest::Client,
+    addr: std::net::SocketAddr,
+    token: &str,
+) -> reqwest::Response {
+    client
+        .post(format!("http://{addr}/users/refresh"))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn refresh_issues_new_token() {
+    let (_, addr) = serve_auth_routes(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = refresh(&client, addr, &token).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let refreshed = response.json::<LoginResponse>().await.unwrap().token;
+    assert_eq!(
+        decode_claims(&refreshed)["sub"],
+        decode_claims(&token)["sub"]
+    );
+}
+
+#[tokio::test]
+async fn refreshed_token_refreshes_only_once() {
+    let (_, addr) = serve_auth_routes(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+
+    assert_eq!(
+        refresh(&client, addr, &token).await.status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        refresh(&client, addr, &token).await.status(),
+        StatusCode::FORBIDDEN,
+        "a token that has been refreshed should not refresh again"
+    );
+}
+
+#[tokio::test]
+async fn expired_token_refreshes_only_within_grace_period() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        refresh_grace_period: Duration::from_secs(5 * 60),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    let expired_token = |seconds_ago: i64| {
+        sign_claims(&json!({
+            "iss": TEST_ISSUER,
+            "sub": "some-user-id",
+            "exp": unix_time(-seconds_ago),
+        }))
+    };
+
+    assert_eq!(
+        refresh(&client, addr, &expired_token(2 * 60))
+            .await
+            .status(),
+        StatusCode::OK,
+        "a token that expired within the grace period should refresh"
+    );
+
+    assert_eq!(
+        refresh(&client, addr, &expired_token(60 * 60))
+            .await
+            .status(),
+        StatusCode::FORBIDDEN,
+        "a token that expired long ago should not refresh"
+    );
+}
+
+#[tokio::test]
+async fn device_bound_token_refreshes_only_from_that_device() {
+    let (_, addr) = serve_auth_routes(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .header(DEVICE_ID_HEADER, "phone")
+        .json(&json!({ "username": "Sam I Am", "password": "foobar" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let token = response.json::<LoginResponse>().await.unwrap().token;
+
+    let refresh_from = |device_id: Option<&'static str>| {
+        let mut request = client
+            .post(format!("http://{addr}/users/refresh"))
+            .bearer_auth(&token);
+        if let Some(device_id) = device_id {
+            request = request.header(DEVICE_ID_HEADER, device_id);
+        }
+        request.send()
+    };
+
+    assert_eq!(
+        refresh_from(Some("laptop")).await.unwrap().status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        refresh_from(None).await.unwrap().status(),
+        StatusCode::FORBIDDEN
+    );
+
+    let response = refresh_from(Some("phone")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // the refreshed token stays bound to the same device
+    let refreshed = response.json::<LoginResponse>().await.unwrap().token;
+    assert_eq!(
+        client
+            .post(format!("http://{addr}/users/refresh"))
+            .bearer_auth(&refreshed)
+            .header(DEVICE_ID_HEADER, "laptop")
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[tokio::test]
+async fn token_issued_until_deadline_refreshes_no_later_than_it() {
+    let (auth, addr) = serve_auth_routes(AuthConfig {
+        refresh_grace_period: Duration::from_secs(5 * 60),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    let deadline = unix_time(60 * 60);
+    let token = auth
+        .generate_token_until(
+            &UserID("some-user-id".into()),
+            UNIX_EPOCH + Duration::from_secs(deadline),
+        )
+        .await
+        .unwrap();
+
+    let response = refresh(&client, addr, &token).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let refreshed = response.json::<LoginResponse>().await.unwrap().token;
+    assert_eq!(decode_claims(&refreshed)["exp"].as_u64(), Some(deadline));
+
+    // and not at all once the deadline has passed, even within the grace period
+    let past_deadline = sign_claims(&json!({
+        "iss": TEST_ISSUER,
+        "sub": "some-user-id",
+        "exp": unix_time(-60),
+        "deadline": unix_time(-60),
+    }));
+    assert_eq!(
+        refresh(&client, addr, &past_deadline).await.status(),
+        StatusCode::FORBIDDEN
+    );
+}
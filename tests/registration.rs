@@ -0,0 +1,174 @@
+mod common;
+
+use std::{
+    error::Error,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use auth_for_warp::{
+    build_api_route_filter, handle_auth_errors, Auth, AuthConfig, LoginIdentifier,
+    RegistrationChallenge, RegistrationLimit,
+};
+use common::test_config;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use warp::Filter;
+
+async fn register_from(auth: &Auth, remote: &str, body: Value) -> StatusCode {
+    let routes = build_api_route_filter(auth).recover(handle_auth_errors);
+
+    warp::test::request()
+        .method("POST")
+        .path("/users/register")
+        .remote_addr(remote.parse::<SocketAddr>().unwrap())
+        .json(&body)
+        .reply(&routes)
+        .await
+        .status()
+}
+
+#[tokio::test]
+async fn rapid_registrations_from_one_address_throttled() {
+    let auth = Auth::new(AuthConfig {
+        registration_limit: Some(RegistrationLimit {
+            max_registrations: 2,
+            window: Duration::from_secs(60),
+        }),
+        ..test_config()
+    });
+
+    for i in 0..2 {
+        let status = register_from(
+            &auth,
+            "203.0.113.1:1234",
+            json!({ "username": format!("user {i}"), "password": "foobar" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "failed to register user {i}");
+    }
+
+    let status = register_from(
+        &auth,
+        "203.0.113.1:1234",
+        json!({ "username": "one too many", "password": "foobar" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    let status = register_from(
+        &auth,
+        "203.0.113.2:1234",
+        json!({ "username": "someone else", "password": "foobar" }),
+    )
+    .await;
+    assert_eq!(
+        status,
+        StatusCode::OK,
+        "another address should not be throttled"
+    );
+}
+
+// Passes only the one response it expects
+struct FixedChallenge(&'static str);
+
+#[async_trait]
+impl RegistrationChallenge for FixedChallenge {
+    async fn verify(
+        &self,
+        response: Option<&str>,
+        _ip: Option<IpAddr>,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        Ok(response == Some(self.0))
+    }
+}
+
+#[tokio::test]
+async fn registration_requires_passing_the_challenge() {
+    let auth = Auth::new(AuthConfig {
+        registration_challenge: Some(Arc::new(FixedChallenge("solved"))),
+        ..test_config()
+    });
+
+    let status = register_from(
+        &auth,
+        "203.0.113.1:1234",
+        json!({ "username": "Sam I Am", "password": "foobar" }),
+    )
+    .await;
+    assert_eq!(
+        status,
+        StatusCode::FORBIDDEN,
+        "registered without a response"
+    );
+
+    let status = register_from(
+        &auth,
+        "203.0.113.1:1234",
+        json!({ "username": "Sam I Am", "password": "foobar", "challenge_response": "guessed" }),
+    )
+    .await;
+    assert_eq!(
+        status,
+        StatusCode::FORBIDDEN,
+        "registered with a wrong response"
+    );
+
+    let status = register_from(
+        &auth,
+        "203.0.113.1:1234",
+        json!({ "username": "Sam I Am", "password": "foobar", "challenge_response": "solved" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn reserved_usernames_rejected() {
+    let reserved_usernames = vec!["admin".to_owned(), "support".to_owned()];
+
+    let auth = Auth::new(AuthConfig {
+        reserved_usernames: reserved_usernames.clone(),
+        ..test_config()
+    });
+
+    let register = |username: &str| {
+        register_from(
+            &auth,
+            "192.0.2.1:1234",
+            json!({ "username": username, "password": "foobar" }),
+        )
+    };
+
+    assert_eq!(register("admin").await, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        register("Admin").await,
+        StatusCode::OK,
+        "usernames are case-sensitive unless configured otherwise"
+    );
+    assert_eq!(register("Sam I Am").await, StatusCode::OK);
+
+    let auth = Auth::new(AuthConfig {
+        reserved_usernames,
+        login_identifier: LoginIdentifier {
+            field: "username".into(),
+            case_insensitive: true,
+        },
+        ..test_config()
+    });
+
+    for username in ["admin", "Admin", " SUPPORT "] {
+        assert_eq!(
+            register_from(
+                &auth,
+                "192.0.2.1:1234",
+                json!({ "username": username, "password": "foobar" })
+            )
+            .await,
+            StatusCode::BAD_REQUEST,
+            "{username} should be reserved"
+        );
+    }
+}
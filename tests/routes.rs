@@ -0,0 +1,432 @@
+mod common;
+
+use std::sync::Arc;
+
+use auth_for_warp::{
+    build_api_route_filter, handle_auth_errors, with_auth, Auth, AuthConfig, AuthFilters,
+    LoginIdentifier, UserID, Username,
+};
+use common::{
+    decode_claims, login, register, serve, serve_auth_routes, test_config, test_config_with_db,
+    TestDB, TEST_ISSUER,
+};
+use reqwest::{
+    header::{
+        HeaderMap, HeaderValue, CACHE_CONTROL, PRAGMA, STRICT_TRANSPORT_SECURITY,
+        X_CONTENT_TYPE_OPTIONS,
+    },
+    StatusCode,
+};
+use serde_json::json;
+use warp::Filter;
+
+#[tokio::test]
+async fn wrong_method_on_auth_route_is_method_not_allowed() {
+    let auth = Auth::new(test_config());
+
+    // every other route requires auth, so unmatched requests are rejected for the missing header
+    let everything_else = warp::any()
+        .and(with_auth(&auth))
+        .then(|user_id| async move { warp::reply::json(&json!({ "user id": user_id })) });
+
+    let routes = build_api_route_filter(&auth)
+        .or(everything_else)
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let response = reqwest::get(format!("http://{addr}/users/login"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+}
+
+async fn route_status(auth: &Auth, method: &str, path: &str) -> StatusCode {
+    let routes = build_api_route_filter(auth).recover(handle_auth_errors);
+
+    warp::test::request()
+        .method(method)
+        .path(path)
+        .json(&json!({"username": "Sam I Am", "password": "foobar"}))
+        .reply(&routes)
+        .await
+        .status()
+}
+
+#[tokio::test]
+async fn mounts_only_enabled_routes() {
+    let auth = Auth::new(AuthConfig {
+        registration_enabled: false,
+        ..test_config()
+    });
+
+    assert_eq!(
+        route_status(&auth, "POST", "/users/register").await,
+        StatusCode::NOT_FOUND,
+        "registration should not be mounted when disabled"
+    );
+    assert_eq!(
+        route_status(&auth, "POST", "/users/magic-link").await,
+        StatusCode::NOT_FOUND,
+        "magic links should not be mounted without a sender"
+    );
+    assert_eq!(
+        route_status(&auth, "GET", "/users/available?username=Sam").await,
+        StatusCode::NOT_FOUND,
+        "username availability should not be mounted unless enabled"
+    );
+    assert_ne!(
+        route_status(&auth, "POST", "/users/login").await,
+        StatusCode::NOT_FOUND,
+        "login should always be mounted"
+    );
+
+    let auth = Auth::new(AuthConfig {
+        magic_link_sender: Some(Arc::new(|_: &Username, _: &str| {})),
+        ..test_config()
+    });
+
+    assert_eq!(
+        route_status(&auth, "POST", "/users/register").await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        route_status(&auth, "POST", "/users/magic-link").await,
+        StatusCode::ACCEPTED
+    );
+    assert_ne!(
+        route_status(&auth, "GET", "/users/magic-login?token=x").await,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn username_availability() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        username_availability_enabled: true,
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "green eggs").await;
+
+    for (username, available) in [("Sam I Am", false), ("Sam You Are", true)] {
+        let response = client
+            .get(format!("http://{addr}/users/available"))
+            .query(&[("username", username)])
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.json::<serde_json::Value>().await.unwrap(),
+            json!({ "available": available }),
+            "{username}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn taken_username_reported_with_configured_status() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        username_taken_status: StatusCode::UNPROCESSABLE_ENTITY,
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = client
+        .post(format!("http://{addr}/users/register"))
+        .json(&json!({ "username": "Sam I Am", "password": "fizzbuzz" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        response.json::<serde_json::Value>().await.unwrap()["field"],
+        "username"
+    );
+}
+
+#[tokio::test]
+async fn taken_email_reported_as_conflicting_field() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        login_identifier: LoginIdentifier {
+            field: "email".into(),
+            case_insensitive: true,
+        },
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    let register = |email: &str| {
+        client
+            .post(format!("http://{addr}/users/register"))
+            .json(&json!({ "email": email, "password": "foobar" }))
+            .send()
+    };
+
+    assert_eq!(
+        register("sam@example.com").await.unwrap().status(),
+        StatusCode::OK
+    );
+
+    let response = register("Sam@Example.com").await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(
+        response.json::<serde_json::Value>().await.unwrap(),
+        json!({
+            "error": "an account with that identifier already exists",
+            "field": "email",
+        })
+    );
+}
+
+#[tokio::test]
+async fn auth_routes_match_with_trailing_slash() {
+    let (_, addr) = serve_auth_routes(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = client
+        .post(format!("http://{addr}/users/login/"))
+        .json(&json!({ "username": "Sam I Am", "password": "foobar" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn auth_routes_can_refuse_a_trailing_slash() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        accept_trailing_slash: false,
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = client
+        .post(format!("http://{addr}/users/login/"))
+        .json(&json!({ "username": "Sam I Am", "password": "foobar" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    login(&client, addr, "Sam I Am", "foobar").await;
+}
+
+#[tokio::test]
+async fn public_config_omits_secrets() {
+    let config = AuthConfig {
+        public_config_enabled: true,
+        ..test_config()
+    };
+    let secret = config.auth_token_secret.clone();
+    let salt = config.password_salt.clone();
+
+    let (_, addr) = serve_auth_routes(config).await;
+
+    let response = reqwest::get(format!("http://{addr}/auth/config"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.text().await.unwrap();
+    assert!(!body.contains(&secret), "the secret was exposed");
+    assert!(!body.contains(&salt), "the salt was exposed");
+
+    let public_config = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+    assert_eq!(public_config["issuer"], TEST_ISSUER);
+    assert_eq!(public_config["token_lifetime"], 60 * 60);
+    assert_eq!(public_config["algorithms"], json!(["HS256"]));
+    assert_eq!(public_config["methods"]["password"], true);
+    assert_eq!(public_config["password_policy"]["min_length"], json!(null));
+}
+
+#[tokio::test]
+async fn public_config_reports_password_policy() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        public_config_enabled: true,
+        min_password_length: Some(12),
+        password_history_length: 3,
+        ..test_config()
+    })
+    .await;
+
+    let response = reqwest::get(format!("http://{addr}/auth/config"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let policy = &response.json::<serde_json::Value>().await.unwrap()["password_policy"];
+    assert_eq!(policy["min_length"], 12);
+    assert_eq!(policy["history_length"], 3);
+    assert_eq!(policy["reject_blank"], true);
+    assert_eq!(policy["min_entropy"], json!(null));
+
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("http://{addr}/users/register"))
+        .json(&json!({ "username": "Sam I Am", "password": "green eggs" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        StatusCode::BAD_REQUEST,
+        "a password shorter than reported should be refused"
+    );
+
+    register(&client, addr, "Sam I Am", "green eggs and ham").await;
+}
+
+#[tokio::test]
+async fn auth_responses_carry_configured_and_no_store_headers() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        response_headers: HeaderMap::from_iter([
+            (
+                STRICT_TRANSPORT_SECURITY,
+                HeaderValue::from_static("max-age=31536000"),
+            ),
+            (X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+        ]),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("http://{addr}/users/register"))
+        .json(&json!({ "username": "Sam I Am", "password": "foobar" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[X_CONTENT_TYPE_OPTIONS], "nosniff");
+
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .json(&json!({ "username": "Sam I Am", "password": "foobar" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let headers = response.headers();
+    assert_eq!(headers[CACHE_CONTROL], "no-store");
+    assert_eq!(headers[PRAGMA], "no-cache");
+    assert_eq!(headers[STRICT_TRANSPORT_SECURITY], "max-age=31536000");
+    assert_eq!(headers[X_CONTENT_TYPE_OPTIONS], "nosniff");
+}
+
+#[tokio::test]
+async fn me_route_describes_user_with_roles() {
+    let db = TestDB::default()
+        .with_roles("admin", &["admin", "editor"])
+        .with_claims("writer", json!({ "roles": ["editor"] }));
+
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        me_route_enabled: true,
+        username_claim: true,
+        ..test_config_with_db(db)
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    for (username, expected_roles) in [
+        ("admin", json!(["admin", "editor"])),
+        ("writer", json!(["editor"])),
+        ("reader", json!([])),
+    ] {
+        register(&client, addr, username, "foobar").await;
+        let token = login(&client, addr, username, "foobar").await;
+
+        let response = client
+            .get(format!("http://{addr}/users/me"))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let me = response.json::<serde_json::Value>().await.unwrap();
+        assert_eq!(me["user_id"], decode_claims(&token)["sub"]);
+        assert_eq!(me["username"], username);
+        assert_eq!(
+            me["roles"], expected_roles,
+            "unexpected roles for {username}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn filters_bundle_shares_one_auth_across_routes() {
+    let auth = Auth::new(test_config());
+    let filters = AuthFilters::new(&auth);
+
+    // as if built in separate modules, each with its own clone of the bundle
+    let (profile_filters, greeting_filters) = (filters.clone(), filters.clone());
+    let profile = warp::path!("profile")
+        .and(profile_filters.with_auth())
+        .map(|user_id: UserID| user_id.0);
+    let greeting = warp::path!("greeting")
+        .and(greeting_filters.with_optional_auth())
+        .map(|user_id: Option<UserID>| user_id.map_or("stranger".to_owned(), |user_id| user_id.0));
+
+    let routes = profile
+        .or(greeting)
+        .or(filters.api_routes())
+        .recover(handle_auth_errors);
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+    let user_id = decode_claims(&token)["sub"].as_str().unwrap().to_owned();
+
+    let get = |path: &str, token: Option<&str>| {
+        let request = client.get(format!("http://{addr}/{path}"));
+        match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+        .send()
+    };
+
+    let response = get("profile", Some(&token)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), user_id);
+
+    let response = get("profile", None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = get("greeting", Some(&token)).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), user_id);
+
+    let response = get("greeting", None).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "stranger");
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use auth_for_warp::{
+    build_api_route_filter, handle_auth_errors, with_auth, Auth, AuthConfig, SecretProvider,
+};
+use common::{login, register, serve, test_config};
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use reqwest::StatusCode;
+use serde_json::Value;
+use warp::{path, Filter};
+
+// Stands in for a secret manager, with secrets that can be swapped at runtime
+#[derive(Default)]
+struct RotatingSecrets {
+    // the signing secret, followed by any other accepted secrets
+    secrets: Mutex<Vec<String>>,
+}
+
+impl RotatingSecrets {
+    fn set(&self, secrets: &[&str]) {
+        *self.secrets.lock().unwrap() = secrets.iter().map(|secret| secret.to_string()).collect();
+    }
+}
+
+impl SecretProvider for RotatingSecrets {
+    fn signing_secret(&self) -> String {
+        self.secrets.lock().unwrap()[0].clone()
+    }
+
+    fn verification_secrets(&self) -> Vec<String> {
+        self.secrets.lock().unwrap().clone()
+    }
+}
+
+fn signed_with(token: &str, secret: &str) -> bool {
+    let mut validation = Validation::default();
+    validation.validate_exp = false;
+    validation.required_spec_claims.clear();
+
+    decode::<Value>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &validation,
+    )
+    .is_ok()
+}
+
+#[tokio::test]
+async fn rotated_secret_signs_new_tokens_while_old_ones_verify() {
+    let secrets = Arc::new(RotatingSecrets::default());
+    secrets.set(&["the first secret"]);
+
+    let auth = Auth::new(AuthConfig {
+        secret_provider: Some(secrets.clone()),
+        ..test_config()
+    });
+
+    let secure_page = path!("secure").and(with_auth(&auth)).map(|_| "hello, user");
+
+    let routes = secure_page
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    let status_with_token = |token: String| {
+        let client = client.clone();
+        async move {
+            client
+                .get(format!("http://{addr}/secure"))
+                .bearer_auth(token)
+                .send()
+                .await
+                .unwrap()
+                .status()
+        }
+    };
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let old_token = login(&client, addr, "Sam I Am", "foobar").await;
+    assert!(signed_with(&old_token, "the first secret"));
+
+    // rotate, still accepting the first secret during the overlap
+    secrets.set(&["the second secret", "the first secret"]);
+
+    let new_token = login(&client, addr, "Sam I Am", "foobar").await;
+    assert!(signed_with(&new_token, "the second secret"));
+
+    assert_eq!(status_with_token(old_token.clone()).await, StatusCode::OK);
+    assert_eq!(status_with_token(new_token.clone()).await, StatusCode::OK);
+
+    // once the overlap ends, only the new secret is accepted
+    secrets.set(&["the second secret"]);
+
+    assert_eq!(status_with_token(old_token).await, StatusCode::FORBIDDEN);
+    assert_eq!(status_with_token(new_token).await, StatusCode::OK);
+}
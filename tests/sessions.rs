@@ -0,0 +1,334 @@
+mod common;
+
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use auth_for_warp::{
+    build_api_route_filter, handle_auth_errors, with_auth, Auth, AuthConfig, AuthError,
+    LockoutPolicy, MemoryTokenStore, SessionLimitPolicy, UserID, DEVICE_ID_HEADER,
+};
+use common::{
+    change_password, decode_claims, login, register, serve, serve_auth_routes, sign_claims,
+    test_config, unix_time, LoginResponse, TEST_ISSUER,
+};
+use reqwest::StatusCode;
+use serde_json::json;
+use warp::{path, Filter};
+
+async fn serve_with_session_limit(policy: SessionLimitPolicy) -> SocketAddr {
+    let auth = Auth::new(AuthConfig {
+        max_sessions: Some(2),
+        session_limit_policy: policy,
+        ..test_config()
+    });
+
+    let hello = path!("hello").and(with_auth(&auth)).map(|_| "hello");
+
+    let routes = hello
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    serve(routes).await.unwrap()
+}
+
+async fn status_with_token(client: &reqwest::Client, addr: SocketAddr, token: &str) -> StatusCode {
+    client
+        .get(format!("http://{addr}/hello"))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn login_beyond_session_limit_evicts_oldest_session() {
+    let addr = serve_with_session_limit(SessionLimitPolicy::EvictOldest).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    let mut tokens = vec![];
+    for _ in 0..3 {
+        tokens.push(login(&client, addr, "Sam I Am", "foobar").await);
+    }
+
+    for (token, expected_status) in
+        tokens
+            .iter()
+            .zip([StatusCode::FORBIDDEN, StatusCode::OK, StatusCode::OK])
+    {
+        assert_eq!(
+            status_with_token(&client, addr, token).await,
+            expected_status
+        );
+    }
+}
+
+#[tokio::test]
+async fn login_beyond_session_limit_rejected() {
+    let addr = serve_with_session_limit(SessionLimitPolicy::RejectNew).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    let first = login(&client, addr, "Sam I Am", "foobar").await;
+    login(&client, addr, "Sam I Am", "foobar").await;
+
+    let login = || {
+        client
+            .post(format!("http://{addr}/users/login"))
+            .json(&json!({"username": "Sam I Am", "password": "foobar"}))
+            .send()
+    };
+
+    assert_eq!(login().await.unwrap().status(), StatusCode::CONFLICT);
+
+    // logging out frees up a session
+    client
+        .post(format!("http://{addr}/users/logout"))
+        .bearer_auth(&first)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(login().await.unwrap().status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn expired_entries_are_purged() {
+    let (auth, addr) = serve_auth_routes(AuthConfig {
+        max_sessions: Some(5),
+        refresh_grace_period: Duration::from_secs(10 * 60),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+
+    // refreshing a token moments before the grace period to refresh it ends revokes it, until it ends
+    let expired = sign_claims(&json!({
+        "sub": decode_claims(&token)["sub"],
+        "iss": TEST_ISSUER,
+        "iat": unix_time(-4258),
+        "exp": unix_time(-658),
+        "jti": "expired",
+    }));
+    let response = client
+        .post(format!("http://{addr}/users/refresh"))
+        .bearer_auth(&expired)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let sizes = auth.store_sizes().await;
+    assert_eq!(sizes.revoked_tokens, 1);
+    assert_eq!(sizes.sessions, 2);
+
+    assert_eq!(
+        change_password(&client, addr, "Sam I Am", "foobar", "barfoo").await,
+        StatusCode::OK
+    );
+    assert_eq!(auth.store_sizes().await.not_before, 1);
+
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    auth.purge_expired().await;
+
+    let sizes = auth.store_sizes().await;
+    assert_eq!(
+        sizes.revoked_tokens, 0,
+        "the expired token should be forgotten"
+    );
+    assert_eq!(
+        sizes.sessions, 1,
+        "only the session started by the password change should be kept"
+    );
+    assert_eq!(
+        sizes.not_before, 1,
+        "the invalidation should be kept while earlier tokens are unexpired"
+    );
+}
+
+async fn login_status(
+    client: &reqwest::Client,
+    addr: SocketAddr,
+    username: &str,
+    password: &str,
+) -> StatusCode {
+    client
+        .post(format!("http://{addr}/users/login"))
+        .header(DEVICE_ID_HEADER, "phone")
+        .json(&json!({ "username": username, "password": password }))
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn store_capacity_refuses_tokens_but_keeps_revocations() {
+    let (auth, addr) = serve_auth_routes(AuthConfig {
+        max_sessions: Some(10),
+        max_store_entries: Some(2),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+    login(&client, addr, "Sam I Am", "foobar").await;
+    assert_eq!(
+        login_status(&client, addr, "Sam I Am", "foobar").await,
+        StatusCode::SERVICE_UNAVAILABLE,
+        "no session should be evicted to make room for another"
+    );
+    assert_eq!(auth.store_sizes().await.sessions, 2);
+
+    let user_id = UserID(decode_claims(&token)["sub"].as_str().unwrap().into());
+
+    let auth_without_sessions = Auth::new(AuthConfig {
+        max_store_entries: Some(2),
+        ..test_config()
+    });
+    let mut tokens = vec![];
+    for hours in 1..=3 {
+        let deadline = SystemTime::now() + Duration::from_secs(hours * 60 * 60);
+        tokens.push(
+            auth_without_sessions
+                .generate_token_until(&user_id, deadline)
+                .await
+                .unwrap(),
+        );
+    }
+
+    let logout = build_api_route_filter(&auth_without_sessions).recover(handle_auth_errors);
+    let logout_addr = serve(logout).await.unwrap();
+    for token in &tokens {
+        let response = client
+            .post(format!("http://{logout_addr}/users/logout"))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    assert_eq!(auth_without_sessions.store_sizes().await.revoked_tokens, 3);
+    for token in &tokens {
+        assert!(
+            auth_without_sessions.authenticate(token).await.is_err(),
+            "no revocation should be evicted, however full the store"
+        );
+    }
+    assert!(matches!(
+        auth_without_sessions
+            .generate_token_until(&user_id, SystemTime::now() + Duration::from_secs(60))
+            .await,
+        Err(AuthError::StoreFull)
+    ));
+}
+
+#[tokio::test]
+async fn store_capacity_bounds_every_store() {
+    let token_store = Arc::new(MemoryTokenStore::default());
+    let auth = Auth::new(AuthConfig {
+        max_store_entries: Some(2),
+        pseudonymous_subjects: true,
+        last_seen_recorder: Some(Arc::new(|_: &UserID, _: SystemTime| {})),
+        lockout: Some(LockoutPolicy {
+            max_failed_attempts: 5,
+            lockout_duration: Duration::from_secs(60),
+        }),
+        token_store: Some(token_store),
+        ..test_config()
+    });
+
+    let hello = path!("hello").and(with_auth(&auth)).map(|_| "hello");
+    let addr = serve(
+        hello
+            .or(build_api_route_filter(&auth))
+            .recover(handle_auth_errors),
+    )
+    .await
+    .unwrap();
+
+    let client = reqwest::Client::new();
+
+    for username in ["Sam", "Pat", "Lee"] {
+        register(&client, addr, username, "foobar").await;
+    }
+
+    for username in ["Sam", "Pat"] {
+        let response = client
+            .post(format!("http://{addr}/users/login"))
+            .header(DEVICE_ID_HEADER, "phone")
+            .json(&json!({ "username": username, "password": "foobar" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let token = response.json::<LoginResponse>().await.unwrap().token;
+
+        let response = client
+            .get(format!("http://{addr}/hello"))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    for username in ["Sam", "Pat"] {
+        assert_eq!(
+            login_status(&client, addr, username, "wrong").await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    // failed attempts that can't be counted are refused, as are tokens that can't be recorded
+    assert_eq!(
+        login_status(&client, addr, "Lee", "wrong").await,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_eq!(
+        login_status(&client, addr, "Lee", "foobar").await,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    let sizes = auth.store_sizes().await;
+    assert_eq!(sizes.pseudonyms, 2);
+    assert_eq!(sizes.device_bindings, 2);
+    assert_eq!(sizes.last_seen, 2);
+    assert_eq!(sizes.throttle_keys, Some(2));
+    assert_eq!(sizes.stored_tokens, Some(2));
+}
+
+#[tokio::test]
+async fn cleanup_task_ends_with_auth_module() {
+    let auth = Auth::new(AuthConfig {
+        max_sessions: Some(5),
+        ..test_config()
+    });
+
+    let cleanup = auth.spawn_cleanup(Duration::from_millis(20));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!cleanup.is_finished());
+
+    drop(auth);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(
+        cleanup.is_finished(),
+        "the task should end with the auth module"
+    );
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use auth_for_warp::{parse_duration, AuthSettings, SettingsError};
+use common::TestDB;
+
+#[test]
+fn builds_config_from_toml_settings() {
+    std::env::set_var(
+        "SETTINGS_TEST_TOKEN_SECRET",
+        "a secret from the environment",
+    );
+
+    let settings: AuthSettings = toml::from_str(
+        r#"
+        password_salt = "this is a terrible salt"
+        auth_token_issuer = "example.com"
+        auth_token_secret = { env = "SETTINGS_TEST_TOKEN_SECRET" }
+        auth_token_lifetime = "1h"
+        refresh_grace_period = "1h30m"
+        db_operation_timeout = "500ms"
+        registration_enabled = false
+        max_sessions = 5
+        accept_trailing_slash = false
+        "#,
+    )
+    .unwrap();
+
+    assert!(
+        !format!("{settings:?}").contains("terrible"),
+        "secrets should be redacted"
+    );
+
+    let config = settings.into_config(Arc::new(TestDB::default())).unwrap();
+
+    assert_eq!(config.password_salt, "this is a terrible salt");
+    assert_eq!(config.auth_token_issuer, "example.com");
+    assert_eq!(config.auth_token_secret, "a secret from the environment");
+    assert_eq!(config.auth_token_lifetime, Duration::from_secs(60 * 60));
+    assert_eq!(config.refresh_grace_period, Duration::from_secs(90 * 60));
+    assert_eq!(
+        config.db_operation_timeout,
+        Some(Duration::from_millis(500))
+    );
+    assert!(!config.registration_enabled);
+    assert_eq!(config.max_sessions, Some(5));
+    assert!(!config.accept_trailing_slash);
+    // left at the defaults
+    assert_eq!(config.magic_link_lifetime, Duration::from_secs(15 * 60));
+    assert!(!config.accept_form_bodies);
+}
+
+#[test]
+fn missing_secret_environment_variable_is_reported() {
+    let settings: AuthSettings = toml::from_str(
+        r#"
+        password_salt = "this is a terrible salt"
+        auth_token_issuer = "example.com"
+        auth_token_secret = { env = "SETTINGS_TEST_UNSET_SECRET" }
+        auth_token_lifetime = "1h"
+        "#,
+    )
+    .unwrap();
+
+    assert!(matches!(
+        settings.into_config(Arc::new(TestDB::default())),
+        Err(SettingsError::MissingSecret { name }) if name == "SETTINGS_TEST_UNSET_SECRET"
+    ));
+}
+
+#[test]
+fn parses_human_friendly_durations() {
+    for (input, expected) in [
+        ("90s", Duration::from_secs(90)),
+        ("15m", Duration::from_secs(15 * 60)),
+        ("1h30m", Duration::from_secs(90 * 60)),
+        ("7d", Duration::from_secs(7 * 24 * 60 * 60)),
+        ("250ms", Duration::from_millis(250)),
+    ] {
+        assert_eq!(parse_duration(input), Ok(expected), "{input}");
+    }
+
+    for input in ["", "15", "m", "1x", "1.5h", "-1h"] {
+        assert!(parse_duration(input).is_err(), "{input}");
+    }
+
+    assert!(toml::from_str::<AuthSettings>(
+        r#"
+        password_salt = "this is a terrible salt"
+        auth_token_issuer = "example.com"
+        auth_token_secret = "secret"
+        auth_token_lifetime = "an hour"
+        "#,
+    )
+    .is_err());
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use auth_for_warp::{handle_auth_errors, with_auth, Auth, AuthConfig, StaticJwks};
+use common::{serve, test_config};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use warp::{path, Filter};
+
+const ISSUER: &str = "https://identity.example.com/";
+
+// Both test key sets merged into one, as embedded in an edge node's config
+fn key_set_json() -> String {
+    let keys = ["jwks_1.json", "jwks_2.json"]
+        .iter()
+        .flat_map(|file| {
+            let path = format!("{}/tests/keys/{file}", env!("CARGO_MANIFEST_DIR"));
+            let set: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+            set["keys"].as_array().unwrap().clone()
+        })
+        .collect::<Vec<_>>();
+
+    json!({ "keys": keys }).to_string()
+}
+
+fn signed_token(key: &str, kid: &str, sub: &str) -> String {
+    let exp = SystemTime::now() + Duration::from_secs(60 * 60);
+
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(kid.into());
+
+    let pem = std::fs::read(format!("{}/tests/keys/{key}", env!("CARGO_MANIFEST_DIR"))).unwrap();
+
+    encode(
+        &header,
+        &json!({
+            "iss": ISSUER,
+            "sub": sub,
+            "exp": exp.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        }),
+        &EncodingKey::from_rsa_pem(&pem).unwrap(),
+    )
+    .unwrap()
+}
+
+async fn get_secure(addr: SocketAddr, token: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("http://{addr}/secure"))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn verifies_tokens_against_static_key_set_by_kid() {
+    let auth = Auth::new(AuthConfig {
+        auth_token_issuer: ISSUER.into(),
+        static_jwks: Some(Arc::new(StaticJwks::from_json(&key_set_json()).unwrap())),
+        ..test_config()
+    });
+
+    let secure_page = path!("secure")
+        .and(with_auth(&auth))
+        .map(|user_id: auth_for_warp::UserID| user_id.0);
+
+    let addr = serve(secure_page.recover(handle_auth_errors))
+        .await
+        .unwrap();
+
+    for (key, kid) in [("rsa_key_1.pem", "key-1"), ("rsa_key_2.pem", "key-2")] {
+        let response = get_secure(addr, &signed_token(key, kid, kid)).await;
+        assert_eq!(response.status(), StatusCode::OK, "{kid}");
+        assert_eq!(response.text().await.unwrap(), kid);
+    }
+
+    // signed by a key in the set, but claiming to be another
+    assert_eq!(
+        get_secure(addr, &signed_token("rsa_key_1.pem", "key-2", "user"))
+            .await
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+
+    assert_eq!(
+        get_secure(addr, &signed_token("rsa_key_1.pem", "key-3", "user"))
+            .await
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use auth_for_warp::AuthConfig;
+use common::{decode_claims, serve_auth_routes, test_config, LoginResponse};
+use reqwest::StatusCode;
+use serde_json::json;
+
+#[tokio::test]
+async fn same_username_in_two_tenants() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        tenant_scoped_usernames: true,
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    let mut user_ids = vec![];
+
+    for (tenant, password) in [("acme", "foobar"), ("globex", "bazqux")] {
+        let credentials = json!({"username": "Sam I Am", "password": password, "tenant": tenant});
+
+        assert_eq!(
+            client
+                .post(format!("http://{addr}/users/register"))
+                .json(&credentials)
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::OK,
+            "failed to register the username in tenant {tenant}"
+        );
+
+        let response = client
+            .post(format!("http://{addr}/users/login"))
+            .json(&credentials)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let claims = decode_claims(&response.json::<LoginResponse>().await.unwrap().token);
+        assert_eq!(claims["tenant"], tenant);
+        user_ids.push(claims["sub"].clone());
+    }
+
+    assert_ne!(
+        user_ids[0], user_ids[1],
+        "tenants should have distinct users"
+    );
+
+    assert_eq!(
+        client
+            .post(format!("http://{addr}/users/login"))
+            .json(&json!({"username": "Sam I Am", "password": "foobar"}))
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::BAD_REQUEST,
+        "login without a tenant should be refused"
+    );
+}
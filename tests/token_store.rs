@@ -0,0 +1,121 @@
+mod common;
+
+use std::{net::SocketAddr, sync::Arc};
+
+use auth_for_warp::{
+    build_api_route_filter, handle_auth_errors, with_auth, Auth, AuthConfig, MemoryTokenStore,
+    TokenStore, UserID,
+};
+use common::{
+    decode_claims, login, register, serve, sign_claims, test_config, unix_time, LoginResponse,
+    TEST_ISSUER,
+};
+use reqwest::StatusCode;
+use serde_json::json;
+use warp::{path, Filter};
+
+async fn start_server() -> (SocketAddr, Arc<MemoryTokenStore>) {
+    let token_store = Arc::new(MemoryTokenStore::default());
+
+    let auth = Auth::new(AuthConfig {
+        token_store: Some(token_store.clone()),
+        ..test_config()
+    });
+
+    let secure_page = path!("secure")
+        .and(with_auth(&auth))
+        .map(|user_id: UserID| user_id.0);
+
+    let addr = serve(
+        secure_page
+            .or(build_api_route_filter(&auth))
+            .recover(handle_auth_errors),
+    )
+    .await
+    .unwrap();
+
+    (addr, token_store)
+}
+
+async fn secure_status(client: &reqwest::Client, addr: SocketAddr, token: &str) -> StatusCode {
+    client
+        .get(format!("http://{addr}/secure"))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn reference_token_round_trips() {
+    let (addr, token_store) = start_server().await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let reference = login(&client, addr, "Sam I Am", "foobar").await;
+
+    assert!(!reference.contains('.'), "{reference} is not opaque");
+    let stored = token_store.get(&reference).await.unwrap().unwrap();
+    assert!(decode_claims(&stored)["sub"].is_string());
+
+    assert_eq!(
+        secure_status(&client, addr, &reference).await,
+        StatusCode::OK
+    );
+
+    let response = client
+        .post(format!("http://{addr}/users/refresh"))
+        .bearer_auth(&reference)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let refreshed = response.json::<LoginResponse>().await.unwrap().token;
+    assert_ne!(refreshed, reference);
+    assert_eq!(
+        secure_status(&client, addr, &refreshed).await,
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn removing_reference_revokes_token() {
+    let (addr, token_store) = start_server().await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let reference = login(&client, addr, "Sam I Am", "foobar").await;
+    assert_eq!(
+        secure_status(&client, addr, &reference).await,
+        StatusCode::OK
+    );
+
+    token_store.remove(&reference).await.unwrap();
+
+    assert_eq!(
+        secure_status(&client, addr, &reference).await,
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[tokio::test]
+async fn self_contained_tokens_rejected() {
+    let (addr, _) = start_server().await;
+
+    let client = reqwest::Client::new();
+
+    let token = sign_claims(&json!({
+        "iss": TEST_ISSUER,
+        "sub": "some-user-id",
+        "exp": unix_time(60 * 60),
+    }));
+
+    assert_eq!(
+        secure_status(&client, addr, &token).await,
+        StatusCode::FORBIDDEN
+    );
+}
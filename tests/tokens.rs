@@ -0,0 +1,691 @@
+mod common;
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use auth_for_warp::{
+    build_api_route_filter, handle_auth_errors, with_auth, with_principal, Algorithm, Auth,
+    AuthConfig, AuthError, Principal, TokenProfile, UserID,
+};
+use common::{
+    decode_claims, login, register, serve, serve_auth_routes, sign_claims, test_config,
+    test_config_with_db, unix_time, TestDB, TEST_ISSUER,
+};
+use jsonwebtoken::decode_header;
+use reqwest::StatusCode;
+use serde_json::json;
+use warp::{path, Filter};
+
+#[tokio::test]
+async fn exchanged_token_is_narrower_and_shorter_lived() {
+    let (auth, addr) = serve_auth_routes(AuthConfig {
+        default_scopes: vec!["read".into(), "write".into()],
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let parent = login(&client, addr, "Sam I Am", "foobar").await;
+
+    let child = auth
+        .exchange_token(
+            &parent,
+            "reporting-service",
+            &["read"],
+            Duration::from_secs(60),
+        )
+        .await
+        .unwrap();
+
+    let parent_claims = decode_claims(&parent);
+    let child_claims = decode_claims(&child);
+
+    assert_eq!(child_claims["sub"], parent_claims["sub"]);
+    assert_eq!(child_claims["scope"], "read");
+    assert_eq!(child_claims["act"]["sub"], "reporting-service");
+    assert!(
+        child_claims["exp"].as_u64() < parent_claims["exp"].as_u64(),
+        "delegated token should expire before its parent"
+    );
+
+    assert!(
+        matches!(
+            auth.exchange_token(
+                &parent,
+                "reporting-service",
+                &["read", "admin"],
+                Duration::from_secs(60)
+            )
+            .await,
+            Err(AuthError::InsufficientPermissions)
+        ),
+        "delegated token should not be able to widen the parent's scopes"
+    );
+}
+
+#[tokio::test]
+async fn exchanged_token_never_outlives_parent() {
+    let (auth, addr) = serve_auth_routes(AuthConfig {
+        auth_token_lifetime: Duration::from_secs(60),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let parent = login(&client, addr, "Sam I Am", "foobar").await;
+
+    let child = auth
+        .exchange_token(
+            &parent,
+            "reporting-service",
+            &[],
+            Duration::from_secs(60 * 60),
+        )
+        .await
+        .unwrap();
+
+    assert!(decode_claims(&child)["exp"].as_u64() <= decode_claims(&parent)["exp"].as_u64());
+}
+
+#[tokio::test]
+async fn token_expires_at_requested_deadline() {
+    let auth = Auth::new(AuthConfig {
+        max_token_lifetime: Some(Duration::from_secs(24 * 60 * 60)),
+        ..test_config()
+    });
+    let user_id = UserID("some-user-id".into());
+
+    let exp = unix_time(3 * 60 * 60);
+    let deadline = UNIX_EPOCH + Duration::from_secs(exp);
+    let token = auth.generate_token_until(&user_id, deadline).await.unwrap();
+
+    let claims = decode_claims(&token);
+    assert_eq!(claims["exp"].as_u64(), Some(exp));
+    assert_eq!(auth.authenticate(&token).await.unwrap().0, user_id.0);
+
+    let beyond_cap = SystemTime::now() + Duration::from_secs(7 * 24 * 60 * 60);
+    let token = auth
+        .generate_token_until(&user_id, beyond_cap)
+        .await
+        .unwrap();
+    assert!(decode_claims(&token)["exp"].as_u64() <= Some(unix_time(24 * 60 * 60)));
+
+    let past = SystemTime::now() - Duration::from_secs(60);
+    assert!(matches!(
+        auth.generate_token_until(&user_id, past).await,
+        Err(AuthError::ExpiryInPast)
+    ));
+}
+
+#[tokio::test]
+async fn user_id_taken_from_configured_claim() {
+    let db = TestDB::default().with_claims("Sam I Am", json!({"uid": "legacy-42"}));
+
+    let auth = Auth::new(AuthConfig {
+        user_id_claim: "uid".into(),
+        ..test_config_with_db(db)
+    });
+
+    let secure_page = path!("secure")
+        .and(with_auth(&auth))
+        .map(|user_id: UserID| user_id.0);
+
+    let addr = serve(
+        secure_page
+            .or(build_api_route_filter(&auth))
+            .recover(handle_auth_errors),
+    )
+    .await
+    .unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+
+    let response = client
+        .get(format!("http://{addr}/secure"))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), "legacy-42");
+}
+
+#[tokio::test]
+async fn token_missing_user_id_claim_is_rejected() {
+    let auth = Auth::new(AuthConfig {
+        user_id_claim: "uid".into(),
+        ..test_config()
+    });
+
+    let secure_page = path!("secure").and(with_auth(&auth)).map(|_| "hello");
+
+    let addr = serve(
+        secure_page
+            .or(build_api_route_filter(&auth))
+            .recover(handle_auth_errors),
+    )
+    .await
+    .unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+
+    assert_eq!(
+        client
+            .get(format!("http://{addr}/secure"))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[tokio::test]
+async fn token_lifetime_overridden_per_user() {
+    let db = TestDB::default().with_token_lifetime("service account", Duration::from_secs(5 * 60));
+
+    let (_, addr) = serve_auth_routes(test_config_with_db(db)).await;
+
+    let client = reqwest::Client::new();
+
+    for (username, lifetime) in [("service account", 5 * 60), ("Sam I Am", 60 * 60)] {
+        register(&client, addr, username, "foobar").await;
+        let token = login(&client, addr, username, "foobar").await;
+
+        let exp = decode_claims(&token)["exp"].as_i64().unwrap();
+        assert!(
+            (exp - unix_time(lifetime) as i64).abs() <= 5,
+            "unexpected token lifetime for {username}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn token_lifetime_override_clamped_to_max() {
+    let db = TestDB::default().with_token_lifetime(
+        "service account",
+        Duration::from_secs(10 * 365 * 24 * 60 * 60),
+    );
+
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        max_token_lifetime: Some(Duration::from_secs(24 * 60 * 60)),
+        ..test_config_with_db(db)
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "service account", "foobar").await;
+    let token = login(&client, addr, "service account", "foobar").await;
+
+    let exp = decode_claims(&token)["exp"].as_i64().unwrap();
+    assert!(
+        (exp - unix_time(24 * 60 * 60) as i64).abs() <= 5,
+        "token lifetime not clamped to the maximum"
+    );
+}
+
+#[tokio::test]
+async fn token_with_unconfigured_algorithm_rejected() {
+    let auth = Auth::new(AuthConfig {
+        auth_token_algorithms: vec![Algorithm::HS512],
+        ..test_config()
+    });
+
+    let secure_page = path!("secure").and(with_auth(&auth)).map(|_| "hello, user");
+
+    let addr = serve(
+        secure_page
+            .or(build_api_route_filter(&auth))
+            .recover(handle_auth_errors),
+    )
+    .await
+    .unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+    assert_eq!(decode_header(&token).unwrap().alg, Algorithm::HS512);
+
+    let response = client
+        .get(format!("http://{addr}/secure"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // signed with the right secret, but HS256
+    let downgraded = sign_claims(&json!({
+        "iss": TEST_ISSUER,
+        "sub": decode_claims(&token)["sub"],
+        "exp": unix_time(60 * 60),
+    }));
+
+    let response = client
+        .get(format!("http://{addr}/secure"))
+        .bearer_auth(&downgraded)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        StatusCode::FORBIDDEN,
+        "token signed with an algorithm that isn't configured should be rejected"
+    );
+}
+
+#[tokio::test]
+async fn malformed_tokens_rejected_before_decoding() {
+    let auth = Auth::new(test_config());
+
+    let oversized = format!("{0}.{0}.{0}", "a".repeat(16 * 1024));
+
+    for token in [
+        oversized.as_str(),
+        "not a jwt",
+        "a.b",
+        "a.b.c.d",
+        "a+b.c/d.e==",
+    ] {
+        let rejection = warp::test::request()
+            .header("authorization", format!("Bearer {token}"))
+            .filter(&with_auth(&auth))
+            .await
+            .unwrap_err();
+
+        // a decoding error would carry its source
+        assert!(
+            matches!(
+                rejection.find(),
+                Some(AuthError::TokenError { source: None })
+            ),
+            "unexpected rejection {rejection:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn authenticate_header_value_from_any_transport() {
+    let (auth, addr) = serve_auth_routes(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+    let user_id = decode_claims(&token)["sub"].as_str().unwrap().to_owned();
+
+    for header_value in [format!("Bearer {token}"), token.clone()] {
+        assert_eq!(auth.authenticate(&header_value).await.unwrap().0, user_id);
+    }
+
+    assert!(matches!(
+        auth.authenticate("Bearer not-a-token").await,
+        Err(AuthError::TokenError { .. })
+    ));
+}
+
+#[tokio::test]
+async fn sub_prefix_namespaces_user_ids() {
+    let auth = Auth::new(AuthConfig {
+        sub_prefix: Some("app-1:".into()),
+        ..test_config()
+    });
+
+    let secure_page = path!("secure")
+        .and(with_auth(&auth))
+        .map(|user_id: UserID| user_id.0);
+
+    let addr = serve(
+        secure_page
+            .or(build_api_route_filter(&auth))
+            .recover(handle_auth_errors),
+    )
+    .await
+    .unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let token = login(&client, addr, "Sam I Am", "foobar").await;
+    assert!(decode_claims(&token)["sub"]
+        .as_str()
+        .unwrap()
+        .starts_with("app-1:"));
+
+    let token_for = |sub: &str| {
+        sign_claims(&json!({
+            "iss": TEST_ISSUER,
+            "sub": sub,
+            "exp": unix_time(60 * 60),
+        }))
+    };
+
+    let response = client
+        .get(format!("http://{addr}/secure"))
+        .bearer_auth(token_for("app-1:user-42"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), "user-42");
+
+    for sub in ["app-2:user-42", "user-42"] {
+        assert_eq!(
+            client
+                .get(format!("http://{addr}/secure"))
+                .bearer_auth(token_for(sub))
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::FORBIDDEN,
+            "a token for {sub} should be rejected"
+        );
+    }
+}
+
+#[tokio::test]
+async fn username_claim_round_trips() {
+    let auth = Auth::new(AuthConfig {
+        username_claim: true,
+        ..test_config()
+    });
+
+    let whoami = path!("whoami")
+        .and(with_principal(&auth))
+        .map(|principal: Principal| principal.username.unwrap().0);
+
+    let routes = whoami
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors);
+
+    let addr = serve(routes).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "green eggs").await;
+    let token = login(&client, addr, "Sam I Am", "green eggs").await;
+
+    assert_eq!(decode_claims(&token)["preferred_username"], "Sam I Am");
+
+    let response = client
+        .get(format!("http://{addr}/whoami"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), "Sam I Am");
+}
+
+#[tokio::test]
+async fn token_header_carries_configured_kid_and_typ() {
+    let (auth, addr) = serve_auth_routes(AuthConfig {
+        auth_token_key_id: Some("2024-signing-key".into()),
+        auth_token_header_type: "at+jwt".into(),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "green eggs").await;
+    let token = login(&client, addr, "Sam I Am", "green eggs").await;
+
+    let header = jsonwebtoken::decode_header(&token).unwrap();
+    assert_eq!(header.kid.as_deref(), Some("2024-signing-key"));
+    assert_eq!(header.typ.as_deref(), Some("at+jwt"));
+
+    assert!(auth.authenticate(&format!("Bearer {token}")).await.is_ok());
+}
+
+#[tokio::test]
+async fn access_token_profile_carries_required_claims() {
+    let (auth, addr) = serve_auth_routes(AuthConfig {
+        token_profile: TokenProfile::AccessToken {
+            client_id: "mobile-app".into(),
+        },
+        auth_token_audience: Some("https://api.example.com".into()),
+        default_scopes: vec!["read".into()],
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "green eggs").await;
+    let token = login(&client, addr, "Sam I Am", "green eggs").await;
+
+    let header = jsonwebtoken::decode_header(&token).unwrap();
+    assert_eq!(header.typ.as_deref(), Some("at+jwt"));
+
+    let claims = decode_claims(&token);
+    for claim in [
+        "iss",
+        "exp",
+        "sub",
+        "client_id",
+        "iat",
+        "jti",
+        "aud",
+        "scope",
+    ] {
+        assert!(claims.get(claim).is_some(), "missing {claim} claim");
+    }
+    assert_eq!(claims["client_id"], "mobile-app");
+    assert_eq!(claims["aud"], "https://api.example.com");
+    assert_eq!(claims["scope"], "read");
+
+    assert!(auth.authenticate(&format!("Bearer {token}")).await.is_ok());
+}
+
+#[tokio::test]
+async fn pseudonymous_subject_resolves_to_user_id() {
+    let auth = Auth::new(AuthConfig {
+        pseudonymous_subjects: true,
+        ..test_config()
+    });
+
+    let secure_page = path!("secure")
+        .and(with_auth(&auth))
+        .map(|user_id: UserID| user_id.0);
+
+    let addr = serve(
+        secure_page
+            .or(build_api_route_filter(&auth))
+            .recover(handle_auth_errors),
+    )
+    .await
+    .unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+
+    let mut subjects = vec![];
+    let mut user_ids = vec![];
+
+    for _ in 0..2 {
+        let token = login(&client, addr, "Sam I Am", "foobar").await;
+        subjects.push(decode_claims(&token)["sub"].as_str().unwrap().to_owned());
+
+        let response = client
+            .get(format!("http://{addr}/secure"))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        user_ids.push(response.text().await.unwrap());
+    }
+
+    assert_eq!(
+        user_ids[0], user_ids[1],
+        "pseudonyms should resolve to the same user"
+    );
+    assert_ne!(
+        subjects[0], subjects[1],
+        "each token should carry its own pseudonym"
+    );
+    assert!(
+        !subjects.contains(&user_ids[0]),
+        "the subject should not reveal the user id"
+    );
+}
+
+#[tokio::test]
+async fn custom_validator_vetoes_token() {
+    let db = TestDB::default().with_claims("suspended", json!({"org": "acme"}));
+
+    let auth = Auth::new(AuthConfig {
+        token_validator: Some(Arc::new(|claims| match claims["org"].as_str() {
+            Some("acme") => Err("organisation suspended".into()),
+            _ => Ok(()),
+        })),
+        ..test_config_with_db(db)
+    });
+
+    let secure_page = path!("secure").and(with_auth(&auth)).map(|_| "hello, user");
+
+    let addr = serve(
+        secure_page
+            .or(build_api_route_filter(&auth))
+            .recover(handle_auth_errors),
+    )
+    .await
+    .unwrap();
+
+    let client = reqwest::Client::new();
+
+    for (username, expected_status) in [
+        ("Sam I Am", StatusCode::OK),
+        ("suspended", StatusCode::FORBIDDEN),
+    ] {
+        register(&client, addr, username, "foobar").await;
+        let token = login(&client, addr, username, "foobar").await;
+
+        assert_eq!(
+            client
+                .get(format!("http://{addr}/secure"))
+                .bearer_auth(&token)
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            expected_status,
+            "unexpected access for {username}"
+        );
+    }
+
+    let token = login(&client, addr, "suspended", "foobar").await;
+    assert!(matches!(
+        auth.authenticate(&token).await,
+        Err(AuthError::TokenRejected { reason }) if reason == "organisation suspended"
+    ));
+}
+
+#[tokio::test]
+async fn token_issued_in_future_rejected_if_configured() {
+    let token = |issued_in: i64| {
+        sign_claims(&json!({
+            "iss": TEST_ISSUER,
+            "sub": "some-user-id",
+            "iat": unix_time(issued_in),
+            "exp": unix_time(issued_in + 60 * 60),
+        }))
+    };
+
+    let lenient = Auth::new(test_config());
+    let strict = Auth::new(AuthConfig {
+        reject_future_iat: true,
+        ..test_config()
+    });
+
+    let far_future = format!("Bearer {}", token(60 * 60));
+    assert!(lenient.authenticate(&far_future).await.is_ok());
+    assert!(matches!(
+        strict.authenticate(&far_future).await,
+        Err(AuthError::TokenError { .. })
+    ));
+
+    // within the leeway allowed for clock skew
+    let slightly_ahead = format!("Bearer {}", token(30));
+    assert!(strict.authenticate(&slightly_ahead).await.is_ok());
+}
+
+#[tokio::test]
+async fn login_issues_token_for_allowed_audience() {
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        login_audiences: vec!["billing".into(), "search".into()],
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "green eggs").await;
+
+    let login_for = |audience: &str| {
+        client
+            .post(format!("http://{addr}/users/login"))
+            .json(
+                &json!({ "username": "Sam I Am", "password": "green eggs", "audience": audience }),
+            )
+            .send()
+    };
+
+    let response = login_for("billing").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let token = response.json::<serde_json::Value>().await.unwrap()["token"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    assert_eq!(decode_claims(&token)["aud"], "billing");
+
+    let response = client
+        .post(format!("http://{addr}/users/refresh"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let refreshed = response.json::<serde_json::Value>().await.unwrap()["token"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    assert_eq!(
+        decode_claims(&refreshed)["aud"],
+        "billing",
+        "the refreshed token should keep its audience"
+    );
+
+    let response = login_for("payroll").await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        response.json::<serde_json::Value>().await.unwrap()["error"],
+        "tokens can't be issued for the requested audience"
+    );
+
+    let token = login(&client, addr, "Sam I Am", "green eggs").await;
+    assert!(
+        decode_claims(&token).get("aud").is_none(),
+        "tokens are only issued for an audience on request"
+    );
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use auth_for_warp::{build_api_route_filter, handle_auth_errors, with_auth, Auth, UserID};
+use common::{login, register, serve, test_config};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, Layer};
+use warp::{path, Filter};
+
+// Collects every field value recorded on any span, as (name, value)
+#[derive(Clone, Default)]
+struct RecordedFields(Arc<Mutex<Vec<(String, String)>>>);
+
+impl Visit for RecordedFields {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .lock()
+            .unwrap()
+            .push((field.name().into(), format!("{value:?}")));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .push((field.name().into(), value.into()));
+    }
+}
+
+impl<S: Subscriber> Layer<S> for RecordedFields {
+    fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+        attrs.record(&mut self.clone());
+    }
+
+    fn on_record(&self, _id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        values.record(&mut self.clone());
+    }
+}
+
+#[tokio::test]
+async fn user_id_recorded_in_request_span() {
+    let auth = Auth::new(test_config());
+
+    let secure_page = path!("secure")
+        .and(with_auth(&auth))
+        .map(|user_id: UserID| user_id.0);
+
+    let routes = secure_page
+        .or(build_api_route_filter(&auth))
+        .recover(handle_auth_errors)
+        .with(warp::trace(|_| {
+            tracing::info_span!("request", user_id = tracing::field::Empty)
+        }));
+
+    let addr = serve(routes.clone()).await.unwrap();
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "green eggs").await;
+    let token = login(&client, addr, "Sam I Am", "green eggs").await;
+
+    let fields = RecordedFields::default();
+    let _guard = tracing_subscriber::registry()
+        .with(fields.clone())
+        .set_default();
+
+    // served in this task, so that the subscriber applies
+    let response = warp::test::request()
+        .path("/secure")
+        .header("authorization", format!("Bearer {token}"))
+        .reply(&routes)
+        .await;
+    let user_id = String::from_utf8(response.body().to_vec()).unwrap();
+
+    let fields = fields.0.lock().unwrap();
+    assert!(fields.contains(&("user_id".into(), user_id)));
+    assert!(
+        fields.iter().all(|(_, value)| !value.contains(&token)),
+        "the token should never be recorded"
+    );
+}
@@ -0,0 +1,63 @@
+#![cfg(feature = "unicode-normalization")]
+
+mod common;
+
+use std::sync::Arc;
+
+use auth_for_warp::{AuthConfig, LoginIdentifier};
+use common::{serve_auth_routes, test_config, TestDB};
+use reqwest::StatusCode;
+use serde_json::json;
+
+#[tokio::test]
+async fn composed_and_decomposed_usernames_are_the_same_user() {
+    let db = Arc::new(TestDB::default());
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        login_identifier: LoginIdentifier {
+            field: "username".into(),
+            case_insensitive: true,
+        },
+        unicode_normalized_identifiers: true,
+        database_connection: db.clone(),
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    // "José" with a precomposed é
+    let response = client
+        .post(format!("http://{addr}/users/register"))
+        .json(&json!({ "username": "Jos\u{e9}", "password": "foobar" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK, "failed to register user");
+
+    assert!(db.lock().await.storage.contains_key("jos\u{e9}"));
+
+    // "JOSÉ" with an E followed by a combining acute accent
+    let response = client
+        .post(format!("http://{addr}/users/login"))
+        .json(&json!({ "username": "JOSE\u{301}", "password": "foobar" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        StatusCode::OK,
+        "failed to login with a decomposed username"
+    );
+
+    let response = client
+        .post(format!("http://{addr}/users/register"))
+        .json(&json!({ "username": "jose\u{301}", "password": "foobar" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        StatusCode::CONFLICT,
+        "registered a second user with the same name"
+    );
+}
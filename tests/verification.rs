@@ -0,0 +1,259 @@
+mod common;
+
+use std::{
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
+
+use auth_for_warp::{AuthConfig, ResendLimit, Username};
+use common::{login, register, serve_auth_routes, test_config, test_config_with_db, TestDB};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+type Outbox = Arc<Mutex<Vec<(String, String)>>>;
+
+async fn start_server(config: AuthConfig) -> (SocketAddr, Outbox) {
+    let outbox = Outbox::default();
+
+    let sent = outbox.clone();
+    let (_, addr) = serve_auth_routes(AuthConfig {
+        verification_sender: Some(Arc::new(move |username: &Username, token: &str| {
+            sent.lock()
+                .unwrap()
+                .push((username.0.clone(), token.to_owned()));
+        })),
+        ..config
+    })
+    .await;
+
+    (addr, outbox)
+}
+
+async fn resend(
+    client: &reqwest::Client,
+    addr: SocketAddr,
+    username: &str,
+) -> (StatusCode, String) {
+    let response = client
+        .post(format!("http://{addr}/users/verify/resend"))
+        .json(&json!({ "username": username }))
+        .send()
+        .await
+        .unwrap();
+
+    (response.status(), response.text().await.unwrap())
+}
+
+async fn verify(client: &reqwest::Client, addr: SocketAddr, token: &str) -> StatusCode {
+    client
+        .post(format!("http://{addr}/users/verify"))
+        .json(&json!({ "token": token }))
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn verify_with_resent_token() {
+    let (addr, outbox) = start_server(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    assert_eq!(
+        outbox.lock().unwrap().len(),
+        1,
+        "a token should be sent at registration"
+    );
+
+    let (status, _) = resend(&client, addr, "Sam I Am").await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let (username, token) = outbox.lock().unwrap().pop().unwrap();
+    assert_eq!(username, "Sam I Am");
+
+    assert_eq!(verify(&client, addr, &token).await, StatusCode::OK);
+    assert_eq!(
+        verify(&client, addr, &token).await,
+        StatusCode::FORBIDDEN,
+        "verification token was accepted twice"
+    );
+}
+
+#[tokio::test]
+async fn resend_does_not_reveal_usernames() {
+    let (addr, outbox) = start_server(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    outbox.lock().unwrap().clear();
+
+    let known = resend(&client, addr, "Sam I Am").await;
+    let unknown = resend(&client, addr, "Green Eggs").await;
+
+    assert_eq!(known.0, StatusCode::ACCEPTED);
+    assert_eq!(known, unknown);
+
+    let outbox = outbox.lock().unwrap();
+    assert_eq!(
+        outbox.len(),
+        1,
+        "only the known user should be sent a token"
+    );
+    assert_eq!(outbox[0].0, "Sam I Am");
+}
+
+#[tokio::test]
+async fn resend_to_verified_user_sends_nothing() {
+    let (addr, outbox) = start_server(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let (_, token) = outbox.lock().unwrap().pop().unwrap();
+    assert_eq!(verify(&client, addr, &token).await, StatusCode::OK);
+
+    let verified = resend(&client, addr, "Sam I Am").await;
+    let unknown = resend(&client, addr, "Green Eggs").await;
+
+    assert_eq!(verified.0, StatusCode::ACCEPTED);
+    assert_eq!(verified, unknown);
+    assert!(
+        outbox.lock().unwrap().is_empty(),
+        "a verified user was sent another token"
+    );
+}
+
+#[tokio::test]
+async fn resends_are_rate_limited() {
+    let (addr, outbox) = start_server(AuthConfig {
+        verification_resend_limit: ResendLimit {
+            max_resends: 2,
+            window: Duration::from_secs(60),
+        },
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    outbox.lock().unwrap().clear();
+
+    for _ in 0..2 {
+        let (status, _) = resend(&client, addr, "Sam I Am").await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+    }
+
+    let (status, _) = resend(&client, addr, "Sam I Am").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(outbox.lock().unwrap().len(), 2);
+
+    // unknown identifiers are limited the same way
+    for _ in 0..2 {
+        resend(&client, addr, "Green Eggs").await;
+    }
+    let (status, _) = resend(&client, addr, "Green Eggs").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn login_blocked_until_verified_when_required() {
+    let (addr, outbox) = start_server(AuthConfig {
+        require_verification: true,
+        ..test_config()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("http://{addr}/users/register"))
+        .json(&json!({ "username": "Sam I Am", "password": "foobar" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert_eq!(
+        response.json::<Value>().await.unwrap()["verification_pending"],
+        true
+    );
+
+    let login = |password: &'static str| {
+        client
+            .post(format!("http://{addr}/users/login"))
+            .json(&json!({ "username": "Sam I Am", "password": password }))
+            .send()
+    };
+
+    let response = login("foobar").await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        response.json::<Value>().await.unwrap()["error"],
+        "the email address must be verified first"
+    );
+
+    let (_, token) = outbox.lock().unwrap().pop().unwrap();
+    assert_eq!(verify(&client, addr, &token).await, StatusCode::OK);
+
+    let response = login("foobar").await.unwrap();
+    assert_eq!(
+        response.status(),
+        StatusCode::OK,
+        "failed to login once verified"
+    );
+}
+
+#[tokio::test]
+async fn registration_completes_when_verification_optional() {
+    let (addr, _) = start_server(test_config()).await;
+
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("http://{addr}/users/register"))
+        .json(&json!({ "username": "Sam I Am", "password": "foobar" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.json::<Value>().await.unwrap()["verification_pending"],
+        Value::Null
+    );
+
+    login(&client, addr, "Sam I Am", "foobar").await;
+}
+
+#[tokio::test]
+async fn failed_verification_leaves_token_usable() {
+    let db = TestDB::default();
+    let failing_writes = db.failing_writes.clone();
+    let (addr, outbox) = start_server(test_config_with_db(db)).await;
+
+    let client = reqwest::Client::new();
+
+    register(&client, addr, "Sam I Am", "foobar").await;
+    let (_, token) = outbox.lock().unwrap().pop().unwrap();
+
+    failing_writes.store(true, Ordering::SeqCst);
+    assert_eq!(
+        verify(&client, addr, &token).await,
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+    failing_writes.store(false, Ordering::SeqCst);
+
+    assert_eq!(
+        verify(&client, addr, &token).await,
+        StatusCode::OK,
+        "the token should still be usable after the failed attempt"
+    );
+    assert_eq!(
+        verify(&client, addr, &token).await,
+        StatusCode::FORBIDDEN,
+        "a verification token may only be used once"
+    );
+}